@@ -0,0 +1,57 @@
+//! How fast instructions get decoded and executed. The loop sticks to cheap register operations,
+//! plus a prefixed one, so the time goes on dispatch rather than on memory.
+//!
+//! To compare looking handlers up in a table against matching each opcode with `#[bitmatch]` as
+//! it's executed, run both of these:
+//!
+//!     cargo bench --bench dispatch
+//!     cargo bench --bench dispatch --features bitmatch-dispatch
+
+#![feature(test)]
+extern crate test;
+
+use hardware::classic::{cpu::Cpu, memory::FlatMemory};
+use test::Bencher;
+
+/// Instructions run per iteration
+const INSTRUCTIONS: usize = 10_000;
+
+const TIGHT_LOOP: &[u8] = &[
+    // loop:
+    0x3C,           // inc A
+    0x80,           // add B
+    0x4F,           // ld C, A
+    0xA9,           // xor C
+    0x05,           // dec B
+    0xCB, 0x37,     // swap A
+    0x18, 0xF8,     // jr loop
+];
+
+#[cfg(not(feature = "bitmatch-dispatch"))]
+#[bench]
+fn tight_loop_table_dispatch(b: &mut Bencher) {
+    tight_loop(b);
+}
+
+#[cfg(feature = "bitmatch-dispatch")]
+#[bench]
+fn tight_loop_bitmatch_dispatch(b: &mut Bencher) {
+    tight_loop(b);
+}
+
+fn tight_loop(b: &mut Bencher) {
+    let mut memory = FlatMemory::from_program(TIGHT_LOOP);
+    let mut cpu = Cpu::init();
+
+    b.iter(|| {
+        let mut executed = 0;
+        while executed < INSTRUCTIONS {
+            if let Ok(cycles) = cpu.step(&mut memory) {
+                if cycles > 0 {
+                    executed += 1;
+                }
+            }
+        }
+        test::black_box(cpu.registers().a.0)
+    });
+}
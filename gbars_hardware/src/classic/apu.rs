@@ -0,0 +1,240 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use std::io::{self, Write, Seek, SeekFrom};
+
+use super::console::{NR50, NR51, NR52};
+use super::memory::MemoryBus;
+use super::utils::CLOCK_SPEED;
+
+/// The first register (NRx1) of each of the square wave channels. Each has four: duty cycle and
+/// length, volume envelope, and then the frequency split across the last two.
+pub const NR11: usize = 0xFF11;
+pub const NR21: usize = 0xFF16;
+
+/// Which of the 8 steps of a square wave are high, for each of the 4 duty cycles (12.5%, 25%, 50%
+/// and 75%)
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// The sample rate most audio devices ask for
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// How loud each channel can get in a sample. There are 4 channels at up to volume 15 and the
+/// master volume goes up to 8, so this keeps the mix inside an i16.
+const SAMPLE_SCALE: i32 = 64;
+
+/// The registers of one square wave channel
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct SquareRegisters {
+    pub duty: u8,
+    pub envelope: u8,
+    pub frequency_lo: u8,
+    pub frequency_hi: u8,
+}
+
+impl SquareRegisters {
+    fn read_from<M: MemoryBus>(bus: &M, nrx1: usize) -> Self {
+        let read = |offset| bus.read(offset).unwrap_or(0);
+
+        Self {
+            duty: read(nrx1),
+            envelope: read(nrx1 + 1),
+            frequency_lo: read(nrx1 + 2),
+            frequency_hi: read(nrx1 + 3),
+        }
+    }
+
+    /// The channel's volume is the top nibble of the envelope register. A DAC that's switched off
+    /// (the top 5 bits all clear) doesn't output anything.
+    fn volume(&self) -> i32 {
+        if self.envelope & 0xF8 == 0 {
+            0
+        } else {
+            (self.envelope >> 4) as i32
+        }
+    }
+
+    /// How many clock cycles each of the wave's 8 steps lasts
+    fn step_cycles(&self) -> u64 {
+        let frequency = ((self.frequency_hi as u64 & 0x07) << 8) | self.frequency_lo as u64;
+        (2048 - frequency) * 4
+    }
+}
+
+/// The sound registers that go into working out what comes out of the speakers
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ApuRegisters {
+    pub square: [SquareRegisters; 2],
+    pub nr50: u8,
+    pub nr51: u8,
+    pub nr52: u8,
+}
+
+impl ApuRegisters {
+    /// Reads the sound registers through a memory bus, the same way the CPU sees them
+    pub fn read_from<M: MemoryBus>(bus: &M) -> Self {
+        let read = |offset| bus.read(offset).unwrap_or(0);
+
+        Self {
+            square: [SquareRegisters::read_from(bus, NR11), SquareRegisters::read_from(bus, NR21)],
+            nr50: read(NR50),
+            nr51: read(NR51),
+            nr52: read(NR52),
+        }
+    }
+}
+
+/// The Audio Processing Unit, turning the sound registers into samples. So far only the two square
+/// wave channels make any sound, and they play at their starting volume for as long as their DAC
+/// is on (no sweep, envelope, or length counter yet).
+pub struct Apu {
+    sample_rate: u32,
+    // Clock cycles times the sample rate, so a sample is due every time this passes CLOCK_SPEED
+    sample_cycles: u64,
+    // How far into its wave each square channel is, in clock cycles
+    channel_cycles: [u64; 2],
+    // Left and right samples, one after the other, waiting to be taken
+    samples: Vec<i16>,
+}
+
+impl Apu {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            sample_cycles: 0,
+            channel_cycles: [0; 2],
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 { self.sample_rate }
+
+    /// Changes how many samples are made per second, to match whatever the host's audio device
+    /// wants. Any part of a sample that was on its way is dropped.
+    pub fn set_sample_rate(&mut self, hz: u32) {
+        self.sample_rate = hz;
+        self.sample_cycles = 0;
+    }
+
+    /// Moves the APU along by some number of clock cycles, adding a stereo sample for every one
+    /// that's due in that time
+    pub fn tick(&mut self, cycles: u64, registers: &ApuRegisters) {
+        for (channel, square) in self.channel_cycles.iter_mut().zip(registers.square.iter()) {
+            *channel = (*channel + cycles) % (square.step_cycles() * 8);
+        }
+
+        self.sample_cycles += cycles * self.sample_rate as u64;
+        while self.sample_cycles >= CLOCK_SPEED as u64 {
+            self.sample_cycles -= CLOCK_SPEED as u64;
+
+            let (left, right) = self.mix(registers);
+            self.samples.push(left);
+            self.samples.push(right);
+        }
+    }
+
+    /// Ticks the APU and takes the samples that came out of it in one go. The leftover bit of a
+    /// sample is carried over to the next batch, so batches of any size add up without drifting.
+    pub fn sample_batch(&mut self, cycles: u64, registers: &ApuRegisters) -> Vec<i16> {
+        self.tick(cycles, registers);
+        self.take_samples()
+    }
+
+    /// Takes all the samples made since last time, left and right interleaved
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        core::mem::take(&mut self.samples)
+    }
+
+    /// Adds up the channels going to each side, as picked by NR51 (bits 4-7 for the left, 0-3 for
+    /// the right), and scales them by the master volume for that side in NR50
+    fn mix(&self, registers: &ApuRegisters) -> (i16, i16) {
+        if registers.nr52 & 0x80 == 0 {
+            return (0, 0);
+        }
+
+        let outputs = self.channel_cycles.iter().zip(registers.square.iter()).map(|(&cycles, square)| {
+            let step = (cycles / square.step_cycles()) % 8;
+            let high = DUTY_PATTERNS[(square.duty >> 6) as usize] & (0x80 >> step) != 0;
+
+            if high { square.volume() } else { -square.volume() }
+        });
+
+        let (mut left, mut right) = (0, 0);
+        for (channel, output) in outputs.enumerate() {
+            if registers.nr51 & (0x10 << channel) != 0 {
+                left += output;
+            }
+            if registers.nr51 & (0x01 << channel) != 0 {
+                right += output;
+            }
+        }
+
+        let left_volume = ((registers.nr50 >> 4) & 0x07) as i32 + 1;
+        let right_volume = (registers.nr50 & 0x07) as i32 + 1;
+
+        ((left * left_volume * SAMPLE_SCALE) as i16, (right * right_volume * SAMPLE_SCALE) as i16)
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self { Self::new(DEFAULT_SAMPLE_RATE) }
+}
+
+/// Writes 16-bit stereo samples out as a WAV file. The header goes in first with the sizes left
+/// blank, and they get filled in by `finish` once we know how many samples there were.
+#[cfg(feature = "std")]
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_size: u32,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write + Seek> WavWriter<W> {
+    const CHANNELS: u16 = 2;
+    const BITS_PER_SAMPLE: u16 = 16;
+    const HEADER_SIZE: u32 = 44;
+
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        let block_align = Self::CHANNELS * Self::BITS_PER_SAMPLE / 8;
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&Self::CHANNELS.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&Self::BITS_PER_SAMPLE.to_le_bytes())?;
+
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self { out, data_size: 0 })
+    }
+
+    /// Writes left and right samples, interleaved
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        for sample in samples {
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size += samples.len() as u32 * 2;
+
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header and hands back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(Self::HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_size.to_le_bytes())?;
+        self.out.flush()?;
+
+        Ok(self.out)
+    }
+}
@@ -3,8 +3,53 @@ use std::fs::File;
 use std::error::Error;
 use std::io::{BufReader, Read, Write};
 use core::fmt;
+use core::ops::Range;
 
 use super::memory::*;
+use super::error::GbError;
+use super::header::{self, LOGO_START, LOGO_END, TITLE_START, TITLE_END, CART_TYPE, DEST_CODE, HEADER_CHECKSUM, GLOBAL_CHECKSUM, HEADER_END};
+
+/// The biggest ROMs ever made for the GameBoy are 8 MiB. Anything bigger than that is assumed to be
+/// garbage (or worse), and isn't loaded unless you ask for a bigger limit.
+pub const MAX_ROM_SIZE: usize = 8 * 1024 * 1024;
+
+// These bytes define a bitmap that makes the Nintendo logo that appears when the GameBoy is
+// turned on. If you're wondering how to read this as a graphic, it's just a binary-encoded
+// bitmap, where 1's are black pixels and 0's are white. You read it like:
+//
+// 0  2  4  6  8  10 12 14 16 18 20 22
+// 1  3  5  7  9  11 13 15 17 19 21 23
+// 24 26 28 30 32 34 36 38 40 42 44 46
+// 25 27 29 31 33 35 37 39 41 43 45 47
+//
+// (In hex)
+// C 6 C 0 0 0 0 0 0 1 8 0
+// E 6 C 0 3 0 0 0 0 1 8 0
+// E 6 0 0 7 8 0 0 0 1 8 0
+// D 6 D B 3 3 C D 8 F 9 E
+// D 6 D D B 6 6 E D 9 B 3
+// C E D 9 B 7 E C D 9 B 3
+// C E D 9 B 6 0 C D 9 B 3
+// C 6 D 9 B 3 E C C F 9 E
+//
+// (In binary, with 0's removed)
+// 11   11 11                             11
+// 111  11 11        11                   11
+// 111  11          1111                  11
+// 11 1 11 11 11 11  11  1111  11 11   11111  1111
+// 11 1 11 11 111 11 11 11  11 111 11 11  11 11  11
+// 11  111 11 11  11 11 111111 11  11 11  11 11  11
+// 11  111 11 11  11 11 11     11  11 11  11 11  11
+// 11   11 11 11  11 11  11111 11  11  11111  1111
+/// The Nintendo logo, which every cartridge has to have at 0x0104 to boot
+pub const NINTENDO_GRAPHIC: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B,
+    0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC,
+    0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
 
 /// Represents a physical GB cartridge and its associated metadata
 pub struct Cartridge {
@@ -17,14 +62,43 @@ pub struct Cartridge {
     pub rom_banks: usize,
     pub ram_size: usize,
     pub ram_banks: usize,
-    pub locale: String,
+    pub destination: Destination,
     pub header_checksum: u8,
     pub global_checksum: u16,
 }
 
 impl fmt::Debug for Cartridge {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Cartridge ( {}, ROM size: {}, {:?}, {} )", self.title, self.rom_size, self.features, self.locale)
+        write!(f, "Cartridge ( {}, ROM size: {}, {:?}, {} )", self.title, self.rom_size, self.features, self.destination)
+    }
+}
+
+/// Where a cartridge was meant to be sold, going by the destination code in its header. There are
+/// only two: Japan, and everywhere else.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+    Unknown,
+}
+
+impl Destination {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x00 => Destination::Japan,
+            0x01 => Destination::Overseas,
+            _ => Destination::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Destination::Japan => write!(f, "Japanese"),
+            Destination::Overseas => write!(f, "Non-Japanese"),
+            Destination::Unknown => write!(f, "Unknown"),
+        }
     }
 }
 
@@ -46,6 +120,23 @@ pub enum CartridgeFeature {
 }
 
 impl Cartridge {
+    /// A cartridge with no header and no MBC, just `program` in ROM. This saves tests from having
+    /// to build a whole ROM image to run a few instructions.
+    pub fn new_blank(program: Vec<u8>) -> Self {
+        Self {
+            title: String::new(),
+            rom_size: program.len(),
+            mbc: MBC::RomOnly(ROM::new(program)),
+            features: vec![CartridgeFeature::ROM],
+            rom_banks: 2,
+            ram_size: 0,
+            ram_banks: 0,
+            destination: Destination::Unknown,
+            header_checksum: 0,
+            global_checksum: 0,
+        }
+    }
+
     /// Loads up a ROM from a file and returns a new Cartridge object on success, or an error
     pub fn load(path_to_rom: &str) -> Result<Self, String> {
         match File::open(path_to_rom)  {
@@ -53,162 +144,203 @@ impl Cartridge {
                 // Read the contents of the ROM
                 let mut contents = vec![];
                 {
-                    let mut reader = BufReader::new(f);
+                    // There's no sense reading any more than one byte past the limit
+                    let mut reader = BufReader::new(f).take(MAX_ROM_SIZE as u64 + 1);
                     if let Err(e) = reader.read_to_end(&mut contents) {
                         return Err(format!("Error reading data from {}: {}", path_to_rom, e.to_string()));
                     }
                 }
 
-                // Get the title
-                let title = {
-                    let mut t = String::new();
-                    for i in 0x134..0x143usize {
-                        if let Some(ch) = contents.get(i) {
-                            if *ch == 0x00 { continue; }
-                            t.push(*ch as char);
-                        }
-                    }
-                    t
-                };
-
-                // Specify the list of features
-                let features = {
-                    use self::CartridgeFeature::*;
-                    if let Some(n) = contents.get(0x147) {
-                        match *n {
-                            0x00 => vec![ROM],
-                            0x01 => vec![MBC1],
-                            0x02 => vec![MBC1, RAM],
-                            0x03 => vec![MBC1, RAM, Battery],
-                            0x05 => vec![MBC2],
-                            0x06 => vec![MBC2, Battery],
-                            0x08 => vec![ROM, RAM],
-                            0x09 => vec![ROM, RAM, Battery],
-                            0x0B => vec![MMM01],
-                            0x0C => vec![MMM01, RAM],
-                            0x0D => vec![MMM01, RAM, Battery],
-                            0x0F => vec![MBC3, Battery, Timer],
-                            0x10 => vec![MBC3, Battery, Timer, RAM],
-                            0x11 => vec![MBC3],
-                            0x12 => vec![MBC3, RAM],
-                            0x13 => vec![MBC3, RAM, Battery],
-                            0x19 => vec![MBC5],
-                            0x1A => vec![MBC5, RAM],
-                            0x1B => vec![MBC5, RAM, Battery],
-                            0x1C => vec![MBC5, Rumble],
-                            0x1D => vec![MBC5, Rumble, RAM],
-                            0x1E => vec![MBC5, Rumble, RAM, Battery],
-                            0x20 => vec![MBC6],
-                            0x22 => vec![MBC7, Sensor, Rumble, RAM, Battery],
-                            0xFC => vec![PocketCamera],
-                            0xFD => vec![BandaiTama5],
-                            0xFE => vec![HuC3],
-                            0xFF => vec![HuC1, RAM, Battery],
-                            _    => vec![Unknown]
-                        }
-                    } else {
-                        vec![Unknown]
-                    }
-                };
-
-                // Get the ROM size and the number of ROM banks
-                let (rom_size, rom_banks) =
-                    if let Some(n) = contents.get(0x148) {
-                        match *n {
-                            0x00 => (0x8_000, 1),
-                            0x01...0x08 => ((0x8_000 << *n) as usize, (2 << *n) as usize),
-                            0x52 => (0x120_000, 72),
-                            0x53 => (0x140_000, 80),
-                            0x54 => (0x180_000, 96),
-                            _ => (0, 0)
-                        }
-                    } else {
-                        (0, 0)
-                    };
-
-                // Get the RAM size (if applicable) and the number of RAM banks
-                let (ram_size, ram_banks) =
-                    if let Some(n) = contents.get(0x149) {
-                        match *n {
-                            0x00 => (0, 0),
-                            0x01 => (0x800, 1),
-                            0x02 => (0x2_000, 1),
-                            0x03 => (0x8_000, 4),
-                            0x04 => (0x20_000, 16),
-                            0x05 => (0x10_000, 8),
-                            _ => (0, 0)
-                        }
-                    } else {
-                        (0, 0)
-                    };
-
-                // Get the memory bank controller, which is part of the features
-                // Currently only four are documented, but they cover most cases. MBC6, MBC7,
-                // MMM01, and the HudsonSoft MBCs were not very prevalent
-                let mbc = if features.contains(&CartridgeFeature::MBC1) {
-                    MBC::MBC1(MBC1 {
-                        rom: ROM::new(contents.clone()),
-                        ram: RAM::new(ram_size),
-                        active_rom_bank: 1,
-                        active_ram_bank: 1,
-                        ram_enabled: false,
-                        mode: MbcMode::RomSelect,
-                    })
-                } else {
-                    MBC::RomOnly(ROM::new(contents.clone()))
-                };
-
-                // Two locales: Japanese and Non-Japanese
-                let locale = if let Some(n) = contents.get(0x14A) {
-                    match *n {
-                        0 => "Japanese",
-                        1 => "Non-Japanese",
-                        _ => "Unknown"
-                    }
-                } else {
-                    "Unknown"
-                }.to_string();
-
-                // Get the header checksum, which is one byte long
-                let header_checksum = match contents.get(0x14D) {
-                    Some(n) => *n,
-                    None => 0
-                };
-
-                // Get the global checksum, which is two bytes long
-                let global_checksum = {
-                    let upper_byte = match contents.get(0x14E) {
-                        Some(n) => *n,
-                        None => 0
-                    } as u16;
-
-                    let lower_byte = match contents.get(0x14F) {
-                        Some(n) => *n,
-                        None => 0
-                    } as u16;
-
-                    upper_byte << 8 | lower_byte
-                };
-
-                Ok(
-                    Self {
-                        title,
-                        mbc,
-                        features,
-                        rom_size,
-                        rom_banks,
-                        ram_size,
-                        ram_banks,
-                        locale,
-                        header_checksum,
-                        global_checksum,
-                    }
-                )
+                Self::from_bytes(contents).map_err(|e| format!("Error loading {}: {}", path_to_rom, e))
             },
             Err(e) => Err(format!("Could not open file {}: {}", path_to_rom, e.to_string())),
         }
     }
 
+    /// Writes all of the cartridge's RAM banks out to a save file, so the game's saves outlive the
+    /// emulator. Only cartridges with a battery keep their RAM when the power's off, so for the
+    /// rest this doesn't touch the disk at all.
+    pub fn save_ram(&self, path: &str) -> std::io::Result<()> {
+        match self.mbc.ram() {
+            Some(ram) if self.has_battery() => File::create(path)?.write_all(ram),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fills the cartridge's RAM banks from a save file written by `save_ram`. Other emulators
+    /// tack extra things like the clock onto the end of their save files, so anything past the
+    /// end of the RAM is ignored.
+    pub fn load_ram(&mut self, path: &str) -> std::io::Result<()> {
+        if !self.has_battery() {
+            return Ok(());
+        }
+
+        if let Some(ram) = self.mbc.ram_mut() {
+            let mut save = vec![];
+            File::open(path)?.take(ram.len() as u64).read_to_end(&mut save)?;
+            ram[..save.len()].copy_from_slice(&save);
+        }
+
+        Ok(())
+    }
+
+    fn has_battery(&self) -> bool {
+        self.features.contains(&CartridgeFeature::Battery)
+    }
+
+    /// Makes a Cartridge out of the raw contents of a ROM, parsing the header at 0x0100-0x014F
+    pub fn from_bytes(contents: Vec<u8>) -> Result<Self, GbError> {
+        Self::parse(contents, MAX_ROM_SIZE, |_| {})
+    }
+
+    /// Same as `from_bytes`, but with a different limit on the size of the ROM than
+    /// `MAX_ROM_SIZE`, for homebrew that needs more room
+    pub fn from_bytes_with_max_size(contents: Vec<u8>, max_size: usize) -> Result<Self, GbError> {
+        Self::parse(contents, max_size, |_| {})
+    }
+
+    /// Same as `from_bytes`, but calls `warn` about anything fishy in the header that had to be
+    /// worked around
+    pub fn from_bytes_with_warnings<W: FnMut(&str)>(contents: Vec<u8>, warn: W) -> Result<Self, GbError> {
+        Self::parse(contents, MAX_ROM_SIZE, warn)
+    }
+
+    fn parse<W: FnMut(&str)>(contents: Vec<u8>, max_size: usize, warn: W) -> Result<Self, GbError> {
+        if contents.len() > max_size {
+            return Err(GbError::RomTooLarge(contents.len()));
+        }
+
+        // Get the title
+        let title = {
+            let mut t = String::new();
+            for i in TITLE_START..TITLE_END {
+                if let Some(ch) = contents.get(i) {
+                    if *ch == 0x00 { continue; }
+                    t.push(*ch as char);
+                }
+            }
+            t
+        };
+
+        // Specify the list of features
+        let features = {
+            use self::CartridgeFeature::*;
+            if let Some(n) = contents.get(CART_TYPE) {
+                match *n {
+                    0x00 => vec![ROM],
+                    0x01 => vec![MBC1],
+                    0x02 => vec![MBC1, RAM],
+                    0x03 => vec![MBC1, RAM, Battery],
+                    0x05 => vec![MBC2],
+                    0x06 => vec![MBC2, Battery],
+                    0x08 => vec![ROM, RAM],
+                    0x09 => vec![ROM, RAM, Battery],
+                    0x0B => vec![MMM01],
+                    0x0C => vec![MMM01, RAM],
+                    0x0D => vec![MMM01, RAM, Battery],
+                    0x0F => vec![MBC3, Battery, Timer],
+                    0x10 => vec![MBC3, Battery, Timer, RAM],
+                    0x11 => vec![MBC3],
+                    0x12 => vec![MBC3, RAM],
+                    0x13 => vec![MBC3, RAM, Battery],
+                    0x19 => vec![MBC5],
+                    0x1A => vec![MBC5, RAM],
+                    0x1B => vec![MBC5, RAM, Battery],
+                    0x1C => vec![MBC5, Rumble],
+                    0x1D => vec![MBC5, Rumble, RAM],
+                    0x1E => vec![MBC5, Rumble, RAM, Battery],
+                    0x20 => vec![MBC6],
+                    0x22 => vec![MBC7, Sensor, Rumble, RAM, Battery],
+                    0xFC => vec![PocketCamera],
+                    0xFD => vec![BandaiTama5],
+                    0xFE => vec![HuC3],
+                    0xFF => vec![HuC1, RAM, Battery],
+                    _    => vec![Unknown]
+                }
+            } else {
+                vec![Unknown]
+            }
+        };
+
+        // Get the ROM size and the number of ROM banks
+        let (rom_size, rom_banks) =
+            if let Some(n) = contents.get(header::ROM_SIZE) {
+                match *n {
+                    0x00 => (0x8_000, 1),
+                    0x01...0x08 => ((0x8_000 << *n) as usize, (2 << *n) as usize),
+                    0x52 => (0x120_000, 72),
+                    0x53 => (0x140_000, 80),
+                    0x54 => (0x180_000, 96),
+                    _ => (0, 0)
+                }
+            } else {
+                (0, 0)
+            };
+
+        // Get the RAM size (if applicable) and the number of RAM banks
+        let (mut ram_size, mut ram_banks) =
+            if let Some(n) = contents.get(header::RAM_SIZE) {
+                match *n {
+                    0x00 => (0, 0),
+                    0x01 => (0x800, 1),
+                    0x02 => (0x2_000, 1),
+                    0x03 => (0x8_000, 4),
+                    0x04 => (0x20_000, 16),
+                    0x05 => (0x10_000, 8),
+                    _ => (0, 0)
+                }
+            } else {
+                (0, 0)
+            };
+
+        // Get the memory bank controller, which is part of the features. This might allocate RAM
+        // the header didn't ask for, so the RAM size is updated to match. Carts that get treated
+        // as ROM only (like ROM+RAM and MMM01) don't allocate any, so they keep what the header says.
+        let mbc = MBC::from_cartridge(contents.clone(), ram_size, warn);
+        if !matches!(mbc, MBC::RomOnly(_)) && mbc.ram_size() != ram_size {
+            ram_size = mbc.ram_size();
+            ram_banks = (ram_size / 0x2000).max(1);
+        }
+
+        let destination = contents.get(DEST_CODE).map_or(Destination::Unknown, |&n| Destination::from_code(n));
+
+        // Get the header checksum, which is one byte long
+        let header_checksum = match contents.get(HEADER_CHECKSUM) {
+            Some(n) => *n,
+            None => 0
+        };
+
+        // Get the global checksum, which is two bytes long
+        let global_checksum = {
+            let upper_byte = match contents.get(GLOBAL_CHECKSUM) {
+                Some(n) => *n,
+                None => 0
+            } as u16;
+
+            let lower_byte = match contents.get(GLOBAL_CHECKSUM + 1) {
+                Some(n) => *n,
+                None => 0
+            } as u16;
+
+            upper_byte << 8 | lower_byte
+        };
+
+        Ok(
+            Self {
+                title,
+                mbc,
+                features,
+                rom_size,
+                rom_banks,
+                ram_size,
+                ram_banks,
+                destination,
+                header_checksum,
+                global_checksum,
+            }
+        )
+    }
+
     /// There are two criteria that the GameBoy checks for to validate ROMs: the scrolling
     /// NintendoⓇ graphic and the header checksum.
     ///
@@ -216,48 +348,13 @@ impl Cartridge {
     /// this is. You can basically just stick the header of an officially-licensed GameBoy game onto
     /// whatever you want and the GameBoy should have no problem trying to play it.
     pub fn validate(&self) -> Result<(), String> {
-        // These bytes define a bitmap that makes the Nintendo logo that appears when the GameBoy is
-        // turned on. If you're wondering how to read this as a graphic, it's just a binary-encoded
-        // bitmap, where 1's are black pixels and 0's are white. You read it like:
-        //
-        // 0  2  4  6  8  10 12 14 16 18 20 22
-        // 1  3  5  7  9  11 13 15 17 19 21 23
-        // 24 26 28 30 32 34 36 38 40 42 44 46
-        // 25 27 29 31 33 35 37 39 41 43 45 47
-        //
-        // (In hex)
-        // C 6 C 0 0 0 0 0 0 1 8 0
-        // E 6 C 0 3 0 0 0 0 1 8 0
-        // E 6 0 0 7 8 0 0 0 1 8 0
-        // D 6 D B 3 3 C D 8 F 9 E
-        // D 6 D D B 6 6 E D 9 B 3
-        // C E D 9 B 7 E C D 9 B 3
-        // C E D 9 B 6 0 C D 9 B 3
-        // C 6 D 9 B 3 E C C F 9 E
-        //
-        // (In binary, with 0's removed)
-        // 11   11 11                             11
-        // 111  11 11        11                   11
-        // 111  11          1111                  11
-        // 11 1 11 11 11 11  11  1111  11 11   11111  1111
-        // 11 1 11 11 111 11 11 11  11 111 11 11  11 11  11
-        // 11  111 11 11  11 11 111111 11  11 11  11 11  11
-        // 11  111 11 11  11 11 11     11  11 11  11 11  11
-        // 11   11 11 11  11 11  11111 11  11  11111  1111
-        let nintendo_graphic = [
-            0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B,
-            0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
-            0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
-            0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
-            0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC,
-            0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
-        ];
-
-        // For better debugging, rather than doing a straight slice comparison, we zip the above
-        // array with the corresponding slice of bytes in memory. Then we filter out all the cases
+        let too_short = || "ROM too short for a header".to_string();
+
+        // For better debugging, rather than doing a straight slice comparison, we zip the logo
+        // with the corresponding slice of bytes in memory. Then we filter out all the cases
         // there the bytes match, leaving only the non-matching bytes.
-        let mut non_matching_bytes: Vec<(usize, u8, u8)> = nintendo_graphic.iter().enumerate()
-            .zip(self.mbc.read_rom_slice(0x104, 0x104 + 48).unwrap())
+        let mut non_matching_bytes: Vec<(usize, u8, u8)> = NINTENDO_GRAPHIC.iter().enumerate()
+            .zip(self.mbc.read_rom_slice(LOGO_START, LOGO_END).ok_or_else(too_short)?)
             .filter(|&((_, &a), b)| a != b)
             .map(|((i, &a), b)| (i, a, b))
             .collect();
@@ -266,7 +363,7 @@ impl Cartridge {
         if !non_matching_bytes.is_empty() {
             let mut error = "Error validating Nintendo graphic: The following bytes are incorrect:\n".to_string();
             for (i, expected, actual) in non_matching_bytes {
-                error += &format!("At offset 0x{:04X}: Expected 0x{:02X}; found 0x{:02X}\n", 0x104 + i, expected, actual);
+                error += &format!("At offset 0x{:04X}: Expected 0x{:02X}; found 0x{:02X}\n", LOGO_START + i, expected, actual);
             }
 
             return Err(error);
@@ -274,7 +371,7 @@ impl Cartridge {
 
         // The checksum starts from 0 and the value of one less than each byte from offset 0x0134 to
         // 0x014D is subtracted from it (with wrapping)
-        let checksum = self.mbc.read_rom_slice(0x134, 0x14D).unwrap()
+        let checksum = self.mbc.read_rom_slice(TITLE_START, HEADER_CHECKSUM).ok_or_else(too_short)?
             .iter()
             .fold(0u8, |c, x|
                 // c - x - 1
@@ -283,7 +380,7 @@ impl Cartridge {
         if checksum != self.header_checksum {
             return Err(
                 format!(
-                    "Invalid header checksum: Expected {}; actual sum is {}",
+                    "Invalid header checksum: Expected 0x{:02X}; actual sum of 0x0134-0x014C is 0x{:02X}",
                     self.header_checksum,
                     checksum
                 )
@@ -296,7 +393,74 @@ impl Cartridge {
     /// Returns true if the result of `validate` is `Ok`.
     pub fn is_valid(&self) -> bool { self.validate().is_ok() }
 
+    /// Recomputes both checksums in the header of a ROM and writes them back in, so that a ROM
+    /// that's been edited (by a patch, say) validates again. The header checksum covers
+    /// 0x0134-0x014C, and the global checksum is the sum of every byte in the ROM apart from the
+    /// global checksum itself, stored big-endian. Nothing happens if the ROM is too small to have
+    /// a header.
+    pub fn fix_checksums(contents: &mut [u8]) {
+        if contents.len() < HEADER_END {
+            return;
+        }
+
+        contents[HEADER_CHECKSUM] = contents[TITLE_START..HEADER_CHECKSUM].iter()
+            .fold(0u8, |c, x| c.wrapping_sub(*x).wrapping_sub(1));
+
+        let global_checksum = Self::global_checksum_of(contents);
+
+        contents[GLOBAL_CHECKSUM] = (global_checksum >> 8) as u8;
+        contents[GLOBAL_CHECKSUM + 1] = global_checksum as u8;
+    }
+
+    /// Checks the global checksum in the header against the sum of the whole ROM. The GameBoy never
+    /// checks this itself, so plenty of ROMs that run fine get it wrong, and `validate` doesn't
+    /// look at it. It's here for tools that want to know whether a ROM's been tampered with.
+    pub fn verify_global_checksum(&self) -> bool {
+        Self::global_checksum_of(self.mbc.rom()) == self.global_checksum
+    }
+
+    /// The sum of every byte in the ROM apart from the two holding the global checksum
+    fn global_checksum_of(contents: &[u8]) -> u16 {
+        contents.iter().enumerate()
+            .filter(|&(i, _)| i != GLOBAL_CHECKSUM && i != GLOBAL_CHECKSUM + 1)
+            .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+    }
+
     pub fn read_rom(&self, offset: usize) -> Option<u8> {
         self.mbc.read_rom(offset)
     }
+
+    /// Prints a hex dump of part of the ROM to stdout (see `dump_to`)
+    pub fn dump(&self, as_chars: bool, range: Range<usize>) {
+        // Not much can be done if stdout is gone
+        let _ = self.dump_to(&mut std::io::stdout(), as_chars, range);
+    }
+
+    /// Writes a dump of part of the ROM, 16 bytes to a line with the offset of the first one at
+    /// the start. The bytes are in hex, or if `as_chars` is set, as characters (with a `.` for
+    /// anything that wouldn't show up). Anything past the end of the ROM is left out.
+    pub fn dump_to<W: Write>(&self, w: &mut W, as_chars: bool, range: Range<usize>) -> std::io::Result<()> {
+        let rom = self.mbc.rom();
+        let end = range.end.min(rom.len());
+
+        for i in range.start..end {
+            if i == range.start || i % 16 == 0 {
+                if i != range.start {
+                    writeln!(w)?;
+                }
+                write!(w, "0x{:08X} ", i)?;
+            }
+
+            let b = rom[i];
+            if !as_chars {
+                write!(w, "{:02X} ", b)?;
+            } else if b.is_ascii_graphic() {
+                write!(w, "{} ", b as char)?;
+            } else {
+                write!(w, ". ")?;
+            }
+        }
+
+        writeln!(w)
+    }
 }
\ No newline at end of file
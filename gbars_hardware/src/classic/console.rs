@@ -1,11 +1,27 @@
 #[cfg(all(not(feature = "std"), feature = "alloc"))]
 use alloc::{
-    vec::Vec
+    vec::Vec,
+    boxed::Box,
 };
 
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime};
+
 use super::{
-    cpu::Cpu,
-    cartridge::Cartridge
+    apu::{Apu, ApuRegisters, WavWriter},
+    cpu::{Cpu, CpuState, Interrupt},
+    cartridge::Cartridge,
+    error::GbError,
+    header::CART_TYPE,
+    joypad::{Joypad, Button, ButtonSet, Socd},
+    serial::SerialLink,
+    state,
+    timer::{Timer, DIV, TAC},
+    ppu::{Ppu, Vram, LcdRegisters, Layer, MonoShadeColors, CYCLES_PER_FRAME, draw_sprite_outlines},
+    memory::{MemoryBus, MbcKind, MBC, CAMERA_WIDTH, CAMERA_HEIGHT},
+    undo::{UndoHistory, DEFAULT_UNDO_DEPTH},
 };
 
 pub const ROM_BANK_0_START: usize = 0x0000;
@@ -22,6 +38,73 @@ pub const HARDWARE_IO_START: usize = 0xFF00;
 pub const HIGH_RAM_START: usize = 0xFF80;
 pub const IE_START: usize = 0xFFFF;
 
+pub const JOYPAD: usize = 0xFF00;
+pub const SB: usize = 0xFF01;
+pub const SC: usize = 0xFF02;
+
+/// How many clock cycles a serial transfer takes on the internal clock: 8 bits at 8192 Hz
+pub const SERIAL_TRANSFER_CYCLES: usize = 8 * 512;
+pub const INTERRUPT_FLAG: usize = 0xFF0F;
+pub const NR10: usize = 0xFF10;
+pub const NR50: usize = 0xFF24;
+pub const NR51: usize = 0xFF25;
+pub const NR52: usize = 0xFF26;
+pub const KEY1: usize = 0xFF4D;
+pub const LCDC: usize = 0xFF40;
+pub const STAT: usize = 0xFF41;
+pub const SCY: usize = 0xFF42;
+pub const SCX: usize = 0xFF43;
+pub const LY: usize = 0xFF44;
+pub const LYC: usize = 0xFF45;
+pub const BGP: usize = 0xFF47;
+pub const OBP0: usize = 0xFF48;
+pub const OBP1: usize = 0xFF49;
+
+/// Whether an address in the I/O region has nothing behind it. Reading one of these gets you
+/// whatever is floating on the data bus, which in practice is always 0xFF, and writing to one does
+/// nothing. The CGB puts some of its extra registers in the DMG's gaps.
+pub fn is_unmapped_io(offset: usize, model: Model) -> bool {
+    let cgb_only = matches!(offset, KEY1 | 0xFF4F | 0xFF51 ..= 0xFF55 | 0xFF68 ..= 0xFF6B | 0xFF70);
+    if cgb_only && model == Model::Cgb {
+        return false;
+    }
+
+    matches!(
+        offset,
+        0xFF03 | 0xFF08 ..= 0xFF0E | 0xFF15 | 0xFF1F | 0xFF27 ..= 0xFF2F | 0xFF4C ..= 0xFF4F |
+        0xFF51 ..= 0xFF7F
+    )
+}
+
+/// The CPU's clock speed. The CGB can run at twice the speed of the DMG.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Speed {
+    Normal,
+    Double,
+}
+
+/// KEY1 (0xFF4D), the CGB's speed switch register. A game switches speeds by setting bit 0 to
+/// "prepare" the switch and then executing `stop`. Bit 7 says which speed the CPU is currently
+/// running at, and only the hardware can change it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Key1(pub u8);
+
+impl Key1 {
+    pub fn prepare_switch(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+
+    pub fn current_speed(&self) -> Speed {
+        if self.0 & 0x80 != 0 { Speed::Double } else { Speed::Normal }
+    }
+
+    /// What the register looks like after `stop` carries out a prepared switch: the other speed,
+    /// and the prepare bit cleared
+    pub fn switched(&self) -> Self {
+        Key1((self.0 ^ 0x80) & 0x80)
+    }
+}
+
 pub const CHR_RAM_SIZE: usize = BG_MAP_DATA_1_START - CHR_RAM_START;
 pub const BG_MAP_DATA_SIZE: usize = CARTRIDGE_RAM_START - BG_MAP_DATA_1_START;
 pub const WRAM_SIZE: usize = ECHO_RAM_START - WRAM_START;
@@ -30,7 +113,70 @@ pub const OAM_SIZE: usize = OAM_END - OAM_START;
 pub const HARDWARE_IO_SIZE: usize = HIGH_RAM_START - HARDWARE_IO_START;
 pub const HIGH_RAM_SIZE: usize = IE_START - HIGH_RAM_START;
 
-pub struct Console {
+/// The GameBoy came in a few different models over the years. They all run the same games (more or
+/// less), but they differ in subtle ways, the most obvious being the values the boot ROM leaves
+/// behind in the registers. Games use these to work out which model they're running on.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Model {
+    Dmg, // The original GameBoy
+    Mgb, // GameBoy Pocket
+    Cgb, // GameBoy Color
+}
+
+impl Model {
+    /// The state of the I/O registers once the boot ROM has finished running, as (address, value)
+    fn post_boot_io(self) -> [(usize, u8); 35] {
+        let (div, stat, dma) = match self {
+            Model::Dmg | Model::Mgb => (0xAB, 0x85, 0xFF),
+            Model::Cgb => (0x00, 0x81, 0x00),
+        };
+
+        [
+            (0xFF01, 0x00), // SB
+            (0xFF02, 0x7E), // SC
+            (0xFF04, div),  // DIV
+            (0xFF05, 0x00), // TIMA
+            (0xFF06, 0x00), // TMA
+            (0xFF07, 0xF8), // TAC
+            (0xFF0F, 0xE1), // IF
+            (0xFF10, 0x80), // NR10
+            (0xFF11, 0xBF), // NR11
+            (0xFF12, 0xF3), // NR12
+            (0xFF13, 0xFF), // NR13
+            (0xFF14, 0xBF), // NR14
+            (0xFF16, 0x3F), // NR21
+            (0xFF17, 0x00), // NR22
+            (0xFF18, 0xFF), // NR23
+            (0xFF19, 0xBF), // NR24
+            (0xFF1A, 0x7F), // NR30
+            (0xFF1B, 0xFF), // NR31
+            (0xFF1C, 0x9F), // NR32
+            (0xFF1D, 0xFF), // NR33
+            (0xFF1E, 0xBF), // NR34
+            (0xFF20, 0xFF), // NR41
+            (0xFF21, 0x00), // NR42
+            (0xFF22, 0x00), // NR43
+            (0xFF23, 0xBF), // NR44
+            (0xFF24, 0x77), // NR50
+            (0xFF25, 0xF3), // NR51
+            (0xFF26, 0xF1), // NR52
+            (0xFF40, 0x91), // LCDC
+            (0xFF41, stat), // STAT
+            (0xFF46, dma),  // DMA
+            (0xFF47, 0xFC), // BGP
+            (0xFF48, 0xFF), // OBP0
+            (0xFF49, 0xFF), // OBP1
+            (0xFF4A, 0x00), // WY
+        ]
+    }
+}
+
+/// Everything on the other end of the CPU's address bus: the cartridge, the internal RAM, and all
+/// the hardware that's controlled through memory-mapped registers. Each address gets routed to
+/// wherever it actually lives.
+pub struct MemoryMap {
+    pub model: Model,
     pub cartridge: Option<Cartridge>,
 
     // internal RAM
@@ -40,12 +186,112 @@ pub struct Console {
     pub oam: Vec<u8>,
     pub hardware: Vec<u8>,
     pub hi_ram: Vec<u8>,
-    pub ie: bool,
+    pub ie: u8,
+
+    pub joypad: Joypad,
+    pub timer: Timer,
+
+    // Whatever's plugged into the link port, if anything
+    pub serial: Option<Box<dyn SerialLink>>,
+    // Clock cycles left until the serial transfer in progress is done, if there is one
+    serial_cycles: Option<usize>,
+
+    // Whether VRAM and OAM are cut off from the CPU while the PPU is using them
+    pub vram_access_strict: bool,
 }
 
-impl Console {
-    pub fn start(cartridge: Option<Cartridge>) -> Self {
+/// The settings a console can be started with, all in one place so a frontend can keep hold of
+/// them between runs. `Default` gives a DMG with everything else left as it would be otherwise.
+///
+/// There's no emulation speed in here, since the console doesn't keep time itself: it runs as
+/// fast as the frontend calls `run_frame`. (Double speed on the CGB is up to the game.) The
+/// renderer isn't either, since that's picked when the crate is built, with the `ppu-scanline`
+/// and `ppu-fifo` features.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConsoleConfig {
+    pub model: Model,
+    pub frame_skip: u32,
+    pub brightness: f32,
+    pub contrast: f32,
+    /// Colors for particular layers, indexed by `Layer` (see `Console::set_layer_palette`)
+    pub layer_palettes: [Option<MonoShadeColors>; 4],
+    pub socd: Socd,
+    /// Whether VRAM and OAM read as 0xFF while the PPU is using them (see
+    /// `Console::set_vram_access_strict`)
+    pub vram_access_strict: bool,
+    pub undo_depth: usize,
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
         Self {
+            model: Model::Dmg,
+            frame_skip: 0,
+            brightness: 1.0,
+            contrast: 1.0,
+            layer_palettes: [None; 4],
+            socd: Socd::default(),
+            vram_access_strict: false,
+            undo_depth: DEFAULT_UNDO_DEPTH,
+        }
+    }
+}
+
+/// Roughly how many frames the GameBoy draws each second
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+/// The registers that the timer, serial port, and PPU write to behind the CPU's back. (DIV and
+/// TIMA are kept in `Timer`.)
+const TICKED_IO: [usize; 6] = [INTERRUPT_FLAG, SB, SC, KEY1, LY, STAT];
+
+/// Everything that moves along by itself while an instruction runs, as it was beforehand, so
+/// that undoing the instruction can put it back. What's already been drawn or played isn't
+/// included, and neither is anything sent over the link cable.
+struct Ticked {
+    io: [u8; TICKED_IO.len()],
+    timer: Timer,
+    serial_cycles: Option<usize>,
+    // LY, how far into the line, the coincidence flag, and whether the LCD is on
+    ppu: (u8, u64, bool, bool),
+}
+
+/// The whole GameBoy: a CPU hooked up to the memory map
+pub struct Console {
+    pub cpu: Cpu,
+    pub bus: MemoryMap,
+    pub ppu: Ppu,
+    history: UndoHistory<Ticked>,
+    // Image adjustments applied in `screen_rgb`
+    brightness: f32,
+    contrast: f32,
+    // Colors for particular layers that `screen_rgb` uses instead of the ones it's given
+    layer_palettes: [Option<MonoShadeColors>; 4],
+    // How many frames `run_frame` runs without drawing them
+    frame_skip: u32,
+    // How far the last frame ran over, since instructions don't line up with the end of a frame.
+    // It's taken off the next one so frames don't drift.
+    frame_overrun: u64,
+    // How many times each opcode has been executed, if anyone's asked
+    opcode_counts: Option<Box<[u64; 256]>>,
+    // Whether `run_frame` keeps the cartridge's clock going, and how many frames it's run towards
+    // the next second
+    rtc_auto_advance: bool,
+    rtc_frames: u32,
+    // The sound being recorded to a WAV file, if it is
+    wav_recording: Option<(Apu, WavWriter<BufWriter<File>>)>,
+    // How many times the PPU has gone into V-blank
+    frame_count: u64,
+    // Addresses that get written with the same value after every frame, for cheats
+    ram_freezes: Vec<(u16, u8)>,
+    // Called with every byte sent out over the link cable
+    serial_out: Option<Box<dyn FnMut(u8)>>,
+}
+
+impl MemoryMap {
+    pub fn new(model: Model, cartridge: Option<Cartridge>) -> Self {
+        Self {
+            model,
             cartridge,
             chr_ram: vec![0; CHR_RAM_SIZE],
             bg_data: vec![0; BG_MAP_DATA_SIZE],
@@ -53,7 +299,12 @@ impl Console {
             oam: vec![0; OAM_SIZE],
             hardware: vec![0; HARDWARE_IO_SIZE],
             hi_ram: vec![0; HIGH_RAM_SIZE],
-            ie: false
+            ie: 0,
+            joypad: Joypad::init(),
+            timer: Timer::init(),
+            serial: None,
+            serial_cycles: None,
+            vram_access_strict: false,
         }
     }
 
@@ -69,6 +320,9 @@ impl Console {
                 None
             },
 
+            // Blocked while the PPU is using it
+            0x8000 ..= 0x9FFF | 0xFE00 ..= 0xFE9F if self.access_blocked(offset) => Some(0xFF),
+
             // Character RAM
             0x8000 ..= 0x97FF => self.chr_ram.get(offset - CHR_RAM_START).map(|b| *b),
 
@@ -94,14 +348,32 @@ impl Console {
             // Unused
             0xFEA0 ..= 0xFEFF => None,
 
+            // Joypad
+            JOYPAD => Some(self.joypad.read()),
+
+            // Timer
+            DIV ..= TAC => self.timer.read(offset),
+
+            // Open bus
+            unmapped if is_unmapped_io(unmapped, self.model) => Some(0xFF),
+
+            // Only bits 0 and 7 of KEY1 are used
+            KEY1 => Some(0x7E | self.hardware[KEY1 - HARDWARE_IO_START]),
+
+            // Bits 4-6 of NR52 aren't used
+            NR52 => Some(0x70 | self.hardware[NR52 - HARDWARE_IO_START]),
+
+            // Bit 7 of STAT isn't used
+            STAT => Some(0x80 | self.hardware[STAT - HARDWARE_IO_START]),
+
             // Hardware I/O
-            0xFF00 ..= 0xFF7F => self.hardware.get(offset - HARDWARE_IO_START).map(|b| *b),
+            0xFF01 ..= 0xFF7F => self.hardware.get(offset - HARDWARE_IO_START).map(|b| *b),
 
             // High RAM Area
             0xFF80 ..= 0xFFFE => self.hi_ram.get(offset - HIGH_RAM_START).map(|b| *b),
 
             // Interrupt Enable Register
-            0xFFFF => Some(self.ie as u8),
+            0xFFFF => Some(self.ie),
 
             _ => None
         }
@@ -119,6 +391,9 @@ impl Console {
                 None
             },
 
+            // Blocked while the PPU is using it
+            0x8000 ..= 0x9FFF | 0xFE00 ..= 0xFE9F if self.access_blocked(offset) => Some(()),
+
             // Character RAM
             0x8000 ..= 0x97FF =>
                 self.chr_ram.get_mut(offset - CHR_RAM_START).map(|b| *b = data),
@@ -129,7 +404,7 @@ impl Console {
 
             // Mapped to cartridge RAM
             0xA000 ..= 0xBFFF => if let Some(cart) = &mut self.cartridge {
-                Some(cart.mbc.write_rom(offset - CARTRIDGE_RAM_START, data))
+                cart.mbc.write_ram(offset - CARTRIDGE_RAM_START, data).ok().map(|_| ())
             } else {
                 None
             },
@@ -149,8 +424,76 @@ impl Console {
             // Unused
             0xFEA0 ..= 0xFEFF => None,
 
+            // Joypad
+            JOYPAD => {
+                if self.joypad.write(data) {
+                    self.request_interrupt(Interrupt::Joypad);
+                }
+                Some(())
+            },
+
+            // Timer
+            DIV ..= TAC => self.timer.write(offset, data),
+
+            // Open bus
+            unmapped if is_unmapped_io(unmapped, self.model) => Some(()),
+
+            // Only the prepare bit of KEY1 can be written
+            KEY1 => {
+                let key1 = &mut self.hardware[KEY1 - HARDWARE_IO_START];
+                *key1 = (*key1 & 0x80) | (data & 0x01);
+                Some(())
+            },
+
+            // LY is read-only: it's the PPU's line counter
+            LY => Some(()),
+
+            // The mode and coincidence flag in the bottom 3 bits of STAT are the PPU's to set
+            STAT => {
+                let stat = &mut self.hardware[STAT - HARDWARE_IO_START];
+                *stat = (*stat & 0x07) | (data & 0x78);
+                Some(())
+            },
+
+            // Moving LYC onto (or off of) the current line shows up in STAT straight away
+            LYC => {
+                self.hardware[LYC - HARDWARE_IO_START] = data;
+
+                let coincidence = if self.hardware[LY - HARDWARE_IO_START] == data { 0x04 } else { 0 };
+                let stat = &mut self.hardware[STAT - HARDWARE_IO_START];
+                *stat = (*stat & !0x04) | coincidence;
+                Some(())
+            },
+
+            NR52 => {
+                self.set_apu_power(data & 0x80 != 0);
+                Some(())
+            },
+
+            // While the APU is off, its registers are stuck at 0. The DMG makes an exception for
+            // the length counters (but not the duty cycles sharing a register with them).
+            NR10 ..= NR51 if !self.apu_powered() => {
+                let mask = match (self.model, offset) {
+                    (Model::Cgb, _) => 0x00,
+                    (_, 0xFF11) | (_, 0xFF16) | (_, 0xFF20) => 0x3F,
+                    (_, 0xFF1B) => 0xFF,
+                    _ => 0x00,
+                };
+
+                let register = &mut self.hardware[offset - HARDWARE_IO_START];
+                *register = (*register & !mask) | (data & mask);
+                Some(())
+            },
+
+            // Serial control
+            SC => {
+                self.hardware[SC - HARDWARE_IO_START] = data;
+                self.start_serial_transfer();
+                Some(())
+            },
+
             // Hardware I/O
-            0xFF00 ..= 0xFF7F =>
+            0xFF01 ..= 0xFF7F =>
                 self.hardware.get_mut(offset - HARDWARE_IO_START).map(|b| *b = data),
 
             // High RAM Area
@@ -158,7 +501,10 @@ impl Console {
                 self.hi_ram.get_mut(offset - HIGH_RAM_START).map(|b| *b = data),
 
             // Interrupt Enable Register
-            0xFFFF => Some(self.ie = data != 0),
+            0xFFFF => {
+                self.ie = data;
+                Some(())
+            },
 
             _ => None
         }
@@ -167,4 +513,721 @@ impl Console {
     pub fn alter(&mut self, offset: usize, f: fn (u8) -> u8) -> Option<()> {
         self.read(offset).and_then(|data| self.write(offset, f(data)))
     }
-}
\ No newline at end of file
+}
+
+impl MemoryMap {
+    pub fn key1(&self) -> Key1 {
+        Key1(self.hardware[KEY1 - HARDWARE_IO_START])
+    }
+
+    /// Whether the CPU is shut out of `offset` right now. The PPU has OAM to itself while it scans
+    /// it (mode 2) and both OAM and VRAM while it draws (mode 3), so reads there get 0xFF and
+    /// writes go nowhere. This only happens with `vram_access_strict` set, since plenty of
+    /// homebrew gets away with touching VRAM at the wrong time on emulators that don't bother.
+    fn access_blocked(&self, offset: usize) -> bool {
+        let lcd_on = self.hardware[LCDC - HARDWARE_IO_START] & 0x80 != 0;
+        let mode = self.hardware[STAT - HARDWARE_IO_START] & 0x03;
+
+        self.vram_access_strict && lcd_on && match offset {
+            OAM_START ..= 0xFE9F => mode == 2 || mode == 3,
+            _ => mode == 3,
+        }
+    }
+
+    /// Whether the APU is on (NR52 bit 7)
+    pub fn apu_powered(&self) -> bool {
+        self.hardware[NR52 - HARDWARE_IO_START] & 0x80 != 0
+    }
+
+    /// Turns the APU on or off. Turning it off clears every sound register from NR10 to NR51 (the
+    /// wave RAM is left alone), and the channels' status bits in NR52 along with them.
+    fn set_apu_power(&mut self, on: bool) {
+        let nr52 = &mut self.hardware[NR52 - HARDWARE_IO_START];
+
+        if on {
+            *nr52 |= 0x80;
+        } else {
+            *nr52 = 0;
+            self.hardware[NR10 - HARDWARE_IO_START..=NR51 - HARDWARE_IO_START]
+                .iter_mut()
+                .for_each(|register| *register = 0);
+        }
+    }
+
+    /// Setting bit 7 of SC starts a transfer, and bit 0 says that the GameBoy is the one driving
+    /// the clock. Transfers on the other side's clock never finish, since nothing here drives it.
+    fn start_serial_transfer(&mut self) {
+        let sc = self.hardware[SC - HARDWARE_IO_START];
+        self.serial_cycles = if sc & 0x81 == 0x81 {
+            Some(SERIAL_TRANSFER_CYCLES)
+        } else {
+            None
+        };
+    }
+
+    /// Moves the serial transfer in progress along by some number of clock cycles. Once all 8
+    /// bits have gone out, the byte in SB gets swapped for whatever's plugged in sends back (0xFF
+    /// if nothing is), SC bit 7 is cleared, and the serial interrupt requested. Returns the byte
+    /// that was sent, if a transfer finished.
+    pub fn step_serial(&mut self, cycles: usize) -> Option<u8> {
+        let remaining = self.serial_cycles?;
+        if remaining > cycles {
+            self.serial_cycles = Some(remaining - cycles);
+            return None;
+        }
+
+        self.serial_cycles = None;
+
+        let sent = self.hardware[SB - HARDWARE_IO_START];
+        self.hardware[SB - HARDWARE_IO_START] = match self.serial.as_mut() {
+            Some(link) => link.exchange(sent),
+            None => 0xFF,
+        };
+        self.hardware[SC - HARDWARE_IO_START] &= 0x7F;
+        self.request_interrupt(Interrupt::Serial);
+
+        Some(sent)
+    }
+}
+
+impl MemoryBus for MemoryMap {
+    fn read(&self, offset: usize) -> Option<u8> {
+        MemoryMap::read(self, offset)
+    }
+
+    fn write(&mut self, offset: usize, data: u8) -> Option<()> {
+        MemoryMap::write(self, offset, data)
+    }
+}
+
+impl Console {
+    pub fn start(cartridge: Option<Cartridge>) -> Self {
+        Self {
+            cpu: Cpu::init(),
+            bus: MemoryMap::new(Model::Dmg, cartridge),
+            ppu: Ppu::init(),
+            history: UndoHistory::new(DEFAULT_UNDO_DEPTH),
+            brightness: 1.0,
+            contrast: 1.0,
+            layer_palettes: [None; 4],
+            frame_skip: 0,
+            frame_overrun: 0,
+            opcode_counts: None,
+            rtc_auto_advance: true,
+            rtc_frames: 0,
+            wav_recording: None,
+            frame_count: 0,
+            ram_freezes: Vec::new(),
+            serial_out: None,
+        }
+    }
+
+    /// Starts up a console as it would be right after the boot ROM hands control over to the
+    /// cartridge, with the registers and I/O set up the way the given model leaves them.
+    pub fn new_with_model(model: Model, cartridge: Cartridge) -> Self {
+        let mut console = Self::start(Some(cartridge));
+        console.bus.model = model;
+        console.cpu = Cpu::post_boot(model);
+        console.ppu.set_model(model);
+
+        for &(addr, data) in model.post_boot_io().iter() {
+            match addr {
+                DIV ..= TAC => console.bus.timer.set(addr, data),
+                _ => console.bus.hardware[addr - HARDWARE_IO_START] = data,
+            }
+        }
+
+        console
+    }
+
+    /// Sets up a console (a DMG, just past the boot ROM) with a cartridge made from the raw
+    /// contents of a ROM. Cartridges whose MBC isn't emulated are turned away, since they'd just
+    /// crash as soon as they tried to switch banks, and so are cartridges with a bad header (see
+    /// `Cartridge::validate`).
+    pub fn from_rom_bytes(rom: Vec<u8>) -> Result<Self, GbError> {
+        Self::from_rom_bytes_with_config(rom, ConsoleConfig::default())
+    }
+
+    /// Same as `from_rom_bytes`, but set up with `config` rather than the defaults
+    pub fn from_rom_bytes_with_config(rom: Vec<u8>, config: ConsoleConfig) -> Result<Self, GbError> {
+        Self::check_mbc(&rom)?;
+
+        let cartridge = Cartridge::from_bytes(rom)?;
+        cartridge.validate().map_err(GbError::InvalidRom)?;
+
+        let mut console = Self::new_with_model(config.model, cartridge);
+        console.set_frame_skip(config.frame_skip);
+        console.set_brightness(config.brightness);
+        console.set_contrast(config.contrast);
+        console.layer_palettes = config.layer_palettes;
+        console.set_socd(config.socd);
+        console.set_vram_access_strict(config.vram_access_strict);
+        console.set_undo_depth(config.undo_depth);
+
+        Ok(console)
+    }
+
+    /// Same as `from_rom_bytes`, but a bad header is only reported to `warn` rather than stopping
+    /// the console from booting. The real check is done by the boot ROM, which isn't run here, so
+    /// homebrew and test ROMs with a blank logo or a wrong checksum work fine. An unsupported MBC
+    /// is still an error.
+    pub fn from_rom_bytes_lenient<W: FnMut(&str)>(rom: Vec<u8>, mut warn: W) -> Result<Self, GbError> {
+        Self::check_mbc(&rom)?;
+
+        let cartridge = Cartridge::from_bytes_with_warnings(rom, &mut warn)?;
+        if let Err(e) = cartridge.validate() {
+            warn(&e);
+        }
+
+        Ok(Self::new_with_model(Model::Dmg, cartridge))
+    }
+
+    fn check_mbc(rom: &[u8]) -> Result<(), GbError> {
+        let kind = MbcKind::from_cartridge_type(rom.get(CART_TYPE).copied().unwrap_or(0));
+        if kind.is_supported() {
+            Ok(())
+        } else {
+            Err(GbError::UnsupportedMbc(kind))
+        }
+    }
+
+    /// Runs the CPU until it has finished executing one whole instruction, remembering what it
+    /// changed so that it can be undone with `undo_instruction`
+    pub fn step_instruction(&mut self) -> Result<(), String> {
+        let start = self.cpu.cycles;
+        let was_stopped = self.cpu.is_stopped();
+        let ticked = self.ticked();
+
+        self.history.record(&mut self.cpu, &mut self.bus, ticked, |cpu, bus| {
+            loop {
+                let executing = cpu.state == CpuState::Exec;
+                cpu.step(bus)?;
+
+                if executing || cpu.is_stopped() || cpu.is_halted() {
+                    return Ok(());
+                }
+            }
+        })?;
+
+        if let Some(counts) = self.opcode_counts.as_mut() {
+            if !was_stopped {
+                let instruction = &self.cpu.instruction;
+                counts[if instruction.prefixed { 0xCB } else { instruction.opcode as usize }] += 1;
+            }
+        }
+
+        // On the CGB, `stop` with a speed switch prepared switches speeds instead of stopping
+        if self.cpu.is_stopped() && self.bus.model == Model::Cgb && self.bus.key1().prepare_switch() {
+            self.bus.hardware[KEY1 - HARDWARE_IO_START] = self.bus.key1().switched().0;
+            self.cpu.stopped = false;
+        }
+
+        // Otherwise the LCD goes off and the timer stops along with the CPU, until a button gets
+        // pressed. Only the APU keeps going, so the host doesn't run out of samples.
+        if self.cpu.is_stopped() {
+            self.ppu.turn_off();
+            self.bus.hardware[LY - HARDWARE_IO_START] = 0;
+            return self.tick_apu(self.cpu.cycles - start);
+        }
+
+        // The timer runs off the CPU's clock, so it speeds up along with it
+        let cycles = self.cpu.cycles - start;
+        if self.bus.timer.step(cycles as usize) {
+            self.bus.request_interrupt(Interrupt::Timer);
+        }
+
+        // So does the serial clock
+        if let Some(sent) = self.bus.step_serial(cycles as usize) {
+            if let Some(f) = self.serial_out.as_mut() {
+                f(sent);
+            }
+        }
+
+        // The PPU and APU don't speed up with the CPU, so in double speed they only see half the cycles
+        let cycles = match self.bus.key1().current_speed() {
+            Speed::Normal => cycles,
+            Speed::Double => cycles / 2,
+        };
+        self.tick_ppu(cycles);
+        self.tick_apu(cycles)?;
+
+        Ok(())
+    }
+
+    /// Starts counting how many times each opcode gets executed, to see where the time goes.
+    /// Prefixed instructions all count towards 0xCB.
+    pub fn enable_opcode_profiling(&mut self) {
+        if self.opcode_counts.is_none() {
+            self.opcode_counts = Some(Box::new([0; 256]));
+        }
+    }
+
+    /// How many times each opcode has been executed since profiling was turned on (all zeroes if
+    /// it never was)
+    pub fn opcode_histogram(&self) -> [u64; 256] {
+        self.opcode_counts.as_ref().map_or([0; 256], |counts| **counts)
+    }
+
+    pub fn model(&self) -> Model {
+        self.bus.model
+    }
+
+    /// The CGB's speed switch register
+    pub fn key1(&self) -> Key1 {
+        self.bus.key1()
+    }
+
+    /// Lets the APU catch up with the CPU while sound is being recorded, and writes out whatever
+    /// it made
+    fn tick_apu(&mut self, cycles: u64) -> Result<(), String> {
+        if let Some((apu, wav)) = self.wav_recording.as_mut() {
+            apu.tick(cycles, &ApuRegisters::read_from(&self.bus));
+            wav.write_samples(&apu.take_samples()).map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts recording the sound to a WAV file at `path` (16-bit stereo, at `sample_rate` samples
+    /// a second) until `stop_wav_recording` is called. A recording that's already going is
+    /// finished off first.
+    pub fn start_wav_recording<P: AsRef<Path>>(&mut self, path: P, sample_rate: u32) -> Result<(), String> {
+        self.stop_wav_recording()?;
+
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let wav = WavWriter::new(BufWriter::new(file), sample_rate).map_err(|e| e.to_string())?;
+        self.wav_recording = Some((Apu::new(sample_rate), wav));
+
+        Ok(())
+    }
+
+    /// Finishes off the WAV file being recorded to, if there is one
+    pub fn stop_wav_recording(&mut self) -> Result<(), String> {
+        if let Some((_, wav)) = self.wav_recording.take() {
+            wav.finish().map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_recording_wav(&self) -> bool {
+        self.wav_recording.is_some()
+    }
+
+    /// Lets the PPU catch up with the CPU and updates the LCD registers to match
+    fn tick_ppu(&mut self, cycles: u64) {
+        let registers = LcdRegisters::read_from(&self.bus);
+        let vram = Vram {
+            chr_ram: &self.bus.chr_ram,
+            bg_data: &self.bus.bg_data,
+        };
+
+        let (ly, mode) = (self.ppu.ly(), self.ppu.mode());
+        let coincidence = self.ppu.tick(cycles, &vram, &self.bus.oam, &registers);
+
+        if ly < 144 && self.ppu.ly() >= 144 {
+            self.frame_count += 1;
+            self.bus.request_interrupt(Interrupt::VBlank);
+        }
+
+        self.bus.hardware[LY - HARDWARE_IO_START] = self.ppu.ly();
+
+        // STAT bit 2 is the coincidence flag, and setting bit 6 asks for an interrupt when it's set.
+        // Bits 0 and 1 are the PPU's mode, and bits 3-5 ask for an interrupt on going into modes 0-2.
+        let stat = &mut self.bus.hardware[STAT - HARDWARE_IO_START];
+        *stat = (*stat & !0x07) | if self.ppu.coincidence() { 0x04 } else { 0 } | self.ppu.mode();
+
+        // Nothing gets requested while the LCD is off, not even for going into HBlank as it turns off
+        let new_mode = self.ppu.lcd_on() && mode != self.ppu.mode() && self.ppu.mode() != 3
+            && *stat & (0x08 << self.ppu.mode()) != 0;
+
+        if (coincidence && *stat & 0x40 != 0) || new_mode {
+            self.bus.request_interrupt(Interrupt::LcdStat);
+        }
+    }
+
+    /// The number of clock cycles (T-cycles) the CPU has spent executing instructions since the
+    /// console was turned on. This only ever goes up, even when instructions are undone.
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles
+    }
+
+    /// Runs instructions until a whole frame's worth of time (70224 clock cycles) has passed, and
+    /// hands back the screen. With frame skip turned on, this runs that many extra frames first,
+    /// but without drawing them.
+    ///
+    /// The last instruction of a frame usually runs a little past the end of it, so the next frame
+    /// is cut short by that much. That way frames average out to exactly the right length.
+    ///
+    /// With the `tracing` feature, each call is wrapped in a `frame` span at debug level, recording
+    /// the frame number it started on and how many cycles and instructions it took.
+    pub fn run_frame(&mut self) -> Result<&[u8], String> {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "frame",
+            frame = self.frame_count,
+            cycles = tracing::field::Empty,
+            instructions = tracing::field::Empty,
+        );
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+        #[cfg(feature = "tracing")]
+        let (start, mut instructions) = (self.cpu.cycles, 0u64);
+
+        let frames = self.frame_skip + 1;
+
+        for frame in 1..=frames {
+            self.ppu.set_rendering(frame == frames);
+
+            let end = self.cpu.cycles + CYCLES_PER_FRAME.saturating_sub(self.frame_overrun);
+            while self.cpu.cycles < end {
+                if let Err(e) = self.step_instruction() {
+                    self.ppu.set_rendering(true);
+                    return Err(e);
+                }
+
+                #[cfg(feature = "tracing")]
+                { instructions += 1; }
+            }
+            self.frame_overrun = self.cpu.cycles - end;
+
+            for &(addr, value) in self.ram_freezes.iter() {
+                self.bus.write(addr as usize, value);
+            }
+
+            if self.rtc_auto_advance {
+                self.rtc_frames += 1;
+                if self.rtc_frames == FRAMES_PER_SECOND {
+                    self.rtc_frames = 0;
+                    self.advance_rtc(1);
+                }
+            }
+        }
+
+        self.ppu.set_rendering(true);
+
+        #[cfg(feature = "tracing")]
+        {
+            span.record("cycles", self.cpu.cycles - start);
+            span.record("instructions", instructions);
+        }
+
+        Ok(self.screen())
+    }
+
+    /// Writes a value straight into memory, for cheats. If it's frozen, it gets written again
+    /// after every frame so the game can't change it for long (infinite health and the like).
+    /// Patching an address again without freezing it lets it go.
+    pub fn apply_ram_patch(&mut self, addr: u16, value: u8, freeze: bool) {
+        self.bus.write(addr as usize, value);
+
+        self.ram_freezes.retain(|&(frozen, _)| frozen != addr);
+        if freeze {
+            self.ram_freezes.push((addr, value));
+        }
+    }
+
+    /// Lets go of all the frozen addresses
+    pub fn clear_ram_patches(&mut self) {
+        self.ram_freezes.clear();
+    }
+
+    /// Moves the cartridge's real-time clock along by some number of seconds, say to catch up on
+    /// the time that passed while the emulator wasn't running. Returns None if the cartridge
+    /// doesn't have a clock.
+    pub fn advance_rtc(&mut self, seconds: u64) -> Option<()> {
+        match self.bus.cartridge.as_mut().map(|cart| &mut cart.mbc) {
+            Some(MBC::MBC3(mbc)) => {
+                mbc.rtc.advance(seconds);
+                Some(())
+            },
+            _ => None,
+        }
+    }
+
+    /// Catches the cartridge's clock up with the host's, by however much real time has passed
+    /// since `since`. Call this after loading a save with the time it was written (the save file's
+    /// modified time, say), and the clock carries on as if the battery had kept it going all along.
+    /// A time in the future counts as no time at all. Returns None if the cartridge doesn't have a
+    /// clock.
+    pub fn sync_rtc_to(&mut self, since: SystemTime) -> Option<()> {
+        let elapsed = SystemTime::now().duration_since(since).unwrap_or_default();
+        self.advance_rtc(elapsed.as_secs())
+    }
+
+    /// Turns on or off keeping the cartridge's clock going as frames are run (on by default). A
+    /// second passes every 60 frames, which is near enough to the real frame rate.
+    pub fn set_rtc_auto_advance(&mut self, on: bool) {
+        self.rtc_auto_advance = on;
+    }
+
+    /// Turns on or off blocking the CPU from VRAM and OAM while the PPU is busy with them. It's off
+    /// by default for the sake of sloppy homebrew, but worth turning on to test for accuracy.
+    pub fn set_vram_access_strict(&mut self, strict: bool) {
+        self.bus.vram_access_strict = strict;
+    }
+
+    /// Sets how many frames to skip drawing for every frame that does get drawn, for hosts that
+    /// can't keep up. Everything other than drawing still happens for the skipped frames.
+    pub fn set_frame_skip(&mut self, frames: u32) {
+        self.frame_skip = frames;
+    }
+
+    pub fn frame_skip(&self) -> u32 { self.frame_skip }
+
+    /// How many frames have been drawn (or skipped) since the console was turned on, counting each
+    /// time the PPU goes into V-blank
+    pub fn frame_count(&self) -> u64 { self.frame_count }
+
+    /// Runs frames as fast as they'll go for some number of seconds and works out how many went by
+    /// each second, for benchmarking the whole thing. Stops early if the CPU runs into trouble.
+    pub fn measure_fps(&mut self, seconds: f32) -> f32 {
+        let start = Instant::now();
+        let frames = self.frame_count;
+
+        while start.elapsed().as_secs_f32() < seconds {
+            if self.run_frame().is_err() {
+                break;
+            }
+        }
+
+        (self.frame_count - frames) as f32 / start.elapsed().as_secs_f32()
+    }
+
+    /// Runs instructions until `pred` is true or `max_cycles` clock cycles have passed, whichever
+    /// comes first. This makes it easy to run until PC hits some address, or until some value in
+    /// memory changes, etc. Returns whether `pred` was satisfied.
+    pub fn run_until<F>(&mut self, mut pred: F, max_cycles: u64) -> Result<bool, String>
+        where F: FnMut(&Console) -> bool
+    {
+        let end = self.cpu.cycles + max_cycles;
+
+        loop {
+            if pred(self) {
+                return Ok(true);
+            }
+
+            if self.cpu.cycles >= end {
+                return Ok(false);
+            }
+
+            self.step_instruction()?;
+        }
+    }
+
+    /// The current contents of the screen, one shade (0-3) per pixel. This is what a frontend
+    /// should draw after each `run_frame`.
+    pub fn screen(&self) -> &[u8] {
+        self.ppu.screen()
+    }
+
+    /// The current contents of the screen as RGB, 3 bytes per pixel, with the brightness and
+    /// contrast settings applied. Layers with their own palette (see `set_layer_palette`) are
+    /// drawn in those colors instead of `colors`.
+    pub fn screen_rgb(&self, colors: &MonoShadeColors) -> Vec<u8> {
+        let mut palettes = [*colors; 4];
+        for (palette, custom) in palettes.iter_mut().zip(self.layer_palettes.iter()) {
+            *palette = custom.unwrap_or(*palette).adjusted(self.brightness, self.contrast);
+        }
+
+        self.ppu.screen_rgb_layered(&palettes)
+    }
+
+    /// The same as `screen_rgb`, but with a box drawn around each sprite in `outline`, to see
+    /// where sprites are even when they're invisible or hidden behind the background
+    pub fn screen_rgb_with_oam_overlay(&self, colors: &MonoShadeColors, outline: [u8; 3]) -> Vec<u8> {
+        let mut rgb = self.screen_rgb(colors);
+        let tall_sprites = self.bus.hardware[LCDC - HARDWARE_IO_START] & 0x04 != 0;
+        draw_sprite_outlines(&mut rgb, &self.bus.oam, tall_sprites, outline);
+
+        rgb
+    }
+
+    /// Puts an image in front of the GameBoy Camera's lens, one byte per pixel from 0 (black) to
+    /// 255 (white), row by row from the top left. The game will see it the next time it takes a
+    /// picture. Returns None if the cartridge isn't a camera.
+    pub fn set_camera_frame(&mut self, pixels: &[u8; CAMERA_WIDTH * CAMERA_HEIGHT]) -> Option<()> {
+        match self.bus.cartridge.as_mut().map(|cart| &mut cart.mbc) {
+            Some(MBC::PocketCamera(camera)) => {
+                camera.frame.copy_from_slice(pixels);
+                Some(())
+            },
+            _ => None,
+        }
+    }
+
+    /// Gives one layer of the picture its own colors in `screen_rgb`, from lightest to darkest.
+    /// This is how you'd get the sprites to stand out from the background, like the CGB does
+    /// when it colorizes old games.
+    pub fn set_layer_palette(&mut self, layer: Layer, colors: [[u8; 3]; 4]) {
+        self.layer_palettes[layer as usize] = Some(MonoShadeColors(colors));
+    }
+
+    /// Goes back to drawing `layer` in the colors given to `screen_rgb`
+    pub fn clear_layer_palette(&mut self, layer: Layer) {
+        self.layer_palettes[layer as usize] = None;
+    }
+
+    /// Sets the brightness of `screen_rgb`'s output, from 0.0 (black) through 1.0 (unchanged) up
+    /// to 2.0. This only affects the colors, not the screen itself.
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = brightness.clamp(0.0, 2.0);
+    }
+
+    pub fn brightness(&self) -> f32 { self.brightness }
+
+    /// Sets the contrast of `screen_rgb`'s output, from 0.0 (flat grey) through 1.0 (unchanged)
+    /// up to 2.0
+    pub fn set_contrast(&mut self, contrast: f32) {
+        self.contrast = contrast.clamp(0.0, 2.0);
+    }
+
+    pub fn contrast(&self) -> f32 { self.contrast }
+
+    /// Steps back over the last instruction run by `step_instruction`, restoring the registers and
+    /// any memory it wrote to, along with the timer, the PPU's position, and the interrupts they
+    /// requested in the meantime. Returns false if there's nothing left to undo.
+    pub fn undo_instruction(&mut self) -> bool {
+        match self.history.undo(&mut self.cpu, &mut self.bus) {
+            Some(ticked) => {
+                self.restore_ticked(ticked);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn ticked(&self) -> Ticked {
+        let mut io = [0; TICKED_IO.len()];
+        for (byte, &addr) in io.iter_mut().zip(TICKED_IO.iter()) {
+            *byte = self.bus.hardware[addr - HARDWARE_IO_START];
+        }
+
+        Ticked {
+            io,
+            timer: self.bus.timer,
+            serial_cycles: self.bus.serial_cycles,
+            ppu: (self.ppu.ly, self.ppu.line_cycles, self.ppu.coincidence, self.ppu.lcd_on),
+        }
+    }
+
+    fn restore_ticked(&mut self, ticked: Ticked) {
+        for (&byte, &addr) in ticked.io.iter().zip(TICKED_IO.iter()) {
+            self.bus.hardware[addr - HARDWARE_IO_START] = byte;
+        }
+
+        self.bus.timer = ticked.timer;
+        self.bus.serial_cycles = ticked.serial_cycles;
+        let (ly, line_cycles, coincidence, lcd_on) = ticked.ppu;
+        self.ppu.ly = ly;
+        self.ppu.line_cycles = line_cycles;
+        self.ppu.coincidence = coincidence;
+        self.ppu.lcd_on = lcd_on;
+    }
+
+    /// Sets how many instructions can be undone. 0 turns off recording, which makes stepping a
+    /// little faster.
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.history.set_depth(depth);
+    }
+
+    /// Writes a save state straight out to `w` (a file, say), without building it up in memory
+    /// first. The undo history isn't saved.
+    pub fn save_state_to_writer<W: Write>(&self, w: &mut W) -> Result<(), String> {
+        state::write_state(self, w).map_err(|e| format!("Could not write save state: {}", e))
+    }
+
+    /// Loads a save state from `r`. It has to come from a console running the same cartridge. If
+    /// anything's wrong with it, the console is left alone. Instructions from before the state
+    /// was loaded can't be undone.
+    pub fn load_state_from_reader<R: Read>(&mut self, r: &mut R) -> Result<(), String> {
+        state::read_state(r)?.restore(self)?;
+        self.history.clear();
+
+        Ok(())
+    }
+
+    /// Snapshots the whole machine: the CPU, memory, timer, PPU, and the cartridge's banks and RAM.
+    /// States start with a magic number and a format version, so old or foreign ones are refused.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        // Writing to a Vec can't fail
+        let _ = self.save_state_to_writer(&mut state);
+        state
+    }
+
+    /// Puts the console back to a state from `save_state`
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.load_state_from_reader(&mut &state[..])
+    }
+
+    /// Plugs something into the link port, like a `Printer`
+    pub fn connect_serial<L: SerialLink + 'static>(&mut self, link: L) {
+        self.bus.serial = Some(Box::new(link));
+    }
+
+    pub fn disconnect_serial(&mut self) {
+        self.bus.serial = None;
+    }
+
+    /// Calls `f` with every byte the game sends out over the link cable, whether or not anything's
+    /// plugged in. Test ROMs print their results this way.
+    pub fn on_serial_out<F: FnMut(u8) + 'static>(&mut self, f: F) {
+        self.serial_out = Some(Box::new(f));
+    }
+
+    /// Presses a button. A button going down is the only thing that wakes the CPU up from `stop`,
+    /// and it requests the joypad interrupt if the game has its row selected.
+    pub fn press(&mut self, button: Button) {
+        if !self.bus.joypad.is_held(button) {
+            self.cpu.stopped = false;
+        }
+
+        if self.bus.joypad.press(button) {
+            self.bus.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.bus.joypad.release(button);
+    }
+
+    /// The buttons the player is holding right now, for showing on screen. This is what's
+    /// actually held, so opposing directions both show up no matter the SOCD policy.
+    pub fn input_state(&self) -> ButtonSet {
+        self.bus.joypad.held()
+    }
+
+    /// Sets how opposing directions held at the same time are reported to the game
+    pub fn set_socd(&mut self, policy: Socd) {
+        self.bus.joypad.set_socd(policy);
+    }
+
+    /// The whole memory map, for renderers and debuggers that want to look at VRAM, OAM, or the
+    /// I/O registers without going through the console a byte at a time
+    pub fn memory_bus(&self) -> &MemoryMap {
+        &self.bus
+    }
+
+    pub fn read(&self, offset: usize) -> Option<u8> {
+        self.bus.read(offset)
+    }
+
+    pub fn write(&mut self, offset: usize, data: u8) -> Option<()> {
+        self.bus.write(offset, data)
+    }
+
+    pub fn alter(&mut self, offset: usize, f: fn (u8) -> u8) -> Option<()> {
+        self.bus.alter(offset, f)
+    }
+}
+
+impl MemoryBus for Console {
+    fn read(&self, offset: usize) -> Option<u8> {
+        Console::read(self, offset)
+    }
+
+    fn write(&mut self, offset: usize, data: u8) -> Option<()> {
+        Console::write(self, offset, data)
+    }
+}
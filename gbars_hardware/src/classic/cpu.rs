@@ -5,11 +5,13 @@ use super::instruction::{Instruction, Arg};
 use super::registers::Registers;
 use bitmatch::bitmatch;
 use core::ops::Add;
-use super::registers::Reg8;
-use super::utils::{wrapping_inc_16, wrapping_dec_16, add_i8_to_u16};
+use super::registers::{Reg8, Reg16};
+use super::utils::{wrapping_inc_16, add_i8_to_u16};
 use crate::classic::utils::{wrapping_dec_8, CLOCK_SPEED, wrapping_inc_8};
 use crate::classic::memory::MBC;
-use crate::classic::console::Console;
+use crate::classic::console::Model;
+use crate::classic::error::GbError;
+use crate::classic::memory::MemoryBus;
 
 /// The CPU here is conceptualized as a state machine with some frills. Consuming a byte from memory
 /// changes its state.
@@ -17,8 +19,18 @@ pub struct Cpu {
     pub(crate) state: CpuState,
     pub(crate) instruction: Instruction,
     pub(crate) registers: Registers,
+    // The interrupt master enable flag. Interrupts are only serviced while it's set.
+    pub(crate) ime: bool,
     pub(crate) disable_interrupts: bool,
-    pub(crate) enable_interrupts: bool
+    pub(crate) enable_interrupts: bool,
+    // Set by `stop`, which turns off the clock until a button is pressed
+    pub(crate) stopped: bool,
+    // Set by `halt`, which waits for an interrupt
+    pub(crate) halted: bool,
+    // Set when `halt` runs into the HALT bug, which makes the next opcode get read twice
+    pub(crate) halt_bug: bool,
+    // Clock cycles spent executing instructions since power-on
+    pub(crate) cycles: u64,
 }
 
 /// There are 3 basic states. In the `OpRead` state, the CPU reads the next byte in memory as an
@@ -44,25 +56,151 @@ pub enum DataRead {
     ShortLo
 }
 
+/// The five interrupts, highest priority first. Each one's bit in IE and IF is its number here,
+/// and its handler is at 0x40 + 8 times that.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank, Interrupt::LcdStat, Interrupt::Timer, Interrupt::Serial, Interrupt::Joypad,
+    ];
+
+    pub fn vector(self) -> u16 {
+        0x40 + 8 * self as u16
+    }
+}
+
+/// A snapshot of where the interrupts stand, for debuggers: which ones are both enabled in IE and
+/// requested in IF, and whether IME would let any of them through
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct InterruptFlags {
+    pub pending: u8,
+    pub ime: bool,
+}
+
+impl InterruptFlags {
+    pub fn contains(&self, interrupt: Interrupt) -> bool {
+        self.pending & (1 << interrupt as u8) != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Interrupt> + '_ {
+        Interrupt::ALL.iter().copied().filter(move |&interrupt| self.contains(interrupt))
+    }
+
+    /// The interrupt that'll be serviced before the next instruction, if any
+    pub fn next(&self) -> Option<Interrupt> {
+        if self.ime { self.iter().next() } else { None }
+    }
+}
+
+/// An instruction's implementation. Returns true if it took the longer of its two times, which
+/// only happens for conditional jumps, calls, and returns that are taken.
+type Handler = fn(&mut Cpu, &mut dyn MemoryBus) -> bool;
+
+lazy_static! {
+    /// Which handler executes each opcode. Matching an opcode against every pattern takes a while,
+    /// so it's done once up front, and executing an instruction is just a lookup in here.
+    static ref HANDLERS: [Handler; 256] = {
+        let mut handlers = [Cpu::unused as Handler; 256];
+        for (opcode, handler) in handlers.iter_mut().enumerate() {
+            *handler = Cpu::handler(opcode as u8);
+        }
+        handlers
+    };
+
+    /// The same for the prefixed instructions
+    static ref PREFIXED_HANDLERS: [Handler; 256] = {
+        let mut handlers = [Cpu::unused as Handler; 256];
+        for (opcode, handler) in handlers.iter_mut().enumerate() {
+            *handler = Cpu::prefixed_handler(opcode as u8);
+        }
+        handlers
+    };
+}
+
 impl Cpu {
     pub fn init() -> Self {
         Self {
             state: CpuState::OpRead(OpRead::General),
             instruction: Instruction::from_opcode(0), // NOP
             registers: Registers::init(),
+            ime: false,
             disable_interrupts: false,
-            enable_interrupts: false
+            enable_interrupts: false,
+            stopped: false,
+            halted: false,
+            halt_bug: false,
+            cycles: 0,
         }
     }
 
+    /// The CPU as the boot ROM leaves it, about to execute the cartridge's entry point at 0x0100
+    pub fn post_boot(model: Model) -> Self {
+        Self {
+            registers: Registers::post_boot(model),
+            ..Self::init()
+        }
+    }
+
+    pub fn registers(&self) -> &Registers { &self.registers }
+
+    pub fn is_stopped(&self) -> bool { self.stopped }
+
+    pub fn is_halted(&self) -> bool { self.halted }
+
+    pub fn ime(&self) -> bool { self.ime }
+
+    pub fn state(&self) -> &CpuState { &self.state }
+
+    /// Same as `step`, but hands back the state the CPU ended up in, so you can watch it go from
+    /// state to state
+    pub fn step_state<M: MemoryBus>(&mut self, memory: &mut M) -> Result<&CpuState, GbError> {
+        self.step(memory).map_err(GbError::Execution)?;
+        Ok(&self.state)
+    }
+
     /// Performs some action based on the CPU's state, and then transitions to the next state.
-    pub fn step(&mut self, console: &mut Console) -> Result<(), String> {
+    /// Returns how many clock cycles (T-cycles) went by. Fetching an instruction and its argument
+    /// doesn't take any time on its own: the whole instruction's cycles are counted when it's
+    /// executed, with the longer time for conditional jumps, calls, and returns that are taken.
+    pub fn step<M: MemoryBus>(&mut self, memory: &mut M) -> Result<u64, String> {
+        let start = self.cycles;
+
+        // Nothing happens while the CPU is stopped, but time still passes on the host's end
+        if self.stopped {
+            self.pause_for_cycles(4);
+            return Ok(self.cycles - start);
+        }
+
+        // Same goes for while it's halted, but any interrupt that's both enabled and requested wakes
+        // it up, whether or not interrupts are enabled
+        if self.halted {
+            if Self::pending_interrupt_bits(memory) == 0 {
+                self.pause_for_cycles(4);
+                return Ok(self.cycles - start);
+            }
+
+            self.halted = false;
+        }
+
         match self.state {
             // This is the initial state of the CPU. In this state, it reads the next byte in memory
             // as an opcode and decodes it as an instruction. The CPU then transitions to the next
             // state based on the argument the instruction expects.
             CpuState::OpRead(OpRead::General) => {
-                let opcode = console.read(self.registers.pc as usize).unwrap();
+                // Interrupts get in before the next instruction does
+                if self.service_interrupt(memory) {
+                    return Ok(self.cycles - start);
+                }
+
+                let opcode = memory.read(self.registers.pc as usize).unwrap();
                 self.instruction = Instruction::from_opcode(opcode);
 
                 match self.instruction.arg {
@@ -82,19 +220,24 @@ impl Cpu {
                     Arg::Offset8(_) => self.state = CpuState::DataRead(DataRead::Byte),
 
                     // And if the instruction requires 16-bit data, it transitions to the
-                    // `DataRead::ShortHi` state (since the next byte is the high-byte of whatever
-                    // data it needs)
+                    // `DataRead::ShortLo` state (since the GameBoy is little-endian, the next byte
+                    // is the low byte of whatever data it needs, and the high byte comes after)
                     Arg::Addr16(_) |
                     Arg::Data16(_) => self.state = CpuState::DataRead(DataRead::ShortLo),
                 }
 
-                self.registers.pc = wrapping_inc_16(self.registers.pc);
+                // The HALT bug: PC doesn't move past this opcode, so it gets read again next time
+                if self.halt_bug {
+                    self.halt_bug = false;
+                } else {
+                    self.registers.pc = wrapping_inc_16(self.registers.pc);
+                }
             },
 
             // In this state, the next byte in memory is read as a *prefixed* opcode, which has its
             // own instruction set.
             CpuState::OpRead(OpRead::PrefixCB) => {
-                let byte = console.read(self.registers.pc as usize).unwrap();
+                let byte = memory.read(self.registers.pc as usize).unwrap();
                 self.instruction = Instruction::prefixed(byte, "");
 
                 self.state = CpuState::Exec;
@@ -104,7 +247,7 @@ impl Cpu {
             // In this state the next byte in memory is read as a literal byte and then the
             // CPU transitions to the `Exec` state.
             CpuState::DataRead(DataRead::Byte) => {
-                let byte = console.read(self.registers.pc as usize).unwrap();
+                let byte = memory.read(self.registers.pc as usize).unwrap();
                 self.instruction.arg = match self.instruction.arg {
                     Arg::Addr8(_) => Arg::Addr8(byte),
                     Arg::Data8(_) => Arg::Data8(byte),
@@ -119,7 +262,7 @@ impl Cpu {
             // The next byte in memory is read as the low byte of a literal short and then the
             // CPU transitions to the `DataRead::ShortHi` state to get the high byte.
             CpuState::DataRead(DataRead::ShortLo) => {
-                let byte = console.read(self.registers.pc as usize).unwrap();
+                let byte = memory.read(self.registers.pc as usize).unwrap();
                 self.instruction.arg = match self.instruction.arg {
                     Arg::Addr16(_) => Arg::Addr16(byte as u16),
                     Arg::Data16(_) => Arg::Data16(byte as u16),
@@ -134,7 +277,7 @@ impl Cpu {
             // combined with the low byte obtained in the previous state to form a whole 16-bit
             // unsigned short. Then the CPU transitions to the `Exec` state.
             CpuState::DataRead(DataRead::ShortHi) => {
-                let byte = console.read(self.registers.pc as usize).unwrap() as u16;
+                let byte = memory.read(self.registers.pc as usize).unwrap() as u16;
                 self.instruction.arg = match self.instruction.arg {
                     Arg::Addr16(addr) => Arg::Addr16((byte << 8) | addr),
                     Arg::Data16(data) => Arg::Data16((byte << 8) | data),
@@ -154,596 +297,38 @@ impl Cpu {
                 let ei = self.enable_interrupts;
 
                 if self.instruction.prefixed {
-                    self.execute_prefixed_instruction(console);
+                    self.execute_prefixed_instruction(memory);
                 } else {
-                    self.execute_instruction(console);
+                    self.execute_instruction(memory);
                 }
 
                 if di {
                     // disable interrupts
                     self.disable_interrupts = false;
+                    self.ime = false;
                 }
 
                 if ei {
                     // enable interrupts
                     self.enable_interrupts = false;
+                    self.ime = true;
                 }
 
                 self.state = CpuState::OpRead(OpRead::General);
             }
         }
 
-        Ok(())
+        Ok(self.cycles - start)
     }
 
     /// Executes the current (unprefixed) instruction
-    #[bitmatch]
-    fn execute_instruction(&mut self, console: &mut Console) -> Result<(), String> {
-        let opcode = self.instruction.opcode;
-        let arg = &self.instruction.arg;
-
-        let extra_cycles = {
-            #[bitmatch]
-            match opcode {
-                // no operation
-                "0000_0000" => false,
-
-                // stop
-                "0001_0000" => false,
-
-                // disable interrupts after next instruction
-                "1111_0011" => {
-                    self.disable_interrupts = true;
-                    false
-                },
-
-                // enable interrupts after next instruction
-                "1111_1011" => {
-                    self.enable_interrupts = true;
-                    false
-                },
-
-                // prefixed instruction (this case isn't possible with this setup but cases must be exhaustive)
-                "1100_1011" => false,
-
-                // decimal-adjust register A
-                "0010_0111" => {
-                    self.registers.daa();
-                    false
-                },
-
-                // complement register A
-                "0010_1111" => {
-                    self.registers.cpl();
-                    false
-                }
-
-                // set carry flag
-                "0011_0111" => {
-                    self.registers.set_flags(
-                        None,
-                        Some(false),
-                        Some(false),
-                        Some(true)
-                    );
-                    false
-                },
-
-                // complement carry flag
-                "0011_1111" => {
-                    self.registers.set_flags(
-                        None,
-                        Some(false),
-                        Some(false),
-                        Some(!self.registers.carry())
-                    );
-                    false
-                },
-
-                // load immediate 16-bit value
-                "00xx_0001" => {
-                    if let &Arg::Data16(data) = arg {
-                        match x {
-                            0b00 => self.registers.set_bc(data),
-                            0b01 => self.registers.set_de(data),
-                            0b10 => self.registers.set_hl(data),
-                            0b11 => self.registers.sp = data,
-                            _ => {}
-                        }
-                    }
-                    false
-                },
-
-                // load A into a stored memory location
-                "00xx_0010" => {
-                    match x {
-                        0b00 => {
-                            console.write(self.registers.get_bc() as usize, self.registers.a.0);
-                        },
-                        0b01 => {
-                            console.write(self.registers.get_de() as usize, self.registers.a.0);
-                        },
-                        0b10 => {
-                            console.write(self.registers.get_hl() as usize, self.registers.a.0);
-                            self.registers.inc_hl();
-                        },
-                        0b11 => {
-                            console.write(self.registers.get_hl() as usize, self.registers.a.0);
-                            self.registers.dec_hl();
-                        },
-                        _ => {}
-                    }
-                    false
-                },
-
-                // load the data stored at a memory location into A
-                "00xx_1010" => {
-                    match x {
-                        0b00 => self.registers.a.0 = console.read(self.registers.get_bc() as usize).unwrap(),
-                        0b01 => self.registers.a.0 = console.read(self.registers.get_de() as usize).unwrap(),
-                        0b10 => {
-                            self.registers.a.0 = console.read(self.registers.get_hl() as usize).unwrap();
-                            self.registers.inc_hl();
-                        },
-                        0b11 => {
-                            self.registers.a.0 = console.read(self.registers.get_hl() as usize).unwrap();
-                            self.registers.dec_hl();
-                        },
-                        _ => {}
-                    }
-                    false
-                }
-
-                // 16-bit increment
-                "00xx_0011" => {
-                    if let Arg::None = arg {
-                        match x {
-                            0b00 => self.registers.inc_bc(),
-                            0b01 => self.registers.inc_de(),
-                            0b10 => self.registers.inc_hl(),
-                            0b11 => self.registers.sp = wrapping_inc_16(self.registers.sp),
-                            _ => {}
-                        }
-                    }
-                    false
-                },
-
-                // 16-bit decrement
-                "00xx_1011" => {
-                    if let Arg::None = arg {
-                        match x {
-                            0b00 => self.registers.dec_bc(),
-                            0b01 => self.registers.dec_de(),
-                            0b10 => self.registers.dec_hl(),
-                            0b11 => self.registers.sp = wrapping_dec_16(self.registers.sp),
-                            _ => {}
-                        }
-                    }
-                    false
-                }
-
-                // 8-bit increment
-                "00xx_x100" => {
-                    if let Arg::None = arg {
-                        match x {
-                            0b000 => self.registers.b += 1,
-                            0b001 => self.registers.c += 1,
-                            0b010 => self.registers.d += 1,
-                            0b011 => self.registers.e += 1,
-                            0b100 => self.registers.h += 1,
-                            0b101 => self.registers.l += 1,
-                            0b110 => {
-                                let offset = self.registers.get_hl() as usize;
-                                console.alter(offset, wrapping_inc_8);
-                            },
-                            0b111 => self.registers.a += 1,
-                            _ => {}
-                        }
-                    }
-                    false
-                }
-
-                // 8-bit decrement
-                "00xx_x101" => {
-                    if let Arg::None = arg {
-                        let before = match x {
-                            0b000 => self.registers.b.0,
-                            0b001 => self.registers.c.0,
-                            0b010 => self.registers.d.0,
-                            0b011 => self.registers.e.0,
-                            0b100 => self.registers.h.0,
-                            0b101 => self.registers.l.0,
-                            0b110 => console.read(self.registers.get_hl() as usize).unwrap(),
-                            0b111 => self.registers.a.0,
-                            _ => panic!()
-                        };
-
-                        let after = wrapping_dec_8(before);
-
-                        match x {
-                            0b000 => self.registers.b.0 = after,
-                            0b001 => self.registers.c.0 = after,
-                            0b010 => self.registers.d.0 = after,
-                            0b011 => self.registers.e.0 = after,
-                            0b100 => self.registers.h.0 = after,
-                            0b101 => self.registers.l.0 = after,
-                            0b110 => {
-                                console.write(self.registers.get_hl() as usize, after);
-                            },
-                            0b111 => self.registers.a.0 = after,
-                            _ => panic!()
-                        }
-
-                        self.registers.set_flags(
-                            Some(after == 0),
-                            Some(false),
-                            Some(Registers::half_borrow_occurred(before, after)),
-                            None
-                        );
-                    }
-                    false
-                },
-
-                // load immediate 8-bit value
-                "00xx_x110" => {
-                    if let &Arg::Data8(data) = arg {
-                        match x {
-                            0b000 => self.registers.b.load(data),
-                            0b001 => self.registers.c.load(data),
-                            0b010 => self.registers.d.load(data),
-                            0b011 => self.registers.e.load(data),
-                            0b100 => self.registers.h.load(data),
-                            0b101 => self.registers.l.load(data),
-                            0b110 => {
-                                console.write(self.registers.get_hl() as usize, data);
-                            },
-                            0b111 => self.registers.a.load(data),
-                            _ => {}
-                        }
-                    }
-                    false
-                },
-
-                // load stored 8-bit value
-                "01tt_tsss" => {
-                    if let Arg::None = arg {
-                        // halt
-                        if opcode == 0x76 {
-
-                        }
-
-                        let data = match s {
-                            0b000 => self.registers.b.0,
-                            0b001 => self.registers.c.0,
-                            0b010 => self.registers.d.0,
-                            0b011 => self.registers.e.0,
-                            0b100 => self.registers.h.0,
-                            0b101 => self.registers.l.0,
-                            0b110 => console.read(self.registers.get_hl() as usize).unwrap(),
-                            0b111 => self.registers.a.0,
-                            _ => panic!()
-                        };
-
-                        match t {
-                            0b000 => self.registers.b.load(data),
-                            0b001 => self.registers.c.load(data),
-                            0b010 => self.registers.d.load(data),
-                            0b011 => self.registers.e.load(data),
-                            0b100 => self.registers.h.load(data),
-                            0b101 => self.registers.l.load(data),
-                            0b110 => {
-                                console.write(self.registers.get_hl() as usize, data);
-                            },
-                            0b111 => self.registers.a.load(data),
-                            _ => panic!()
-                        }
-                    }
-                    false
-                },
-
-                // accumulator arithmetic
-                "10ff_fsss" => {
-                    if let Arg::None = arg {
-                        let data = match s {
-                            0b000 => self.registers.b.0,
-                            0b001 => self.registers.c.0,
-                            0b010 => self.registers.d.0,
-                            0b011 => self.registers.e.0,
-                            0b100 => self.registers.h.0,
-                            0b101 => self.registers.l.0,
-                            0b110 => console.read(self.registers.get_hl() as usize).unwrap(),
-                            0b111 => self.registers.a.0,
-                            _ => panic!()
-                        };
-
-                        match f {
-                            0b000 => self.registers.add(data),
-                            0b001 => self.registers.adc(data),
-                            0b010 => self.registers.sub(data),
-                            0b011 => self.registers.sbc(data),
-                            0b100 => self.registers.and(data),
-                            0b101 => self.registers.xor(data),
-                            0b110 => self.registers.or(data),
-                            0b111 => self.registers.cp(data),
-                            _ => {}
-                        }
-                    }
-                    false
-                },
-
-                "11xx_x110" => {
-                    if let &Arg::Data8(data) = arg {
-                        match x {
-                            0b000 => self.registers.add(data),
-                            0b001 => self.registers.adc(data),
-                            0b010 => self.registers.sub(data),
-                            0b011 => self.registers.sbc(data),
-                            0b100 => self.registers.and(data),
-                            0b101 => self.registers.xor(data),
-                            0b110 => self.registers.or(data),
-                            0b111 => self.registers.cp(data),
-                            _ => panic!()
-                        }
-                    }
-                    false
-                },
-
-                // 16-bit arithmetic
-                "00xx_1001" => {
-                    let source = match x {
-                        0b00 => self.registers.get_bc(),
-                        0b01 => self.registers.get_de(),
-                        0b10 => self.registers.get_hl(),
-                        0b11 => self.registers.sp,
-                        _ => panic!()
-                    };
-
-                    self.registers.add_hl(source);
-
-                    false
-                },
-
-                // pop the stack
-                "11xx_0001" => {
-                    let data = self.pop_stack(console);
-                    match x {
-                        0b00 => self.registers.set_bc(data),
-                        0b01 => self.registers.set_de(data),
-                        0b10 => self.registers.set_hl(data),
-                        0b11 => self.registers.set_af(data),
-                        _ => panic!()
-                    }
-                    false
-                },
-
-                // push on the stack
-                "11xx_0101" => {
-                    let data = match x {
-                        0b00 => self.registers.get_bc(),
-                        0b01 => self.registers.get_de(),
-                        0b10 => self.registers.get_hl(),
-                        0b11 => self.registers.get_af(),
-                        _ => panic!()
-                    };
-                    self.push_stack(console, data);
-                    false
-                },
-
-                // Call a reset
-                "11xx_x111" => {
-                    if let Arg::None = arg {
-                        let reset = x * 8;
-                        self.push_stack(console, self.registers.pc);
-
-                        self.registers.pc = reset as u16;
-                    }
-                    false
-                },
-
-                // relative jumps
-                "0001_1000" => {
-                    if let &Arg::Offset8(offset) = arg {
-                        self.registers.pc = add_i8_to_u16(self.registers.pc, offset);
-                    }
-                    false
-                },
-
-                "001x_x000" => {
-                    if let &Arg::Offset8(offset) = arg {
-                        let cond = match x {
-                            0b00 => !self.registers.zero(),
-                            0b01 => self.registers.zero(),
-                            0b10 => !self.registers.carry(),
-                            0b11 => self.registers.carry(),
-                            _ => panic!()
-                        };
-
-                        if cond {
-                            self.registers.pc = add_i8_to_u16(self.registers.pc, offset);
-                        }
-
-                        cond
-                    } else { false }
-                },
-
-                // absolute jumps
-                "1100_0011" => {
-                    if let &Arg::Addr16(addr) = arg {
-                        self.registers.pc = addr;
-                    }
-                    false
-                },
-
-                "1110_1001" => {
-                    self.registers.pc = self.registers.get_hl();
-                    false
-                },
-
-                "110x_x010" => {
-                    if let &Arg::Addr16(addr) = arg {
-                        let cond = match x {
-                            0b00 => !self.registers.zero(),
-                            0b01 => self.registers.zero(),
-                            0b10 => !self.registers.carry(),
-                            0b11 => self.registers.carry(),
-                            _ => panic!()
-                        };
-
-                        if cond {
-                            self.registers.pc = addr;
-                        }
-
-                        cond
-                    } else { false }
-                },
-
-                // calls
-                "1100_1101" => {
-                    if let &Arg::Addr16(addr) = arg {
-                        self.push_stack(console, self.registers.pc);
-
-                        self.registers.pc = addr;
-                    }
-                    false
-                },
-
-                "110x_x100" => {
-                    if let &Arg::Addr16(addr) = arg {
-                        let cond = match x {
-                            0b00 => !self.registers.zero(),
-                            0b01 => self.registers.zero(),
-                            0b10 => !self.registers.carry(),
-                            0b11 => self.registers.carry(),
-                            _ => panic!()
-                        };
-
-                        if cond {
-                            self.push_stack(console, self.registers.pc);
-                            self.registers.pc = addr;
-                        }
-
-                        cond
-                    } else { false }
-                },
-
-                // returns
-                "110x_1001" => {
-                    if let Arg::None = arg {
-                        self.registers.pc = self.pop_stack(console);
-
-                        if x == 1 {
-                            self.enable_interrupts = true;
-                        }
-                    }
-                    false
-                }
+    fn execute_instruction<M: MemoryBus>(&mut self, memory: &mut M) -> Result<(), String> {
+        #[cfg(not(feature = "bitmatch-dispatch"))]
+        let handler = HANDLERS[self.instruction.opcode as usize];
+        #[cfg(feature = "bitmatch-dispatch")]
+        let handler = Cpu::handler(self.instruction.opcode);
 
-                "110x_x000" => {
-                    if let Arg::None = arg {
-                        let cond = match x {
-                            0b00 => !self.registers.zero(),
-                            0b01 => self.registers.zero(),
-                            0b10 => !self.registers.carry(),
-                            0b11 => self.registers.carry(),
-                            _ => panic!()
-                        };
-
-                        if cond {
-                            self.registers.pc = self.pop_stack(console);
-                        }
-
-                        cond
-                    } else { false }
-                },
-
-                // accumulator rotations
-                "000x_x111" => {
-                    match x {
-                        0b00 => self.registers.rlca(),
-                        0b01 => self.registers.rrca(),
-                        0b10 => self.registers.rla(),
-                        0b11 => self.registers.rra(),
-                        _ => {}
-                    }
-                    false
-                },
-
-                // immediate address loads
-                "111x_0000" => {
-                    if let &Arg::Addr8(half_addr) = arg {
-                        let addr = 0xFF00 + (half_addr as usize);
-
-                        if x == 0 {
-                            console.write(addr, self.registers.a.0);
-                        } else {
-                            self.registers.a.load(console.read(addr).unwrap());
-                        }
-                    }
-                    false
-                },
-
-                "111x_0010" => {
-                    let addr = 0xFF00 + (self.registers.c.0 as usize);
-
-                    if x == 0 {
-                        console.write(addr, self.registers.a.0);
-                    } else {
-                        self.registers.a.load(console.read(addr).unwrap());
-                    }
-
-                    false
-                },
-
-                "111x_1010" => {
-                    if let &Arg::Addr16(addr) = arg {
-                        if x == 0 {
-                            console.write(addr as usize, self.registers.a.0);
-                        } else {
-                            self.registers.a.load(console.read(addr as usize).unwrap());
-                        }
-                    }
-                    false
-                },
-
-                // stack pointer loads
-                "0000_1000" => {
-                    if let &Arg::Addr16(addr) = arg {
-                        console.write(addr as usize, (self.registers.sp & 0xF0) as u8);
-                        console.write((addr + 1) as usize, (self.registers.sp & 0x0F) as u8);
-                    }
-                    false
-                },
-
-                "1111_1000" => {
-                    if let &Arg::Offset8(offset) = arg {
-                        let data = add_i8_to_u16(self.registers.sp, offset);
-                        self.registers.set_hl(data);
-                    }
-                    false
-                },
-
-                "1111_1001" => {
-                    let hl = self.registers.get_hl();
-                    self.registers.sp = hl;
-                    false
-                },
-
-                // stack pointer arithmetic
-                "1110_1000" => {
-                    if let &Arg::Offset8(offset) = arg {
-                        self.registers.sp = add_i8_to_u16(self.registers.sp, offset);
-                    }
-                    false
-                },
-
-                // unused
-                "1101_?011" => panic!(),
-                "1101_1101" => panic!(),
-                "1110_?011" => panic!(),
-                "111?_?100" => panic!(),
-                "111?_1101" => panic!()
-            }
-        };
+        let extra_cycles = handler(self, memory);
 
         self.pause_for_cycles(
             if extra_cycles {
@@ -759,183 +344,821 @@ impl Cpu {
     /// The so-called "prefixed instructions" are nonvalant bitwise operations. The opcode 0xCB
     /// is used to signal to the processor to use these instructions, so I call them "prefixed
     /// instructions".
+    fn execute_prefixed_instruction<M: MemoryBus>(&mut self, memory: &mut M) -> Result<(), String> {
+        #[cfg(not(feature = "bitmatch-dispatch"))]
+        let handler = PREFIXED_HANDLERS[self.instruction.opcode as usize];
+        #[cfg(feature = "bitmatch-dispatch")]
+        let handler = Cpu::prefixed_handler(self.instruction.opcode);
+
+        handler(self, memory);
+        self.pause_for_cycles(self.instruction.cycles.0);
+
+        Ok(())
+    }
+
+    /// Picks out the handler for an (unprefixed) opcode. Where patterns overlap, the first one
+    /// wins.
+    #[bitmatch]
+    fn handler(opcode: u8) -> Handler {
+        #[bitmatch]
+        match opcode {
+            "0000_0000" => Cpu::nop,
+            "0001_0000" => Cpu::stop,
+            "1111_0011" => Cpu::di,
+            "1111_1011" => Cpu::ei,
+            // prefixed instruction (this case isn't possible with this setup but cases must be exhaustive)
+            "1100_1011" => Cpu::nop,
+            "0010_0111" => Cpu::daa,
+            "0010_1111" => Cpu::cpl,
+            "0011_0111" => Cpu::scf,
+            "0011_1111" => Cpu::ccf,
+            "00??_0001" => Cpu::ld_rr_d16,
+            "00??_0010" => Cpu::ld_indirect_a,
+            "00??_1010" => Cpu::ld_a_indirect,
+            "00??_0011" => Cpu::inc_rr,
+            "00??_1011" => Cpu::dec_rr,
+            "00??_?100" => Cpu::inc_r,
+            "00??_?101" => Cpu::dec_r,
+            "00??_?110" => Cpu::ld_r_d8,
+            "0111_0110" => Cpu::halt,
+            "01??_????" => Cpu::ld_r_r,
+            "10??_????" => Cpu::alu_r,
+            "11??_?110" => Cpu::alu_d8,
+            "00??_1001" => Cpu::add_hl_rr,
+            "11??_0001" => Cpu::pop,
+            "11??_0101" => Cpu::push,
+            "11??_?111" => Cpu::rst,
+            "0001_1000" => Cpu::jr,
+            "001?_?000" => Cpu::jr_cc,
+            "1100_0011" => Cpu::jp,
+            "1110_1001" => Cpu::jp_hl,
+            "110?_?010" => Cpu::jp_cc,
+            "1100_1101" => Cpu::call,
+            "110?_?100" => Cpu::call_cc,
+            "110?_1001" => Cpu::ret,
+            "110?_?000" => Cpu::ret_cc,
+            "000?_?111" => Cpu::rotate_a,
+            "111?_0000" => Cpu::ldh_a8,
+            "111?_0010" => Cpu::ldh_c,
+            "111?_1010" => Cpu::ld_a16,
+            "0000_1000" => Cpu::ld_a16_sp,
+            "1111_1000" => Cpu::ld_hl_sp_offset,
+            "1111_1001" => Cpu::ld_sp_hl,
+            "1110_1000" => Cpu::add_sp_offset,
+            "????_????" => Cpu::unused,
+        }
+    }
+
+    /// Picks out the handler for a prefixed opcode. The top 5 bits say what to do, and the bottom
+    /// 3 which register to do it to (see `prefixed_target`).
     #[bitmatch]
-    fn execute_prefixed_instruction(&mut self, console: &mut Console) -> Result<(), String> {
-        // Destructure the opcode to get information about which function (f) to execute and the
-        // target (t) of the instruction.
-        #[bitmatch] let "ffff_fttt" = self.instruction.opcode;
+    fn prefixed_handler(opcode: u8) -> Handler {
+        #[bitmatch]
+        match opcode {
+            "0000_0???" => Cpu::rlc,
+            "0000_1???" => Cpu::rrc,
+            "0001_0???" => Cpu::rl,
+            "0001_1???" => Cpu::rr,
+            "0010_0???" => Cpu::sla,
+            "0010_1???" => Cpu::sra,
+            "0011_0???" => Cpu::swap,
+            "0011_1???" => Cpu::srl,
+            "01??_????" => Cpu::bit,
+            "10??_????" => Cpu::res,
+            "11??_????" => Cpu::set,
+        }
+    }
 
-        let target = match t {
+    /// Reads one of the 8-bit operands that opcodes pick out with 3 bits: B, C, D, E, H, L, (HL),
+    /// or A
+    fn read_r8(&self, memory: &dyn MemoryBus, r: u8) -> u8 {
+        match r {
             0b000 => self.registers.b.0,
             0b001 => self.registers.c.0,
             0b010 => self.registers.d.0,
             0b011 => self.registers.e.0,
             0b100 => self.registers.h.0,
             0b101 => self.registers.l.0,
-            0b110 => console.read(self.registers.get_hl() as usize).unwrap(),
+            0b110 => memory.read(self.registers.get_hl() as usize).unwrap(),
             0b111 => self.registers.a.0,
             _ => panic!()
-        };
+        }
+    }
 
+    /// The other end of `read_r8`
+    fn write_r8(&mut self, memory: &mut dyn MemoryBus, r: u8, data: u8) {
+        match r {
+            0b000 => self.registers.b.load(data),
+            0b001 => self.registers.c.load(data),
+            0b010 => self.registers.d.load(data),
+            0b011 => self.registers.e.load(data),
+            0b100 => self.registers.h.load(data),
+            0b101 => self.registers.l.load(data),
+            0b110 => {
+                memory.write(self.registers.get_hl() as usize, data);
+            },
+            0b111 => self.registers.a.load(data),
+            _ => panic!()
+        }
+    }
 
-        let result = {
-            #[bitmatch]
-            match f {
-                // rlc: rotate left through the carry
-                // C <- [7 <- 0] <- [7]
-                "00000" => {
-                    #[bitmatch] let "xyyy_yyyy" = target;
-                    let r = bitpack!("yyyy_yyyx") as u8;
-                    self.registers.set_flags(
-                        Some(r == 0),
-                        Some(false),
-                        Some(false),
-                        Some(x == 1)
-                    );
-                    r
-                },
-
-                // rrc: rotate right through the carry
-                // [0] -> [7 -> 0] -> C
-                "00001" => {
-                    #[bitmatch] let "yyyy_yyyx" = target;
-                    let r = bitpack!("xyyy_yyyy") as u8;
-                    self.registers.set_flags(
-                        Some(r == 0),
-                        Some(false),
-                        Some(false),
-                        Some(x == 1)
-                    );
-                    r
-                },
-
-                // rl: rotate left
-                // C <- [7 <- 0] <- C
-                "00010" => {
-                    let c = self.registers.carry_bit();
-                    #[bitmatch] let "xyyy_yyyy" = target;
-                    let r = bitpack!("yyyy_yyyc") as u8;
-                    self.registers.set_flags(
-                        Some(r == 0),
-                        Some(false),
-                        Some(false),
-                        Some(x == 1)
-                    );
-                    r
-                },
-
-                // rr: rotate right
-                // C -> [7 -> 0] -> C
-                "00011" => {
-                    let c = self.registers.carry_bit();
-                    #[bitmatch] let "yyyy_yyyx" = target;
-                    let r = bitpack!("cyyy_yyyy") as u8;
-                    self.registers.set_flags(
-                        Some(r == 0),
-                        Some(false),
-                        Some(false),
-                        Some(x == 1)
-                    );
-                    r
-                },
-
-                // sla: arithmetic left shift
-                // C <- [7 <- 0] <- 0
-                "00100" => {
-                    #[bitmatch] let "xyyy_yyyy" = target;
-                    let r = bitpack!("yyyy_yyy0") as u8;
-                    self.registers.set_flags(
-                        Some(r == 0),
-                        Some(false),
-                        Some(false),
-                        Some(x == 1)
-                    );
-                    r
-                },
-
-                // sra: arithmetic right shift
-                // [7] -> [7 -> 0] -> C
-                "00101" => {
-                    #[bitmatch] let "xyyy_yyyz" = target;
-                    let r = bitpack!("xxyy_yyyy") as u8;
-                    self.registers.set_flags(
-                        Some(r == 0),
-                        Some(false),
-                        Some(false),
-                        Some(z == 1)
-                    );
-                    r
-                },
-
-                // swap: swap the upper and lower nibbles
-                "00110" => {
-                    #[bitmatch] let "xxxx_yyyy" = target;
-                    let r = bitpack!("yyyy_xxxx") as u8;
-                    self.registers.set_flags(
-                        Some(r == 0),
-                        Some(false),
-                        Some(false),
-                        Some(false)
-                    );
-                    r
-                },
-
-                // srl: logical right shift
-                // 0 -> [7 -> 0] -> C
-                "00111" => {
-                    #[bitmatch] let "yyyy_yyyx" = target;
-                    let r = bitpack!("0yyy_yyyy") as u8;
-                    self.registers.set_flags(
-                        Some(r == 0),
-                        Some(false),
-                        Some(false),
-                        Some(x == 0)
-                    );
-                    r
-                },
-
-                // bit: get the value of bit n
-                "01nnn" => {
-                    let mask = 1 << n;
-                    let b = (target & mask) >> n;
-                    self.registers.set_flags(
-                        Some(b == 0),
-                        Some(false),
-                        Some(true),
-                        None
-                    );
-                    b
-                },
-
-                // res: reset bit n (set it to 0)
-                "10nnn" => {
-                    let mask = !(1 << n);
-                    target & mask
-                },
-
-                // set: set bit n (set it to 1)
-                "11nnn" => {
-                    let mask = 1 << n;
-                    target | mask
-                }
+    /// The conditions that jumps, calls, and returns are picked out with: NZ, Z, NC, or C
+    fn condition(&self, cc: u8) -> bool {
+        match cc {
+            0b00 => !self.registers.zero(),
+            0b01 => self.registers.zero(),
+            0b10 => !self.registers.carry(),
+            0b11 => self.registers.carry(),
+            _ => panic!()
+        }
+    }
+
+    /// Runs the accumulator arithmetic operation picked out by 3 bits of the opcode
+    fn alu(&mut self, f: u8, data: u8) {
+        match f {
+            0b000 => self.registers.add(data),
+            0b001 => self.registers.adc(data),
+            0b010 => self.registers.sub(data),
+            0b011 => self.registers.sbc(data),
+            0b100 => self.registers.and(data),
+            0b101 => self.registers.xor(data),
+            0b110 => self.registers.or(data),
+            0b111 => self.registers.cp(data),
+            _ => panic!()
+        }
+    }
+
+    // Everything below here is a `Handler`, and the bits of the opcode they look at are named the
+    // same as in the patterns they were picked out with.
+
+    // no operation
+    fn nop(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        false
+    }
+
+    // stop
+    // This is really a 2-byte instruction, `10 00`, so the second byte has already been
+    // read as data and PC is past it. On hardware, a second byte other than 0x00 gives
+    // a "corrupted" stop, but no game relies on that, so I treat it the same.
+    fn stop(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        self.stopped = true;
+        false
+    }
+
+    // disable interrupts after next instruction
+    fn di(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        self.disable_interrupts = true;
+        false
+    }
+
+    // enable interrupts after next instruction
+    fn ei(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        self.enable_interrupts = true;
+        false
+    }
+
+    // decimal-adjust register A
+    fn daa(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        self.registers.daa();
+        false
+    }
+
+    // complement register A
+    fn cpl(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        self.registers.cpl();
+        false
+    }
+
+    // set carry flag
+    fn scf(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        self.registers.set_flags(
+            None,
+            Some(false),
+            Some(false),
+            Some(true)
+        );
+        false
+    }
+
+    // complement carry flag
+    fn ccf(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        self.registers.set_flags(
+            None,
+            Some(false),
+            Some(false),
+            Some(!self.registers.carry())
+        );
+        false
+    }
+
+    // load immediate 16-bit value
+    #[bitmatch]
+    fn ld_rr_d16(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_????" = self.instruction.opcode;
+        if let Arg::Data16(data) = self.instruction.arg {
+            match x {
+                0b00 => self.registers.set_bc(data),
+                0b01 => self.registers.set_de(data),
+                0b10 => self.registers.set_hl(data),
+                0b11 => self.registers.sp.load(data),
+                _ => {}
             }
-        };
+        }
+        false
+    }
 
-        match t {
-            0b000 => self.registers.b.0 = result,
-            0b001 => self.registers.c.0 = result,
-            0b010 => self.registers.d.0 = result,
-            0b011 => self.registers.e.0 = result,
-            0b100 => self.registers.h.0 = result,
-            0b101 => self.registers.l.0 = result,
-            0b110 => {
-                console.write(self.registers.get_hl() as usize, result);
+    // load A into a stored memory location
+    #[bitmatch]
+    fn ld_indirect_a(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_????" = self.instruction.opcode;
+        match x {
+            0b00 => {
+                memory.write(self.registers.get_bc() as usize, self.registers.a.0);
+            },
+            0b01 => {
+                memory.write(self.registers.get_de() as usize, self.registers.a.0);
+            },
+            0b10 => {
+                memory.write(self.registers.get_hl() as usize, self.registers.a.0);
+                self.registers.inc_hl();
+            },
+            0b11 => {
+                memory.write(self.registers.get_hl() as usize, self.registers.a.0);
+                self.registers.dec_hl();
+            },
+            _ => {}
+        }
+        false
+    }
+
+    // load the data stored at a memory location into A
+    #[bitmatch]
+    fn ld_a_indirect(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_????" = self.instruction.opcode;
+        match x {
+            0b00 => self.registers.a.0 = memory.read(self.registers.get_bc() as usize).unwrap(),
+            0b01 => self.registers.a.0 = memory.read(self.registers.get_de() as usize).unwrap(),
+            0b10 => {
+                self.registers.a.0 = memory.read(self.registers.get_hl() as usize).unwrap();
+                self.registers.inc_hl();
+            },
+            0b11 => {
+                self.registers.a.0 = memory.read(self.registers.get_hl() as usize).unwrap();
+                self.registers.dec_hl();
             },
-            0b111 => self.registers.a.0 = result,
+            _ => {}
+        }
+        false
+    }
+
+    // 16-bit increment
+    #[bitmatch]
+    fn inc_rr(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_????" = self.instruction.opcode;
+        if let Arg::None = self.instruction.arg {
+            match x {
+                0b00 => self.registers.inc_bc(),
+                0b01 => self.registers.inc_de(),
+                0b10 => self.registers.inc_hl(),
+                0b11 => self.registers.sp.inc(),
+                _ => {}
+            }
+        }
+        false
+    }
+
+    // 16-bit decrement
+    #[bitmatch]
+    fn dec_rr(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_????" = self.instruction.opcode;
+        if let Arg::None = self.instruction.arg {
+            match x {
+                0b00 => self.registers.dec_bc(),
+                0b01 => self.registers.dec_de(),
+                0b10 => self.registers.dec_hl(),
+                0b11 => self.registers.sp.dec(),
+                _ => {}
+            }
+        }
+        false
+    }
+
+    // 8-bit increment
+    #[bitmatch]
+    fn inc_r(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_x???" = self.instruction.opcode;
+        if let Arg::None = self.instruction.arg {
+            let before = self.read_r8(memory, x);
+            let after = wrapping_inc_8(before);
+            self.write_r8(memory, x, after);
+
+            self.registers.set_flags(
+                Some(after == 0),
+                Some(false),
+                Some(Registers::half_carry_occurred(before, 1, false)),
+                None
+            );
+        }
+        false
+    }
+
+    // 8-bit decrement
+    #[bitmatch]
+    fn dec_r(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_x???" = self.instruction.opcode;
+        if let Arg::None = self.instruction.arg {
+            let before = self.read_r8(memory, x);
+            let after = wrapping_dec_8(before);
+            self.write_r8(memory, x, after);
+
+            self.registers.set_flags(
+                Some(after == 0),
+                Some(true),
+                Some(Registers::half_borrow_occurred(before, 1, false)),
+                None
+            );
+        }
+        false
+    }
+
+    // load immediate 8-bit value
+    #[bitmatch]
+    fn ld_r_d8(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_x???" = self.instruction.opcode;
+        if let Arg::Data8(data) = self.instruction.arg {
+            self.write_r8(memory, x, data);
+        }
+        false
+    }
+
+    // halt
+    // This sits where `ld (HL), (HL)` would be. If an interrupt is already waiting while
+    // interrupts are disabled, the CPU doesn't halt at all, but it trips over the HALT
+    // bug instead.
+    fn halt(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        if !self.ime && Self::pending_interrupt_bits(memory) != 0 {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+        false
+    }
+
+    // load stored 8-bit value
+    #[bitmatch]
+    fn ld_r_r(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??tt_tsss" = self.instruction.opcode;
+        if let Arg::None = self.instruction.arg {
+            let data = self.read_r8(memory, s);
+            self.write_r8(memory, t, data);
+        }
+        false
+    }
+
+    // accumulator arithmetic
+    #[bitmatch]
+    fn alu_r(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??ff_fsss" = self.instruction.opcode;
+        if let Arg::None = self.instruction.arg {
+            let data = self.read_r8(memory, s);
+            self.alu(f, data);
+        }
+        false
+    }
+
+    #[bitmatch]
+    fn alu_d8(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_x???" = self.instruction.opcode;
+        if let Arg::Data8(data) = self.instruction.arg {
+            self.alu(x, data);
+        }
+        false
+    }
+
+    // 16-bit arithmetic
+    #[bitmatch]
+    fn add_hl_rr(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_????" = self.instruction.opcode;
+        let source = match x {
+            0b00 => self.registers.get_bc(),
+            0b01 => self.registers.get_de(),
+            0b10 => self.registers.get_hl(),
+            0b11 => self.registers.sp.0,
             _ => panic!()
         };
 
-        Ok(())
+        self.registers.add_hl(source);
+
+        false
+    }
+
+    // pop the stack
+    #[bitmatch]
+    fn pop(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_????" = self.instruction.opcode;
+        let data = self.pop_stack(memory);
+        match x {
+            0b00 => self.registers.set_bc(data),
+            0b01 => self.registers.set_de(data),
+            0b10 => self.registers.set_hl(data),
+            // The bottom nibble of F doesn't exist, so it always comes back as 0
+            0b11 => self.registers.set_af(data & 0xFFF0),
+            _ => panic!()
+        }
+        false
+    }
+
+    // push on the stack
+    #[bitmatch]
+    fn push(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_????" = self.instruction.opcode;
+        let data = match x {
+            0b00 => self.registers.get_bc(),
+            0b01 => self.registers.get_de(),
+            0b10 => self.registers.get_hl(),
+            0b11 => self.registers.get_af(),
+            _ => panic!()
+        };
+        self.push_stack(memory, data);
+        false
+    }
+
+    // Call a reset
+    #[bitmatch]
+    fn rst(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??xx_x???" = self.instruction.opcode;
+        if let Arg::None = self.instruction.arg {
+            let reset = x * 8;
+            self.push_stack(memory, self.registers.pc);
+
+            self.registers.pc = reset as u16;
+        }
+        false
+    }
+
+    // relative jumps
+    fn jr(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        if let Arg::Offset8(offset) = self.instruction.arg {
+            self.registers.pc = add_i8_to_u16(self.registers.pc, offset);
+        }
+        false
+    }
+
+    #[bitmatch]
+    fn jr_cc(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "???x_x???" = self.instruction.opcode;
+        if let Arg::Offset8(offset) = self.instruction.arg {
+            let cond = self.condition(x);
+
+            if cond {
+                self.registers.pc = add_i8_to_u16(self.registers.pc, offset);
+            }
+
+            cond
+        } else { false }
+    }
+
+    // absolute jumps
+    fn jp(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        if let Arg::Addr16(addr) = self.instruction.arg {
+            self.registers.pc = addr;
+        }
+        false
+    }
+
+    fn jp_hl(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        self.registers.pc = self.registers.get_hl();
+        false
+    }
+
+    #[bitmatch]
+    fn jp_cc(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "???x_x???" = self.instruction.opcode;
+        if let Arg::Addr16(addr) = self.instruction.arg {
+            let cond = self.condition(x);
+
+            if cond {
+                self.registers.pc = addr;
+            }
+
+            cond
+        } else { false }
+    }
+
+    // calls
+    fn call(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        if let Arg::Addr16(addr) = self.instruction.arg {
+            self.push_stack(memory, self.registers.pc);
+
+            self.registers.pc = addr;
+        }
+        false
+    }
+
+    #[bitmatch]
+    fn call_cc(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "???x_x???" = self.instruction.opcode;
+        if let Arg::Addr16(addr) = self.instruction.arg {
+            let cond = self.condition(x);
+
+            if cond {
+                self.push_stack(memory, self.registers.pc);
+                self.registers.pc = addr;
+            }
+
+            cond
+        } else { false }
+    }
+
+    // returns
+    #[bitmatch]
+    fn ret(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "???x_????" = self.instruction.opcode;
+        if let Arg::None = self.instruction.arg {
+            self.registers.pc = self.pop_stack(memory);
+
+            // Unlike `ei`, `reti` turns interrupts back on right away, so an interrupt
+            // that came in during the handler gets serviced before anything else runs
+            if x == 1 {
+                self.ime = true;
+            }
+        }
+        false
+    }
+
+    #[bitmatch]
+    fn ret_cc(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "???x_x???" = self.instruction.opcode;
+        if let Arg::None = self.instruction.arg {
+            let cond = self.condition(x);
+
+            if cond {
+                self.registers.pc = self.pop_stack(memory);
+            }
+
+            cond
+        } else { false }
+    }
+
+    // accumulator rotations
+    #[bitmatch]
+    fn rotate_a(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "???x_x???" = self.instruction.opcode;
+        match x {
+            0b00 => self.registers.rlca(),
+            0b01 => self.registers.rrca(),
+            0b10 => self.registers.rla(),
+            0b11 => self.registers.rra(),
+            _ => {}
+        }
+        false
+    }
+
+    // immediate address loads
+    #[bitmatch]
+    fn ldh_a8(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "???x_????" = self.instruction.opcode;
+        if let Arg::Addr8(half_addr) = self.instruction.arg {
+            let addr = 0xFF00 + (half_addr as usize);
+
+            if x == 0 {
+                memory.write(addr, self.registers.a.0);
+            } else {
+                self.registers.a.load(memory.read(addr).unwrap());
+            }
+        }
+        false
+    }
+
+    #[bitmatch]
+    fn ldh_c(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "???x_????" = self.instruction.opcode;
+        let addr = 0xFF00 + (self.registers.c.0 as usize);
+
+        if x == 0 {
+            memory.write(addr, self.registers.a.0);
+        } else {
+            self.registers.a.load(memory.read(addr).unwrap());
+        }
+
+        false
+    }
+
+    #[bitmatch]
+    fn ld_a16(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "???x_????" = self.instruction.opcode;
+        if let Arg::Addr16(addr) = self.instruction.arg {
+            if x == 0 {
+                memory.write(addr as usize, self.registers.a.0);
+            } else {
+                self.registers.a.load(memory.read(addr as usize).unwrap());
+            }
+        }
+        false
+    }
+
+    // stack pointer loads
+    fn ld_a16_sp(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        if let Arg::Addr16(addr) = self.instruction.arg {
+            let Reg16(sp) = self.registers.sp;
+            memory.write(addr as usize, sp as u8);
+            memory.write(addr.wrapping_add(1) as usize, (sp >> 8) as u8);
+        }
+        false
+    }
+
+    fn ld_hl_sp_offset(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        if let Arg::Offset8(offset) = self.instruction.arg {
+            let data = self.registers.sp.wrapping_add(offset);
+            self.registers.set_hl(data);
+        }
+        false
+    }
+
+    fn ld_sp_hl(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        let hl = self.registers.get_hl();
+        self.registers.sp.load(hl);
+        false
+    }
+
+    // stack pointer arithmetic
+    fn add_sp_offset(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        if let Arg::Offset8(offset) = self.instruction.arg {
+            let sp = self.registers.sp.wrapping_add(offset);
+            self.registers.sp.load(sp);
+        }
+        false
+    }
+
+    // unused
+    fn unused(&mut self, _memory: &mut dyn MemoryBus) -> bool {
+        panic!()
+    }
+
+    /// Every prefixed instruction works on the register (or (HL)) picked out by the bottom 3 bits
+    /// of the opcode. This reads it...
+    fn prefixed_target(&self, memory: &dyn MemoryBus) -> u8 {
+        self.read_r8(memory, self.instruction.opcode & 0b111)
+    }
+
+    /// ...and this writes the result back to it
+    fn set_prefixed_target(&mut self, memory: &mut dyn MemoryBus, result: u8) -> bool {
+        self.write_r8(memory, self.instruction.opcode & 0b111, result);
+        false
+    }
+
+    // rlc: rotate left through the carry
+    // C <- [7 <- 0] <- [7]
+    #[bitmatch]
+    fn rlc(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "xyyy_yyyy" = self.prefixed_target(memory);
+        let r = bitpack!("yyyy_yyyx") as u8;
+        self.registers.set_flags(
+            Some(r == 0),
+            Some(false),
+            Some(false),
+            Some(x == 1)
+        );
+        self.set_prefixed_target(memory, r)
+    }
+
+    // rrc: rotate right through the carry
+    // [0] -> [7 -> 0] -> C
+    #[bitmatch]
+    fn rrc(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "yyyy_yyyx" = self.prefixed_target(memory);
+        let r = bitpack!("xyyy_yyyy") as u8;
+        self.registers.set_flags(
+            Some(r == 0),
+            Some(false),
+            Some(false),
+            Some(x == 1)
+        );
+        self.set_prefixed_target(memory, r)
+    }
+
+    // rl: rotate left
+    // C <- [7 <- 0] <- C
+    #[bitmatch]
+    fn rl(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        let c = self.registers.carry_bit();
+        #[bitmatch] let "xyyy_yyyy" = self.prefixed_target(memory);
+        let r = bitpack!("yyyy_yyyc") as u8;
+        self.registers.set_flags(
+            Some(r == 0),
+            Some(false),
+            Some(false),
+            Some(x == 1)
+        );
+        self.set_prefixed_target(memory, r)
+    }
+
+    // rr: rotate right
+    // C -> [7 -> 0] -> C
+    #[bitmatch]
+    fn rr(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        let c = self.registers.carry_bit();
+        #[bitmatch] let "yyyy_yyyx" = self.prefixed_target(memory);
+        let r = bitpack!("cyyy_yyyy") as u8;
+        self.registers.set_flags(
+            Some(r == 0),
+            Some(false),
+            Some(false),
+            Some(x == 1)
+        );
+        self.set_prefixed_target(memory, r)
+    }
+
+    // sla: arithmetic left shift
+    // C <- [7 <- 0] <- 0
+    #[bitmatch]
+    fn sla(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "xyyy_yyyy" = self.prefixed_target(memory);
+        let r = bitpack!("yyyy_yyy0") as u8;
+        self.registers.set_flags(
+            Some(r == 0),
+            Some(false),
+            Some(false),
+            Some(x == 1)
+        );
+        self.set_prefixed_target(memory, r)
+    }
+
+    // sra: arithmetic right shift
+    // [7] -> [7 -> 0] -> C
+    fn sra(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        let target = self.prefixed_target(memory);
+        let r = (target >> 1) | (target & 0x80);
+        self.registers.set_flags(
+            Some(r == 0),
+            Some(false),
+            Some(false),
+            Some(target & 1 == 1)
+        );
+        self.set_prefixed_target(memory, r)
+    }
+
+    // swap: swap the upper and lower nibbles
+    #[bitmatch]
+    fn swap(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "xxxx_yyyy" = self.prefixed_target(memory);
+        let r = bitpack!("yyyy_xxxx") as u8;
+        self.registers.set_flags(
+            Some(r == 0),
+            Some(false),
+            Some(false),
+            Some(false)
+        );
+        self.set_prefixed_target(memory, r)
+    }
+
+    // srl: logical right shift
+    // 0 -> [7 -> 0] -> C
+    #[bitmatch]
+    fn srl(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "yyyy_yyyx" = self.prefixed_target(memory);
+        let r = bitpack!("0yyy_yyyy") as u8;
+        self.registers.set_flags(
+            Some(r == 0),
+            Some(false),
+            Some(false),
+            Some(x == 1)
+        );
+        self.set_prefixed_target(memory, r)
+    }
+
+    // bit: get the value of bit n
+    // This only sets Z, so unlike the rest, it leaves its target alone
+    #[bitmatch]
+    fn bit(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??nn_n???" = self.instruction.opcode;
+        let mask = 1 << n;
+        let b = (self.prefixed_target(memory) & mask) >> n;
+        self.registers.set_flags(
+            Some(b == 0),
+            Some(false),
+            Some(true),
+            None
+        );
+        false
+    }
+
+    // res: reset bit n (set it to 0)
+    #[bitmatch]
+    fn res(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??nn_n???" = self.instruction.opcode;
+        let mask = !(1 << n);
+        let r = self.prefixed_target(memory) & mask;
+        self.set_prefixed_target(memory, r)
+    }
+
+    // set: set bit n (set it to 1)
+    #[bitmatch]
+    fn set(&mut self, memory: &mut dyn MemoryBus) -> bool {
+        #[bitmatch] let "??nn_n???" = self.instruction.opcode;
+        let mask = 1 << n;
+        let r = self.prefixed_target(memory) | mask;
+        self.set_prefixed_target(memory, r)
     }
 
     /// "Cycle accuracy" is not a goal of this emulator, thus the way we keep timings consistent is
@@ -945,26 +1168,71 @@ impl Cpu {
     ///
     /// TODO: This will have to be reworked for no_std.
     fn pause_for_cycles(&mut self, cycles: usize) {
+        self.cycles += cycles as u64;
+
 //        std::thread::sleep(
 //            std::time::Duration::from_secs_f64(cycles as f64 / CLOCK_SPEED as f64)
 //        )
     }
 
+    /// If interrupts are enabled and one of them is both enabled in IE (0xFFFF) and requested in
+    /// IF (0xFF0F), jumps to its handler: PC is pushed, the request is cleared, and IME is turned
+    /// off until the handler turns it back on. When more than one is pending, the lowest bit wins.
+    fn service_interrupt<M: MemoryBus>(&mut self, memory: &mut M) -> bool {
+        if !self.ime {
+            return false;
+        }
+
+        let requested = memory.read(0xFF0F).unwrap_or(0);
+        let pending = Self::pending_interrupt_bits(memory);
+        if pending == 0 {
+            return false;
+        }
+
+        let bit = pending.trailing_zeros() as u16;
+        memory.write(0xFF0F, requested & !(1 << bit));
+        self.ime = false;
+
+        self.push_stack(memory, self.registers.pc);
+        self.registers.pc = 0x40 + bit * 8;
+        self.pause_for_cycles(20);
+
+        true
+    }
+
+    /// The interrupts that are both enabled in IE and requested in IF, along with whether IME is
+    /// set. It's possible for interrupts to be pending and still not get serviced.
+    pub fn pending_interrupts<M: MemoryBus>(&self, memory: &M) -> InterruptFlags {
+        InterruptFlags {
+            pending: Self::pending_interrupt_bits(memory),
+            ime: self.ime,
+        }
+    }
+
+    /// The interrupts that are both enabled in IE (0xFFFF) and requested in IF (0xFF0F)
+    fn pending_interrupt_bits<M: MemoryBus + ?Sized>(memory: &M) -> u8 {
+        memory.read(0xFFFF).unwrap_or(0) & memory.read(0xFF0F).unwrap_or(0) & 0x1F
+    }
+
+    /// SP is moved down before each byte goes in, high byte first, so it ends up pointing at the
+    /// low byte
     #[bitmatch]
-    fn push_stack(&mut self, console: &mut Console, addr: u16) {
+    fn push_stack<M: MemoryBus + ?Sized>(&mut self, memory: &mut M, addr: u16) {
         #[bitmatch] let "hhhhhhhh_llllllll" = addr;
-        console.write(self.registers.sp as usize, h as u8);
-        self.registers.sp = wrapping_dec_16(self.registers.sp);
-        console.write(self.registers.sp as usize, l as u8);
-        self.registers.sp = wrapping_dec_16(self.registers.sp);
+        self.registers.sp.dec();
+        memory.write(self.registers.sp.0 as usize, h as u8);
+        self.registers.sp.dec();
+        memory.write(self.registers.sp.0 as usize, l as u8);
     }
 
+    /// The opposite of `push_stack`: the low byte is on top of the stack, with the high byte
+    /// above it
     #[bitmatch]
-    fn pop_stack(&mut self, console: &mut Console) -> u16 {
-        let h = console.read(self.registers.sp as usize).unwrap();
-        self.registers.sp = wrapping_inc_16(self.registers.sp);
-        let l = console.read(self.registers.sp as usize).unwrap();
-        self.registers.sp = wrapping_inc_16(self.registers.sp);
+    fn pop_stack<M: MemoryBus + ?Sized>(&mut self, memory: &mut M) -> u16 {
+        let l = memory.read(self.registers.sp.0 as usize).unwrap();
+        self.registers.sp.inc();
+        let h = memory.read(self.registers.sp.0 as usize).unwrap();
+        self.registers.sp.inc();
 
         bitpack!("hhhhhhhh_llllllll") as u16
     }
@@ -0,0 +1,33 @@
+use core::fmt;
+
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::string::String;
+
+use super::memory::MbcKind;
+
+/// Things that can go wrong when setting up or running the emulator
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum GbError {
+    /// The cartridge uses a memory bank controller that isn't emulated
+    UnsupportedMbc(MbcKind),
+    /// The ROM couldn't be made sense of
+    InvalidRom(String),
+    /// The ROM is bigger than the size limit (see `cartridge::MAX_ROM_SIZE`), given in bytes
+    RomTooLarge(usize),
+    /// Something went wrong while the CPU was running
+    Execution(String),
+}
+
+impl fmt::Display for GbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GbError::UnsupportedMbc(kind) => write!(f, "Unsupported memory bank controller: {:?}", kind),
+            GbError::InvalidRom(reason) => write!(f, "Invalid ROM: {}", reason),
+            GbError::RomTooLarge(size) => write!(f, "ROM is too large ({} bytes)", size),
+            GbError::Execution(reason) => write!(f, "Execution error: {}", reason),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for GbError {}
@@ -0,0 +1,107 @@
+use super::ppu::{Vram, LcdRegisters, shade, color_at};
+
+/// How many pixels the FIFO holds. The fetcher only pushes a new tile once there's room for all 8
+/// of its pixels.
+const FIFO_SIZE: usize = 16;
+
+/// The steps the pixel fetcher goes through to get a tile's worth of pixels, each of which takes 2
+/// clock cycles on hardware
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum FetchStep {
+    TileNumber,
+    DataLo,
+    DataHi,
+    Push,
+}
+
+/// A ring buffer of color numbers (not shades; the palette is applied on the way out)
+struct PixelFifo {
+    pixels: [u8; FIFO_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl PixelFifo {
+    fn new() -> Self {
+        Self {
+            pixels: [0; FIFO_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, color: u8) {
+        self.pixels[(self.head + self.len) % FIFO_SIZE] = color;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let color = self.pixels[self.head];
+        self.head = (self.head + 1) % FIFO_SIZE;
+        self.len -= 1;
+
+        Some(color)
+    }
+}
+
+/// Draws line `ly` of the background the way the hardware does: a fetcher reads the background map
+/// a tile at a time and pushes 8 pixels into a FIFO, and the LCD pops one pixel out per clock
+/// cycle. The first `SCX % 8` pixels are thrown away, which is how fine horizontal scrolling
+/// works. This costs more than the scanline renderer but sets things up for mid-line timing.
+pub fn render_line(ly: u8, vram: &Vram, registers: &LcdRegisters, line: &mut [u8]) {
+    if registers.lcdc & 0x01 == 0 {
+        line.iter_mut().for_each(|pixel| *pixel = 0);
+        return;
+    }
+
+    let y = ly.wrapping_add(registers.scy);
+
+    let mut fifo = PixelFifo::new();
+    let mut step = FetchStep::TileNumber;
+    let mut tile_x = registers.scx / 8;
+    let (mut lo, mut hi) = (0, 0);
+
+    let mut discard = registers.scx % 8;
+    let mut x = 0;
+
+    while x < line.len() {
+        // The fetcher
+        step = match step {
+            FetchStep::TileNumber => FetchStep::DataLo,
+            FetchStep::DataLo => FetchStep::DataHi,
+            FetchStep::DataHi => {
+                let (l, h) = vram.bg_tile_row(registers.lcdc, tile_x, y / 8, y % 8);
+                lo = l;
+                hi = h;
+                FetchStep::Push
+            },
+            FetchStep::Push => if fifo.len <= FIFO_SIZE - 8 {
+                for bit in (0..8).rev() {
+                    fifo.push(color_at(lo, hi, bit));
+                }
+
+                tile_x = (tile_x + 1) % 32;
+                FetchStep::TileNumber
+            } else {
+                FetchStep::Push
+            },
+        };
+
+        // The LCD only takes pixels while the FIFO has more than 8 in it, so there's always a
+        // tile's worth ready to mix sprites into
+        if fifo.len > 8 {
+            if let Some(color) = fifo.pop() {
+                if discard > 0 {
+                    discard -= 1;
+                } else {
+                    line[x] = shade(registers.bgp, color);
+                    x += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Where everything is in the cartridge header, which takes up 0x0100-0x014F of every ROM
+
+/// The scrolling Nintendo logo, which the boot ROM checks byte for byte
+pub const LOGO_START: usize = 0x0104;
+pub const LOGO_END: usize = 0x0134;
+
+/// The game's title, in uppercase ASCII padded with zeroes. Later games use the end of it for a
+/// manufacturer code and the CGB flag.
+pub const TITLE_START: usize = 0x0134;
+pub const TITLE_END: usize = 0x0143;
+
+/// Which MBC the cartridge has, and what else is on it (RAM, a battery, a clock...)
+pub const CART_TYPE: usize = 0x0147;
+
+/// How much ROM and RAM the cartridge has, as codes rather than sizes
+pub const ROM_SIZE: usize = 0x0148;
+pub const RAM_SIZE: usize = 0x0149;
+
+/// 0 if the game was sold in Japan, 1 if it was sold anywhere else
+pub const DEST_CODE: usize = 0x014A;
+
+/// A checksum of TITLE_START up to (but not including) itself, which the boot ROM checks
+pub const HEADER_CHECKSUM: usize = 0x014D;
+
+/// The big-endian sum of every other byte in the ROM, which nothing checks
+pub const GLOBAL_CHECKSUM: usize = 0x014E;
+
+/// Where the header ends and the game's code carries on
+pub const HEADER_END: usize = 0x0150;
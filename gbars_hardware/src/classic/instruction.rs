@@ -1,5 +1,10 @@
 #[cfg(all(not(feature = "std"), feature = "alloc"))]
-use alloc::string::{String, ToString};
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+
+use super::memory::MBC;
 
 #[derive(Debug, Clone)]
 pub struct Instruction {
@@ -41,16 +46,66 @@ impl Instruction {
         }
     }
 
+    /// Prefixed instructions all take 8 cycles when they work on a register. Working on (HL)
+    /// (target 6) means going out to memory, which takes 16 cycles to read and write back, or 12
+    /// for `bit`, which only reads.
     pub(crate) fn prefixed(
         opcode: u8,
         asm: &str
     ) -> Self {
+        let cycles = match (opcode >> 6, opcode & 0x07) {
+            (0b01, 6) => 12,
+            (_, 6) => 16,
+            _ => 8,
+        };
+
         Self {
             opcode,
             prefixed: true,
             asm: asm.to_string(),
             arg: Arg::None,
-            cycles: (8, 8),
+            cycles: (cycles, cycles),
+        }
+    }
+
+    /// How many bytes the instruction takes up, counting the opcode (and the 0xCB in front of a
+    /// prefixed one)
+    pub fn size(&self) -> usize {
+        let arg = match self.arg {
+            Arg::None => 0,
+            Arg::Data8(_) | Arg::Addr8(_) | Arg::Offset8(_) => 1,
+            Arg::Data16(_) | Arg::Addr16(_) => 2,
+        };
+
+        if self.prefixed { 2 } else { 1 + arg }
+    }
+
+    /// Writes the instruction out as assembly, with its argument filled in. Offsets come out as
+    /// signed decimal, everything else as hex.
+    pub fn disassemble(&self) -> String {
+        if self.asm.is_empty() {
+            return format!("db ${:02X}", self.opcode);
+        }
+
+        match self.arg {
+            Arg::None => self.asm.clone(),
+            Arg::Data8(data) => self.asm.replace("<d8>", &format!("${:02X}", data)),
+            Arg::Data16(data) => self.asm.replace("<d16>", &format!("${:04X}", data)),
+            Arg::Addr8(addr) => self.asm.replace("<a8>", &format!("$FF{:02X}", addr)),
+            Arg::Addr16(addr) => self.asm.replace("<a16>", &format!("${:04X}", addr)),
+            Arg::Offset8(offset) => self.asm.replace("<r8>", &offset.to_string()),
+        }
+    }
+
+    /// Same as `disassemble`, but for an instruction at `pc`, so relative jumps can say where they
+    /// end up
+    pub fn disassemble_at_pc(&self, pc: u16) -> String {
+        match self.arg {
+            Arg::Offset8(offset) if self.asm.starts_with("jr") => {
+                let target = pc.wrapping_add(self.size() as u16).wrapping_add(offset as i16 as u16);
+                format!("{} ; ${:04X}", self.disassemble(), target)
+            },
+            _ => self.disassemble(),
         }
     }
 
@@ -65,6 +120,52 @@ impl Instruction {
     }
 }
 
+/// The assembly for a prefixed instruction. These are laid out so regularly that there's no need
+/// for a table: the top two bits pick the kind of operation (and for shifts and rotates, the next
+/// three pick which one), and the bottom three pick the register.
+pub fn prefixed_asm(opcode: u8) -> String {
+    const TARGETS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+    const SHIFTS: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+
+    let target = TARGETS[(opcode & 0x07) as usize];
+    let n = (opcode >> 3) & 0x07;
+
+    match opcode >> 6 {
+        0b00 => format!("{} {}", SHIFTS[n as usize], target),
+        0b01 => format!("bit {}, {}", n, target),
+        0b10 => format!("res {}, {}", n, target),
+        _ => format!("set {}, {}", n, target),
+    }
+}
+
+/// Decodes the instruction at `addr` in the cartridge's ROM (as the CPU would see it, with the
+/// current banks), for debuggers and traces. Returns the instruction with its argument filled in
+/// and how many bytes it takes up, so the next one can be found. Bytes past the end of the ROM
+/// read as 0xFF.
+pub fn disassemble_at(mbc: &MBC, addr: usize) -> (Instruction, usize) {
+    let byte = |offset: usize| mbc.read_rom(addr + offset).unwrap_or(0xFF);
+
+    let opcode = byte(0);
+    if opcode == 0xCB {
+        let opcode = byte(1);
+        return (Instruction::prefixed(opcode, &prefixed_asm(opcode)), 2);
+    }
+
+    let mut instruction = Instruction::from_opcode(opcode);
+    let short = || (byte(2) as u16) << 8 | byte(1) as u16;
+    instruction.arg = match instruction.arg {
+        Arg::None => Arg::None,
+        Arg::Data8(_) => Arg::Data8(byte(1)),
+        Arg::Addr8(_) => Arg::Addr8(byte(1)),
+        Arg::Offset8(_) => Arg::Offset8(byte(1) as i8),
+        Arg::Data16(_) => Arg::Data16(short()),
+        Arg::Addr16(_) => Arg::Addr16(short()),
+    };
+
+    let len = instruction.size();
+    (instruction, len)
+}
+
 impl Arg {
     fn d8() -> Self { Arg::Data8(0) }
     fn d16() -> Self { Arg::Data16(0) }
@@ -0,0 +1,175 @@
+/// The eight buttons on the GameBoy. Their discriminants are the bits used to keep track of which
+/// buttons are held: the directions in the low nibble and the action buttons in the high nibble.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Button {
+    Right = 0,
+    Left = 1,
+    Up = 2,
+    Down = 3,
+    A = 4,
+    B = 5,
+    Select = 6,
+    Start = 7,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right, Button::Left, Button::Up, Button::Down,
+        Button::A, Button::B, Button::Select, Button::Start,
+    ];
+}
+
+/// A set of buttons, one bit per button (indexed by `Button`)
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct ButtonSet(pub u8);
+
+impl ButtonSet {
+    pub fn contains(&self, button: Button) -> bool {
+        self.0 & (1 << button as u8) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Button> + '_ {
+        Button::ALL.iter().copied().filter(move |&button| self.contains(button))
+    }
+}
+
+/// What to do when opposing directions are held at the same time ("simultaneous opposing cardinal
+/// directions"). A real D-pad physically can't press Left and Right together, but a keyboard can,
+/// and some games do strange things when they see both.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Socd {
+    /// Both directions cancel out and neither is reported, which is the closest to real hardware
+    #[default]
+    Neutral,
+    /// Only the most recently pressed direction is reported
+    LastWins,
+    /// Both directions are reported as-is
+    Allow,
+}
+
+/// The joypad register (P1, at 0xFF00). The buttons are wired in a 2x4 matrix: writing a 0 to bit 4
+/// selects the direction keys and writing a 0 to bit 5 selects the action buttons, and then the low
+/// nibble reads back which buttons in the selected row(s) are held. Somewhat counterintuitively, a
+/// held button reads as 0.
+///
+/// The joypad interrupt is requested whenever one of those four lines goes from 1 to 0, which can
+/// happen either by pressing a button in a selected row or by selecting a row with a button held.
+pub struct Joypad {
+    // One bit per button, indexed by `Button`, where 1 means held
+    held: u8,
+    // Bits 4 and 5 as last written by the game
+    select: u8,
+    socd: Socd,
+    // The direction on each axis that was pressed most recently, for `Socd::LastWins`
+    last_horizontal: Button,
+    last_vertical: Button,
+}
+
+impl Joypad {
+    pub fn init() -> Self {
+        Self {
+            held: 0,
+            // The boot ROM leaves both rows selected
+            select: 0x00,
+            socd: Socd::default(),
+            last_horizontal: Button::Right,
+            last_vertical: Button::Up,
+        }
+    }
+
+    /// Returns true if this pulled one of the P1 lines low, requesting the joypad interrupt
+    pub fn press(&mut self, button: Button) -> bool {
+        let lines = self.lines();
+        self.held |= 1 << button as u8;
+
+        match button {
+            Button::Left | Button::Right => self.last_horizontal = button,
+            Button::Up | Button::Down => self.last_vertical = button,
+            _ => {}
+        }
+
+        self.fell_from(lines)
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.held &= !(1 << button as u8);
+    }
+
+    pub fn is_held(&self, button: Button) -> bool {
+        self.held & (1 << button as u8) != 0
+    }
+
+    /// Every button that's held, before the SOCD policy gets a say
+    pub fn held(&self) -> ButtonSet {
+        ButtonSet(self.held)
+    }
+
+    pub fn set_socd(&mut self, policy: Socd) {
+        self.socd = policy;
+    }
+
+    pub fn socd(&self) -> Socd { self.socd }
+
+    /// Reads the P1 register. Bits 6 and 7 aren't connected to anything and always read as 1.
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    /// Only the row select bits are writable. Returns true if selecting a row pulled one of the
+    /// P1 lines low, requesting the joypad interrupt.
+    pub fn write(&mut self, data: u8) -> bool {
+        let lines = self.lines();
+        self.select = data & 0x30;
+        self.fell_from(lines)
+    }
+
+    /// The low nibble of P1: the selected rows, ANDed together, with held buttons as 0
+    fn lines(&self) -> u8 {
+        let held = self.resolve_socd();
+        let mut nibble = 0x0F;
+
+        // Direction keys
+        if self.select & 0x10 == 0 {
+            nibble &= !held & 0x0F;
+        }
+
+        // Action buttons
+        if self.select & 0x20 == 0 {
+            nibble &= !(held >> 4) & 0x0F;
+        }
+
+        nibble
+    }
+
+    fn fell_from(&self, lines: u8) -> bool {
+        lines & !self.lines() != 0
+    }
+
+    /// Applies the SOCD policy to the held buttons, returning the buttons the game gets to see
+    fn resolve_socd(&self) -> u8 {
+        let mut held = self.held;
+
+        for &(first, second, last) in [
+            (Button::Right, Button::Left, self.last_horizontal),
+            (Button::Up, Button::Down, self.last_vertical),
+        ].iter() {
+            let both = (1 << first as u8) | (1 << second as u8);
+            if held & both != both {
+                continue;
+            }
+
+            match self.socd {
+                Socd::Neutral => held &= !both,
+                Socd::LastWins => held &= !both | (1 << last as u8),
+                Socd::Allow => {}
+            }
+        }
+
+        held
+    }
+}
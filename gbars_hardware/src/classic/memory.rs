@@ -7,10 +7,76 @@ use alloc::{
 use core::ops::{Deref, DerefMut};
 use bitmatch::bitmatch;
 
+use super::cpu::Interrupt;
+use super::header::CART_TYPE;
+use super::tile::{encode_tile, TILE_SIZE};
+
 pub trait Readable {
     fn read_byte(&self, offset: usize) -> u8;
 }
 
+/// Anything the CPU can be hooked up to. Normally this is the `Console`, which maps each address to
+/// the cartridge, the internal RAM, or one of the hardware registers, but the CPU doesn't care
+/// what's on the other end as long as it can read and write bytes.
+pub trait MemoryBus {
+    fn read(&self, offset: usize) -> Option<u8>;
+
+    fn write(&mut self, offset: usize, data: u8) -> Option<()>;
+
+    fn alter(&mut self, offset: usize, f: fn (u8) -> u8) -> Option<()> {
+        self.read(offset).and_then(|data| self.write(offset, f(data)))
+    }
+
+    /// Reads `len` bytes starting at `start` in one go. Anything that can't be read comes back as
+    /// 0xFF, like open bus. That includes whatever runs off the end of the 16-bit address space.
+    fn read_slice(&self, start: usize, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| start.checked_add(i).filter(|&offset| offset <= 0xFFFF))
+            .map(|offset| offset.and_then(|offset| self.read(offset)).unwrap_or(0xFF))
+            .collect()
+    }
+
+    /// Sets the interrupt's bit in IF (0xFF0F). The CPU will get around to it once it's enabled in
+    /// IE and IME is on.
+    fn request_interrupt(&mut self, interrupt: Interrupt) {
+        let requested = self.read(0xFF0F).unwrap_or(0);
+        self.write(0xFF0F, requested | (1 << interrupt as u8));
+    }
+}
+
+/// A flat 64 KiB address space with no bank switching, no memory-mapped hardware, and no
+/// read-only regions. This is a lot less work for the CPU than going through the whole memory map,
+/// and it's plenty for test programs and small ROMs that fit entirely in the address space.
+pub struct FlatMemory(pub [u8; 0x10000]);
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        Self([0; 0x10000])
+    }
+
+    /// Copies a program into the start of the address space
+    pub fn from_program(program: &[u8]) -> Self {
+        let mut memory = Self::new();
+        let len = program.len().min(memory.0.len());
+        memory.0[..len].copy_from_slice(&program[..len]);
+        memory
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self { Self::new() }
+}
+
+impl MemoryBus for FlatMemory {
+    fn read(&self, offset: usize) -> Option<u8> {
+        self.0.get(offset).copied()
+    }
+
+    fn write(&mut self, offset: usize, data: u8) -> Option<()> {
+        self.0.get_mut(offset).map(|b| *b = data)
+    }
+}
+
 /// The ROM of the cartridge, which is a pointer to a vector of bytes
 pub struct ROM(Vec<u8>);
 
@@ -47,11 +113,71 @@ pub enum MBC {
     MBC2(MBC2),
     MBC3(MBC3),
     MBC5(MBC5),
+    PocketCamera(PocketCamera),
     RomOnly(ROM),
 }
 
+/// Which kind of memory bank controller a cartridge has, going by its header. This covers every
+/// kind a cartridge can declare, including the ones that aren't emulated.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MbcKind {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    PocketCamera,
+    BandaiTama5,
+    HuC1,
+    HuC3,
+    Unknown,
+}
+
+const SUPPORTED_MBC_KINDS: [MbcKind; 6] = [
+    MbcKind::RomOnly,
+    MbcKind::Mbc1,
+    MbcKind::Mbc2,
+    MbcKind::Mbc3,
+    MbcKind::Mbc5,
+    MbcKind::PocketCamera,
+];
+
+/// The kinds of MBC the emulator can actually run
+pub fn supported_mbc_kinds() -> &'static [MbcKind] {
+    &SUPPORTED_MBC_KINDS
+}
+
+impl MbcKind {
+    /// Works out the MBC from the cartridge type byte in the header (at 0x0147)
+    pub fn from_cartridge_type(cartridge_type: u8) -> Self {
+        match cartridge_type {
+            0x00 | 0x08 | 0x09 => MbcKind::RomOnly,
+            0x01 ..= 0x03 => MbcKind::Mbc1,
+            0x05 | 0x06 => MbcKind::Mbc2,
+            0x0B ..= 0x0D => MbcKind::Mmm01,
+            0x0F ..= 0x13 => MbcKind::Mbc3,
+            0x19 ..= 0x1E => MbcKind::Mbc5,
+            0x20 => MbcKind::Mbc6,
+            0x22 => MbcKind::Mbc7,
+            0xFC => MbcKind::PocketCamera,
+            0xFD => MbcKind::BandaiTama5,
+            0xFE => MbcKind::HuC3,
+            0xFF => MbcKind::HuC1,
+            _ => MbcKind::Unknown,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        SUPPORTED_MBC_KINDS.contains(self)
+    }
+}
+
 /// The mode for the MBC. When prompted to switch a bank, the mode determines whether the MBC
 /// will switch the ROM bank or RAM bank.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum MbcMode {
     RomSelect,
     RamSelect,
@@ -66,6 +192,17 @@ pub struct MBC1 {
     pub mode: MbcMode,
 }
 
+impl MBC1 {
+    /// The RAM bank that's mapped in. The bank register only counts in RAM banking mode; otherwise
+    /// it's always bank 0.
+    fn ram_bank(&self) -> usize {
+        match self.mode {
+            MbcMode::RomSelect => 0,
+            MbcMode::RamSelect => self.active_ram_bank,
+        }
+    }
+}
+
 pub struct MBC2 {
     pub rom: ROM,
     pub ram: RAM,
@@ -80,6 +217,82 @@ pub struct MBC3 {
     pub active_rom_bank: usize,
     pub active_ram_bank: usize,
     pub ram_and_timer_enabled: bool,
+    pub rtc: Rtc,
+    // What the game sees of the clock: a copy taken when it last latched it, so the time can't
+    // tick over halfway through being read
+    pub latched_rtc: Rtc,
+    // Set by writing 0x00 to the latch register, which has to come right before the 0x01 that
+    // latches the clock
+    pub latch_armed: bool,
+}
+
+/// The real-time clock in MBC3 cartridges. It keeps counting seconds, minutes, hours, and days
+/// (up to 511) off of a battery, even while the GameBoy is off.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Rtc {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub days: u16,
+    // Stops the clock when set, so games can set the time without it running away from them
+    pub halted: bool,
+    // Set when the day counter overflows, and stays set until the game clears it
+    pub day_carry: bool,
+}
+
+impl Rtc {
+    /// Moves the clock along by some number of seconds, unless it's halted
+    pub fn advance(&mut self, seconds: u64) {
+        if self.halted {
+            return;
+        }
+
+        let seconds = self.seconds as u64 + seconds;
+        let minutes = self.minutes as u64 + seconds / 60;
+        let hours = self.hours as u64 + minutes / 60;
+        let days = self.days as u64 + hours / 24;
+
+        self.seconds = (seconds % 60) as u8;
+        self.minutes = (minutes % 60) as u8;
+        self.hours = (hours % 24) as u8;
+        self.days = (days % 512) as u16;
+
+        if days >= 512 {
+            self.day_carry = true;
+        }
+    }
+
+    /// Reads one of the clock's registers, the way the game sees them through RAM banks
+    /// 0x08-0x0C. The last one holds bit 8 of the day counter in bit 0, with the halt flag in bit
+    /// 6 and the day counter's carry in bit 7.
+    pub fn read_register(&self, register: usize) -> u8 {
+        match register {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days as u8,
+            0x0C => (self.days >> 8) as u8 & 0x01
+                | if self.halted { 0x40 } else { 0 }
+                | if self.day_carry { 0x80 } else { 0 },
+            _ => 0xFF,
+        }
+    }
+
+    /// Sets one of the clock's registers, ignoring any bits that don't fit
+    pub fn write_register(&mut self, register: usize, data: u8) {
+        match register {
+            0x08 => self.seconds = data & 0x3F,
+            0x09 => self.minutes = data & 0x3F,
+            0x0A => self.hours = data & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | data as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((data as u16 & 0x01) << 8);
+                self.halted = data & 0x40 != 0;
+                self.day_carry = data & 0x80 != 0;
+            },
+            _ => {}
+        }
+    }
 }
 
 pub struct MBC5 {
@@ -88,6 +301,70 @@ pub struct MBC5 {
     pub active_rom_bank: usize,
     pub active_ram_bank: usize,
     pub ram_enabled: bool,
+    // Carts with a rumble motor use bit 3 of the RAM bank register to turn it on and off
+    pub rumble: bool,
+}
+
+/// The size of the image the GameBoy Camera captures, in pixels
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+/// Where in RAM bank 0 the camera puts the image it captured, as 16x14 tiles
+pub const CAMERA_IMAGE_START: usize = 0x0100;
+
+/// How many of the camera's registers there are. Register 0 starts a capture, 1-5 control the
+/// sensor, and the rest (0x06-0x35) are a 4x4 matrix of dithering thresholds.
+pub const CAMERA_REGISTERS: usize = 0x36;
+
+/// The GameBoy Camera's cartridge. Besides the usual ROM and RAM banking, writing 0x10 to the RAM
+/// bank register swaps RAM out for the camera's registers. The image sensor isn't emulated: the
+/// picture it "sees" is whatever was last put in `frame`, which gets processed into RAM when the
+/// game asks for a capture.
+pub struct PocketCamera {
+    pub rom: ROM,
+    pub ram: RAM,
+    pub active_rom_bank: usize,
+    pub active_ram_bank: usize,
+    pub ram_enabled: bool,
+    pub registers_selected: bool,
+    pub registers: [u8; CAMERA_REGISTERS],
+    // Brightness of each pixel, from 0 (black) to 255 (white), row by row from the top left
+    pub frame: Vec<u8>,
+}
+
+impl PocketCamera {
+    /// Turns `frame` into a 2bpp image in RAM bank 0, the way the camera's sensor chip does once
+    /// it's taken a picture. Each pixel is compared against the 3 thresholds for its spot in the
+    /// dithering matrix; the darker it is, the more thresholds it falls under, and the darker the
+    /// color it gets.
+    pub fn capture(&mut self) {
+        let tiles_across = CAMERA_WIDTH / 8;
+
+        for tile_y in 0..CAMERA_HEIGHT / 8 {
+            for tile_x in 0..tiles_across {
+                let mut grid = [[0; 8]; 8];
+
+                for (row, pixels) in grid.iter_mut().enumerate() {
+                    for (col, pixel) in pixels.iter_mut().enumerate() {
+                        let x = tile_x * 8 + col;
+                        let y = tile_y * 8 + row;
+                        let matrix = 0x06 + ((y % 4) * 4 + x % 4) * 3;
+                        let thresholds = &self.registers[matrix..matrix + 3];
+                        let brightness = self.frame[y * CAMERA_WIDTH + x];
+
+                        *pixel = thresholds.iter().filter(|&&t| brightness < t).count() as u8;
+                    }
+                }
+
+                let start = CAMERA_IMAGE_START + (tile_y * tiles_across + tile_x) * TILE_SIZE;
+                self.ram[start..start + TILE_SIZE].copy_from_slice(&encode_tile(&grid));
+            }
+        }
+    }
+
+    fn ram_offset(&self, offset: usize) -> usize {
+        self.active_ram_bank * 0x2000 + offset
+    }
 }
 
 impl ROM {
@@ -109,11 +386,21 @@ impl ROM {
             Some(Vec::from(&self[start..end]))
         }
     }
+
+    /// How many 16 KiB banks there are (at least 1, for tiny test ROMs)
+    pub fn banks(&self) -> usize {
+        (self.len() / 0x4000).max(1)
+    }
 }
 
 impl RAM {
     pub fn new(size: usize) -> Self {
-        Self(Vec::with_capacity(size))
+        Self(vec![0; size])
+    }
+
+    /// How many bytes of RAM there are
+    pub fn size(&self) -> usize {
+        self.len()
     }
 
     pub fn read_byte(&self, offset: usize) -> Option<u8> {
@@ -132,7 +419,7 @@ impl RAM {
     }
 
     pub fn write_byte(&mut self, offset: usize, data: u8) -> Result<usize, String> {
-        if offset > self.len() {
+        if offset >= self.len() {
             Err(format!("Could not write data at offset {:04X}: Out of bounds", offset))
         } else {
             self[offset] = data;
@@ -140,6 +427,27 @@ impl RAM {
         }
     }
 
+    /// Where `offset` into the 8 KiB window for `bank` ends up. Cartridges with less RAM than
+    /// the selected bank needs just have it mirrored.
+    fn banked_offset(&self, bank: usize, offset: usize) -> Option<usize> {
+        if self.is_empty() {
+            None
+        } else {
+            Some((bank * 0x2000 + offset) % self.len())
+        }
+    }
+
+    pub fn read_banked(&self, bank: usize, offset: usize) -> u8 {
+        self.banked_offset(bank, offset).map_or(0xFF, |offset| self[offset])
+    }
+
+    pub fn write_banked(&mut self, bank: usize, offset: usize, data: u8) -> Result<usize, String> {
+        match self.banked_offset(bank, offset) {
+            Some(offset) => self.write_byte(offset, data),
+            None => Ok(0),
+        }
+    }
+
     pub fn write_bytes(&mut self, start: usize, data: &[u8]) -> Result<usize, String> {
         if start > self.len() {
             Err(format!("Could not write data to cartridge RAM at offset {:04X}: Out of bounds", start))
@@ -160,21 +468,158 @@ impl RAM {
 }
 
 impl MBC {
+    /// Sets up the right MBC for a ROM, going by its cartridge type (0x0147), with `ram_size`
+    /// bytes of RAM. Currently only four are documented, but they cover most cases. MBC6, MBC7,
+    /// MMM01, and the HudsonSoft MBCs were not very prevalent, so those carts just get treated as
+    /// if they had no MBC (see `MbcKind::is_supported`).
+    ///
+    /// Some ROM hacks have an MBC1, MBC3, or MBC5 cartridge type with RAM but say in the header
+    /// that the RAM size is 0. The game will go right ahead and use the RAM anyway, so in that case
+    /// it gets a single 8 KiB bank, and `warn` is told about it. Other kinds of cartridge either
+    /// have their RAM built in or aren't given any, so the header is left alone for them.
+    pub fn from_cartridge<W: FnMut(&str)>(contents: Vec<u8>, mut ram_size: usize, mut warn: W) -> Self {
+        let cartridge_type = contents.get(CART_TYPE).copied().unwrap_or(0);
+
+        let has_ram = matches!(
+            cartridge_type,
+            0x02 | 0x03 | 0x10 | 0x12 | 0x13 | 0x1A | 0x1B | 0x1D | 0x1E
+        );
+
+        if has_ram && ram_size == 0 {
+            warn(&format!(
+                "Cartridge type 0x{:02X} has RAM but the header says it has none; assuming 8 KiB",
+                cartridge_type
+            ));
+            ram_size = 0x2000;
+        }
+
+        match MbcKind::from_cartridge_type(cartridge_type) {
+            MbcKind::Mbc1 => MBC::MBC1(MBC1 {
+                rom: ROM::new(contents),
+                ram: RAM::new(ram_size),
+                active_rom_bank: 1,
+                active_ram_bank: 0,
+                ram_enabled: false,
+                mode: MbcMode::RomSelect,
+            }),
+            // MBC2 has 512 half-bytes of RAM built in, regardless of what the header says
+            MbcKind::Mbc2 => MBC::MBC2(MBC2 {
+                rom: ROM::new(contents),
+                ram: RAM::new(0x200),
+                active_rom_bank: 1,
+                active_ram_bank: 0,
+                ram_enabled: false,
+            }),
+            MbcKind::Mbc3 => MBC::MBC3(MBC3 {
+                rom: ROM::new(contents),
+                ram: RAM::new(ram_size),
+                active_rom_bank: 1,
+                active_ram_bank: 0,
+                ram_and_timer_enabled: false,
+                rtc: Rtc::default(),
+                latched_rtc: Rtc::default(),
+                latch_armed: false,
+            }),
+            MbcKind::Mbc5 => MBC::MBC5(MBC5 {
+                rom: ROM::new(contents),
+                ram: RAM::new(ram_size),
+                active_rom_bank: 1,
+                active_ram_bank: 0,
+                ram_enabled: false,
+                rumble: matches!(cartridge_type, 0x1C..=0x1E),
+            }),
+            // The camera needs at least one bank of RAM to put its pictures in
+            MbcKind::PocketCamera => MBC::PocketCamera(PocketCamera {
+                rom: ROM::new(contents),
+                ram: RAM::new(ram_size.max(0x2000)),
+                active_rom_bank: 1,
+                active_ram_bank: 0,
+                ram_enabled: false,
+                registers_selected: false,
+                registers: [0; CAMERA_REGISTERS],
+                frame: vec![0xFF; CAMERA_WIDTH * CAMERA_HEIGHT],
+            }),
+            _ => MBC::RomOnly(ROM::new(contents)),
+        }
+    }
+
+    /// How many bytes of RAM the cartridge has
+    pub fn ram_size(&self) -> usize {
+        match self {
+            MBC::MBC1(mbc) => mbc.ram.len(),
+            MBC::MBC2(mbc) => mbc.ram.len(),
+            MBC::MBC3(mbc) => mbc.ram.len(),
+            MBC::MBC5(mbc) => mbc.ram.len(),
+            MBC::PocketCamera(mbc) => mbc.ram.len(),
+            MBC::RomOnly(_) => 0,
+        }
+    }
+
+    /// The whole ROM, without any banking
+    pub fn rom(&self) -> &ROM {
+        match self {
+            MBC::MBC1(mbc) => &mbc.rom,
+            MBC::MBC2(mbc) => &mbc.rom,
+            MBC::MBC3(mbc) => &mbc.rom,
+            MBC::MBC5(mbc) => &mbc.rom,
+            MBC::PocketCamera(mbc) => &mbc.rom,
+            MBC::RomOnly(rom) => rom,
+        }
+    }
+
+    /// The cartridge's RAM, if it has any
+    pub fn ram(&self) -> Option<&RAM> {
+        match self {
+            MBC::MBC1(mbc) => Some(&mbc.ram),
+            MBC::MBC2(mbc) => Some(&mbc.ram),
+            MBC::MBC3(mbc) => Some(&mbc.ram),
+            MBC::MBC5(mbc) => Some(&mbc.ram),
+            MBC::PocketCamera(mbc) => Some(&mbc.ram),
+            MBC::RomOnly(_) => None,
+        }
+    }
+
+    pub fn ram_mut(&mut self) -> Option<&mut RAM> {
+        match self {
+            MBC::MBC1(mbc) => Some(&mut mbc.ram),
+            MBC::MBC2(mbc) => Some(&mut mbc.ram),
+            MBC::MBC3(mbc) => Some(&mut mbc.ram),
+            MBC::MBC5(mbc) => Some(&mut mbc.ram),
+            MBC::PocketCamera(mbc) => Some(&mut mbc.ram),
+            MBC::RomOnly(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> MbcKind {
+        match self {
+            MBC::MBC1(_) => MbcKind::Mbc1,
+            MBC::MBC2(_) => MbcKind::Mbc2,
+            MBC::MBC3(_) => MbcKind::Mbc3,
+            MBC::MBC5(_) => MbcKind::Mbc5,
+            MBC::PocketCamera(_) => MbcKind::PocketCamera,
+            MBC::RomOnly(_) => MbcKind::RomOnly,
+        }
+    }
+
     pub fn read_rom(&self, offset: usize) -> Option<u8> {
+        // The MBC doesn't know how big the ROM is, so bank numbers past the end just wrap around
+        // (the extra bank bits aren't wired to anything)
         #[inline]
         fn read_rom_bank(rom: &ROM, offset: usize, bank: usize) -> Option<u8> {
             if offset < 0x4000 {
                 rom.read_byte(offset)
             } else {
-                rom.read_byte(0x4000 * bank + offset)
+                rom.read_byte(0x4000 * (bank % rom.banks()) + (offset - 0x4000))
             }
         }
 
         match self {
             MBC::MBC1(mbc) => {
+                // The upper two bits only count towards the ROM bank in ROM banking mode. In RAM
+                // banking mode they pick the RAM bank instead.
                 let mut active_rom_bank = match mbc.mode {
-                    MbcMode::RomSelect => mbc.active_rom_bank & 0x1F,
-                    MbcMode::RamSelect => mbc.active_rom_bank
+                    MbcMode::RomSelect => mbc.active_rom_bank,
+                    MbcMode::RamSelect => mbc.active_rom_bank & 0x1F,
                 };
 
                 // Bank 0 isn't switchable and banks 0x20, 0x40, and 0x60 are not usable. Attempting
@@ -189,6 +634,7 @@ impl MBC {
             MBC::MBC2(mbc) => read_rom_bank(&mbc.rom, offset, mbc.active_rom_bank),
             MBC::MBC3(mbc) => read_rom_bank(&mbc.rom, offset, mbc.active_rom_bank),
             MBC::MBC5(mbc) => read_rom_bank(&mbc.rom, offset, mbc.active_rom_bank),
+            MBC::PocketCamera(mbc) => read_rom_bank(&mbc.rom, offset, mbc.active_rom_bank),
             MBC::RomOnly(rom) => rom.read_byte(offset)
         }
     }
@@ -196,12 +642,13 @@ impl MBC {
     pub fn read_rom_slice(&self, start: usize, end: usize) -> Option<Vec<u8>> {
         #[inline]
         fn read_rom_bank_slice(rom: &ROM, start: usize, end: usize, bank: usize) -> Option<Vec<u8>> {
+            let bank = bank % rom.banks();
             if start < 0x4000 {
                 rom.read_bytes(start, end)
             } else {
                 rom.read_bytes(
-                    0x4000 * bank + start,
-                    0x4000 * bank + end
+                    0x4000 * bank + (start - 0x4000),
+                    0x4000 * bank + (end - 0x4000)
                 )
             }
         }
@@ -211,6 +658,7 @@ impl MBC {
             MBC::MBC2(mbc) => read_rom_bank_slice(&mbc.rom, start, end, mbc.active_rom_bank),
             MBC::MBC3(mbc) => read_rom_bank_slice(&mbc.rom, start, end, mbc.active_rom_bank),
             MBC::MBC5(mbc) => read_rom_bank_slice(&mbc.rom, start, end, mbc.active_rom_bank),
+            MBC::PocketCamera(mbc) => read_rom_bank_slice(&mbc.rom, start, end, mbc.active_rom_bank),
             MBC::RomOnly(rom) => rom.read_bytes(start, end),
         }
     }
@@ -239,16 +687,17 @@ impl MBC {
                     mbc.active_rom_bank = bank_number;
                 },
 
-                // RAM bank select or (Upper) ROM Bank select
+                // RAM bank select or (Upper) ROM Bank select, depending on the mode
                 0x4000..=0x5FFF => {
-                    let mut bank_number = (data & 0x02) as usize;
-                    if mbc.ram_enabled {
-                        mbc.active_ram_bank = bank_number;
-                    } else {
-                        bank_number <<= 5;
-                        bank_number |= mbc.active_rom_bank & 0x1F;
-
-                        mbc.active_rom_bank = bank_number;
+                    let mut bank_number = (data & 0x03) as usize;
+                    match mbc.mode {
+                        MbcMode::RamSelect => mbc.active_ram_bank = bank_number,
+                        MbcMode::RomSelect => {
+                            bank_number <<= 5;
+                            bank_number |= mbc.active_rom_bank & 0x1F;
+
+                            mbc.active_rom_bank = bank_number;
+                        },
                     }
                 },
 
@@ -277,11 +726,12 @@ impl MBC {
                     }
                 },
 
-                // ROM bank selection. We take the lower 4 bits only because MBC2 only has 16 banks.
-                // Additionally, the least significant bit of the upper address byte must be 1.
-                // This is the same byte as above.
-                0x2000..=0x3FFF => if offset & 0x0100 == 1 {
-                    let bank_number = data & 0x0F;
+                // ROM bank selection. We take the lower 4 bits only because MBC2 only has 16 banks,
+                // and asking for bank 0 gets bank 1, like on MBC1. Additionally, the least
+                // significant bit of the upper address byte must be 1. This is the same byte as
+                // above.
+                0x2000..=0x3FFF => if offset & 0x0100 != 0 {
+                    let bank_number = (data & 0x0F).max(1);
                     mbc.active_rom_bank = bank_number as usize;
                 },
 
@@ -314,9 +764,13 @@ impl MBC {
                     mbc.active_ram_bank = data as usize;
                 },
 
-                // Latches the time to the time register
-                0x6000..=0x7FFF => if data == 1 && mbc.rom[offset] == 0 {
-                    // TODO: Figure out a way to implement this
+                // Writing 0x00 and then 0x01 copies the clock's time into the registers the game
+                // reads, where it stays put until the next time it's latched
+                0x6000..=0x7FFF => {
+                    if data == 0x01 && mbc.latch_armed {
+                        mbc.latched_rtc = mbc.rtc;
+                    }
+                    mbc.latch_armed = data == 0x00;
                 },
 
                 _ => {}
@@ -329,6 +783,9 @@ impl MBC {
                     mbc.ram_enabled = true;
                 },
 
+                // The ROM bank number is 9 bits, split across two registers: the low 8 bits, and
+                // then bit 8 on its own. Unlike the other MBCs, bank 0 can go in the switchable
+                // area too.
                 0x2000..=0x2FFF => {
                     let mut bank_number = data as usize;
                     bank_number |= mbc.active_rom_bank & 0x0100;
@@ -337,13 +794,36 @@ impl MBC {
                 },
 
                 0x3000..=0x3FFF => {
-                    let mut bank_number = ((1 & data as usize) << 8);
-                    bank_number |= mbc.active_ram_bank & 0x00FF;
+                    let mut bank_number = (1 & data as usize) << 8;
+                    bank_number |= mbc.active_rom_bank & 0x00FF;
 
                     mbc.active_rom_bank = bank_number;
                 },
 
                 0x4000..=0x5FFF => {
+                    let mask = if mbc.rumble { 0x07 } else { 0x0F };
+                    mbc.active_ram_bank = (mask & data) as usize;
+                },
+
+                _ => {}
+            },
+
+            MBC::PocketCamera(mbc) => match offset {
+                0..=0x1FFF => if data == 0 {
+                    mbc.ram_enabled = false;
+                } else if data & 0x0F == 0x0A {
+                    mbc.ram_enabled = true;
+                },
+
+                0x2000..=0x3FFF => {
+                    mbc.active_rom_bank = (0x3F & data) as usize;
+                },
+
+                // Bit 4 maps the camera registers in place of RAM
+                0x4000..=0x5FFF => if data & 0x10 != 0 {
+                    mbc.registers_selected = true;
+                } else {
+                    mbc.registers_selected = false;
                     mbc.active_ram_bank = (0x0F & data) as usize;
                 },
 
@@ -354,13 +834,40 @@ impl MBC {
         }
     }
 
+    /// Reads from the cartridge RAM window (0xA000-0xBFFF, so `offset` is from the start of it)
+    /// through whichever bank is selected. With the RAM turned off, or no RAM at all, nothing
+    /// drives the bus and reads come back as 0xFF.
     pub fn read_ram(&self, offset: usize) -> Option<u8> {
         match self {
-            MBC::MBC1(mbc) => mbc.ram.read_byte(offset),
-            MBC::MBC2(mbc) => mbc.ram.read_byte(offset),
-            MBC::MBC3(mbc) => mbc.ram.read_byte(offset),
-            MBC::MBC5(mbc) => mbc.ram.read_byte(offset),
-            MBC::RomOnly(_) => None,
+            MBC::MBC1(mbc) => Some(if mbc.ram_enabled {
+                mbc.ram.read_banked(mbc.ram_bank(), offset)
+            } else {
+                0xFF
+            }),
+            // Only the bottom nibble of each byte is there; the top one reads as all 1s
+            MBC::MBC2(mbc) => Some(if mbc.ram_enabled {
+                0xF0 | mbc.ram.read_banked(0, offset % mbc.ram.len())
+            } else {
+                0xFF
+            }),
+            // Banks 0x08-0x0C are the clock's registers rather than RAM
+            MBC::MBC3(mbc) => Some(match mbc.active_ram_bank {
+                _ if !mbc.ram_and_timer_enabled => 0xFF,
+                0x00..=0x07 => mbc.ram.read_banked(mbc.active_ram_bank, offset),
+                register => mbc.latched_rtc.read_register(register),
+            }),
+            MBC::MBC5(mbc) => Some(if mbc.ram_enabled {
+                mbc.ram.read_banked(mbc.active_ram_bank, offset)
+            } else {
+                0xFF
+            }),
+            // Only register 0 can be read back. Captures finish instantly, so it never says busy.
+            MBC::PocketCamera(mbc) => if mbc.registers_selected {
+                Some(if offset & 0x7F == 0 { mbc.registers[0] } else { 0 })
+            } else {
+                mbc.ram.read_byte(mbc.ram_offset(offset))
+            },
+            MBC::RomOnly(_) => Some(0xFF),
         }
     }
 
@@ -370,16 +877,61 @@ impl MBC {
             MBC::MBC2(mbc) => mbc.ram.read_bytes(start, end),
             MBC::MBC3(mbc) => mbc.ram.read_bytes(start, end),
             MBC::MBC5(mbc) => mbc.ram.read_bytes(start, end),
+            MBC::PocketCamera(mbc) => mbc.ram.read_bytes(mbc.ram_offset(start), mbc.ram_offset(end)),
             MBC::RomOnly(_) => None,
         }
     }
 
+    /// Writes to the cartridge RAM window through whichever bank is selected. Writes while the RAM
+    /// is turned off go nowhere. Returns how many bytes were written.
     pub fn write_ram(&mut self, offset: usize, data: u8) -> Result<usize, String> {
         match self {
-            MBC::MBC1(mbc) => mbc.ram.write_byte(offset, data),
-            MBC::MBC2(mbc) => mbc.ram.write_byte(offset, data),
-            MBC::MBC3(mbc) => mbc.ram.write_byte(offset, data),
-            MBC::MBC5(mbc) => mbc.ram.write_byte(offset, data),
+            MBC::MBC1(mbc) => if mbc.ram_enabled {
+                let bank = mbc.ram_bank();
+                mbc.ram.write_banked(bank, offset, data)
+            } else {
+                Ok(0)
+            },
+            MBC::MBC2(mbc) => if mbc.ram_enabled {
+                let offset = offset % mbc.ram.len();
+                mbc.ram.write_banked(0, offset, data & 0x0F)
+            } else {
+                Ok(0)
+            },
+            MBC::MBC3(mbc) => match mbc.active_ram_bank {
+                _ if !mbc.ram_and_timer_enabled => Ok(0),
+                0x00..=0x07 => mbc.ram.write_banked(mbc.active_ram_bank, offset, data),
+                // Setting the time goes straight to the clock, but won't be seen until it's latched
+                register => {
+                    mbc.rtc.write_register(register, data);
+                    Ok(1)
+                },
+            },
+            MBC::MBC5(mbc) => if mbc.ram_enabled {
+                mbc.ram.write_banked(mbc.active_ram_bank, offset, data)
+            } else {
+                Ok(0)
+            },
+            // The registers are mirrored every 0x80 bytes. Setting bit 0 of register 0 takes a
+            // picture.
+            MBC::PocketCamera(mbc) => if mbc.registers_selected {
+                let register = offset & 0x7F;
+                if register < CAMERA_REGISTERS {
+                    mbc.registers[register] = data;
+                }
+
+                if register == 0 && data & 0x01 != 0 {
+                    mbc.capture();
+                    mbc.registers[0] &= !0x01;
+                }
+
+                Ok(1)
+            } else if mbc.ram_enabled {
+                let offset = mbc.ram_offset(offset);
+                mbc.ram.write_byte(offset, data)
+            } else {
+                Ok(0)
+            },
             MBC::RomOnly(_) => Ok(0),
         }
     }
@@ -390,6 +942,10 @@ impl MBC {
             MBC::MBC2(mbc) => mbc.ram.write_bytes(start, data),
             MBC::MBC3(mbc) => mbc.ram.write_bytes(start, data),
             MBC::MBC5(mbc) => mbc.ram.write_bytes(start, data),
+            MBC::PocketCamera(mbc) => {
+                let start = mbc.ram_offset(start);
+                mbc.ram.write_bytes(start, data)
+            },
             MBC::RomOnly(_) => Ok(0),
         }
     }
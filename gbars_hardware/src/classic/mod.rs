@@ -1,18 +1,50 @@
+pub mod apu;
 // cartridge depends on std::fs, std::io, and std::error
 #[cfg(feature = "std")] pub mod cartridge;
 pub mod cpu;
+pub mod error;
+#[cfg(feature = "ppu-fifo")] pub mod fifo;
+pub mod header;
 pub mod instruction;
+pub mod joypad;
 pub mod memory;
+pub mod ppu;
 pub mod registers;
+#[cfg(feature = "ppu-scanline")] pub mod scanline;
+pub mod serial;
+// save states are read and written through std::io
+#[cfg(feature = "std")] pub mod state;
+pub mod tile;
+pub mod timer;
 pub mod console;
+pub mod undo;
 pub(crate) mod utils;
 
 #[cfg(test)]
 mod test {
-    use super::cartridge::Cartridge;
-    use super::cpu::{Cpu, CpuState, OpRead, DataRead};
-    use super::memory::{MBC, ROM};
-    use crate::classic::console::Console;
+    use super::cartridge::{Cartridge, Destination, NINTENDO_GRAPHIC};
+    use super::cpu::{Cpu, CpuState, OpRead, DataRead, Interrupt};
+    use super::instruction::{Instruction, Arg, disassemble_at};
+    use super::memory::{MBC, RAM, FlatMemory, MemoryBus, MbcKind, supported_mbc_kinds, CAMERA_WIDTH, CAMERA_HEIGHT, CAMERA_IMAGE_START};
+    use super::error::GbError;
+    use crate::classic::console::{Console, ConsoleConfig, Model, FRAMES_PER_SECOND, Speed, is_unmapped_io, KEY1, INTERRUPT_FLAG, BGP, LCDC, STAT, LY, LYC, NR50, NR51, NR52};
+    use crate::classic::apu::{Apu, ApuRegisters, DEFAULT_SAMPLE_RATE, NR11};
+    use crate::classic::utils::CLOCK_SPEED;
+    use crate::classic::ppu::{Ppu, Vram, LcdRegisters, Layer, MonoShadeColors, MAX_SPRITES_PER_LINE, CYCLES_PER_FRAME, SCREEN_WIDTH, SCREEN_HEIGHT};
+    use crate::classic::joypad::{Button, ButtonSet, Socd};
+    use crate::classic::registers::{Registers, Reg16};
+    use crate::classic::serial::{Printer, PrintedImage, SerialLink};
+    use crate::classic::header::{LOGO_START, LOGO_END, TITLE_START, TITLE_END, CART_TYPE, ROM_SIZE, RAM_SIZE, DEST_CODE, HEADER_CHECKSUM, GLOBAL_CHECKSUM, HEADER_END};
+    use crate::classic::timer::{Timer, DIV, TIMA, TMA, TAC, DIV_PERIOD};
+    use crate::classic::tile::{decode_tile, encode_tile, TILE_SIZE};
+
+    /// 32 KiB of NOPs with the Nintendo logo in place and checksums that add up, so it boots
+    fn valid_rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[LOGO_START..LOGO_END].copy_from_slice(&NINTENDO_GRAPHIC);
+        Cartridge::fix_checksums(&mut rom);
+        rom
+    }
 
     #[test]
     fn cartridge_loads_and_parses_header_correctly() {
@@ -20,6 +52,7 @@ mod test {
 
         assert_eq!(cartridge.title, "POKEMON BLUE");
         assert_eq!(cartridge.rom_size, 1_048_576);
+        assert_eq!(cartridge.destination, Destination::Overseas);
     }
 
     #[test]
@@ -33,6 +66,12 @@ mod test {
         assert!(cartridge.is_valid());
     }
 
+    #[test]
+    fn cartridge_global_checksum_matches() {
+        let cartridge = Cartridge::load("src/test_roms/pokeblue.gbc").unwrap();
+        assert!(cartridge.verify_global_checksum());
+    }
+
     // #[test]
     // fn test_cpu() {
     //     let mut cpu = Cpu::init();
@@ -141,18 +180,7 @@ mod test {
             0xC2, 0x06, 0x00    // jp nz, loop
         ];
 
-        let cartridge = Cartridge {
-            title: "".to_string(),
-            mbc: MBC::RomOnly(ROM::new(program.clone())),
-            features: vec![],
-            rom_size: 0,
-            rom_banks: 0,
-            ram_size: 0,
-            ram_banks: 0,
-            locale: "".to_string(),
-            header_checksum: 0,
-            global_checksum: 0
-        };
+        let cartridge = Cartridge::new_blank(program.clone());
 
         let mut cpu = Cpu::init();
 
@@ -165,6 +193,2821 @@ mod test {
         assert_eq!(cpu.registers.a.0, 8);
     }
 
+    #[test]
+    fn run_until_stops_at_the_condition_or_gives_up() {
+        // The same multiplication as above, but letting the console drive
+        let program = vec![
+            0x3E, 0x02,         // ld A, $02
+            0x4F,               // ld C, A
+            0x06, 0x04,         // ld B, $04
+            0x05,               // dec B
+            // loop:
+            0x81,               // add C
+            0x05,               // dec B
+            0xC2, 0x06, 0x00    // jp nz, loop
+        ];
+
+        let mut console = Console::start(Some(Cartridge::new_blank(program.clone())));
+        let done = console.run_until(|c| c.cpu.registers().pc as usize == program.len(), 10_000);
+        assert_eq!(done, Ok(true));
+        assert_eq!(console.cpu.registers().a.0, 8);
+
+        // It should give up if the program never gets there
+        let mut console = Console::start(Some(Cartridge::new_blank(vec![
+            0x18, 0xFE,         // jr -2
+        ])));
+        assert_eq!(console.run_until(|c| c.cpu.registers().pc == 0x1234, 1_000), Ok(false));
+    }
+
+    #[test]
+    fn oam_scan_drops_sprites_past_the_line_limit() {
+        let mut oam = vec![0u8; 0xA0];
+
+        // 12 sprites whose tops are all on line 40
+        for i in 0..12 {
+            oam[i * 4] = 40 + 16;
+            oam[i * 4 + 1] = 8 + 8 * i as u8;
+        }
+
+        // ...and one more well out of the way on line 100
+        oam[12 * 4] = 100 + 16;
+        oam[12 * 4 + 1] = 8;
+
+        let mut ppu = Ppu::init();
+
+        let sprites = ppu.scan_oam(40, &oam, false);
+        assert_eq!(sprites.len(), MAX_SPRITES_PER_LINE);
+        assert_eq!(sprites.last().unwrap().oam_index, 9);
+        assert_eq!(ppu.sprites_dropped_on_line(40), 2);
+
+        // 8x8 sprites starting on line 40 cover lines 40 through 47
+        ppu.scan_oam(47, &oam, false);
+        assert_eq!(ppu.sprites_dropped_on_line(47), 2);
+        ppu.scan_oam(48, &oam, false);
+        assert_eq!(ppu.sprites_dropped_on_line(48), 0);
+
+        let sprites = ppu.scan_oam(100, &oam, false);
+        assert_eq!(sprites.len(), 1);
+        assert_eq!(ppu.sprites_dropped_on_line(100), 0);
+
+        assert_eq!(ppu.max_sprites_dropped(), 2);
+    }
+
+    #[test]
+    fn opposing_directions_follow_the_socd_policy() {
+        // (policy, expected low nibble of P1 with the direction row selected)
+        let cases = [
+            (Socd::Neutral, 0b1111),  // neither Left nor Right
+            (Socd::LastWins, 0b1110), // just Right
+            (Socd::Allow, 0b1100),    // both
+        ];
+
+        for &(policy, expected) in cases.iter() {
+            let mut console = Console::start(None);
+            console.set_socd(policy);
+
+            // Select the direction keys
+            console.write(0xFF00, 0x20);
+
+            console.press(Button::Left);
+            assert_eq!(console.read(0xFF00).unwrap() & 0x0F, 0b1101);
+
+            console.press(Button::Right);
+            assert_eq!(console.read(0xFF00).unwrap() & 0x0F, expected, "{:?}", policy);
+
+            // Letting go of Left always leaves just Right
+            console.release(Button::Left);
+            assert_eq!(console.read(0xFF00).unwrap() & 0x0F, 0b1110);
+        }
+    }
+
+    #[test]
+    fn joypad_rows_are_read_and_interrupt_separately() {
+        let mut console = Console::start(None);
+        let joypad_interrupt = |console: &Console| console.read(INTERRUPT_FLAG).unwrap() & 0x10 != 0;
+
+        // Select the action buttons
+        console.write(0xFF00, 0x10);
+        console.write(INTERRUPT_FLAG, 0);
+
+        console.press(Button::A);
+        assert_eq!(console.read(0xFF00).unwrap() & 0x0F, 0b1110);
+        assert!(joypad_interrupt(&console));
+
+        // Over on the direction keys, nothing's held
+        console.write(0xFF00, 0x20);
+        assert_eq!(console.read(0xFF00).unwrap() & 0x0F, 0b1111);
+
+        // Pressing a button in a row that isn't selected doesn't pull any line low...
+        console.write(INTERRUPT_FLAG, 0);
+        console.press(Button::B);
+        assert!(!joypad_interrupt(&console));
+
+        // ...until its row is selected
+        console.write(0xFF00, 0x10);
+        assert_eq!(console.read(0xFF00).unwrap() & 0x0F, 0b1100);
+        assert!(joypad_interrupt(&console));
+    }
+
+    #[test]
+    fn post_boot_state_depends_on_model() {
+        let dmg = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![]));
+        let cgb = Console::new_with_model(Model::Cgb, Cartridge::new_blank(vec![]));
+
+        assert_eq!(dmg.cpu.registers.a.0, 0x01);
+        assert_eq!(cgb.cpu.registers.a.0, 0x11);
+
+        for console in [dmg, cgb].iter() {
+            assert_eq!(console.cpu.registers.pc, 0x0100);
+            assert_eq!(console.cpu.registers.sp.0, 0xFFFE);
+            assert_eq!(console.read(0xFF40), Some(0x91)); // LCDC
+            assert_eq!(console.read(0xFF47), Some(0xFC)); // BGP
+        }
+    }
+
+    #[test]
+    fn cpu_runs_against_flat_memory() {
+        let mut rom = vec![0u8; 0x8000];
+
+        // Entry point
+        rom[0x100..0x104].copy_from_slice(&[
+            0x00,               // nop
+            0xC3, 0x50, 0x01,   // jp $0150
+        ]);
+
+        // Adds up 10 + 9 + ... + 1 and stores the result in work RAM
+        rom[0x150..0x15E].copy_from_slice(&[
+            0x3E, 0x00,         // ld A, $00
+            0x06, 0x0A,         // ld B, $0A
+            // loop:
+            0x80,               // add B
+            0x05,               // dec B
+            0xC2, 0x54, 0x01,   // jp nz, loop
+            0xEA, 0x00, 0xC0,   // ld ($C000), A
+            // done:
+            0x18, 0xFE,         // jr done
+        ]);
+
+        let mut memory = FlatMemory::from_program(&rom);
+        let mut cpu = Cpu::init();
+        cpu.registers.pc = 0x100;
+
+        let mut steps = 0;
+        while cpu.registers.pc != 0x15C || cpu.state == CpuState::Exec {
+            cpu.step(&mut memory).unwrap();
+
+            steps += 1;
+            assert!(steps < 1000, "program never finished");
+        }
+
+        assert_eq!(memory.read(0xC000), Some(55));
+    }
+
+    #[test]
+    fn undoing_instructions_restores_registers_and_memory() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x113].copy_from_slice(&[
+            0x3E, 0x05,         // ld A, $05
+            0xEA, 0x00, 0xC0,   // ld ($C000), A
+            0x31, 0x00, 0xD0,   // ld SP, $D000
+            0x21, 0x10, 0xC0,   // ld HL, $C010
+            0x3C,               // inc A
+            0x22,               // ld (HL+), A
+            0xE5,               // push HL
+            0x3C,               // inc A
+            0xEA, 0x00, 0xC0,   // ld ($C000), A
+            0x77,               // ld (HL), A
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+
+        for _ in 0..5 {
+            console.step_instruction().unwrap();
+        }
+        let registers = console.cpu.registers;
+        let wram = console.bus.wram.clone();
+
+        for _ in 0..5 {
+            console.step_instruction().unwrap();
+        }
+        assert_ne!(console.bus.wram, wram);
+
+        for _ in 0..5 {
+            assert!(console.undo_instruction());
+        }
+        assert_eq!(console.cpu.registers, registers);
+        assert_eq!(console.bus.wram, wram);
+        assert_eq!(console.read(0xC000), Some(0x05));
+    }
+
+    #[test]
+    fn undoing_instructions_winds_back_the_timer_and_ppu() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[
+            0x18, 0xFE,         // jr -2
+        ]);
+        rom[0x200..0x202].copy_from_slice(&[
+            0x10, 0x00,         // stop
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        console.write(0xFF07, 0x05); // TIMA counts every 16 cycles
+        console.run_until(|c| c.read(LY) == Some(143), 100_000).unwrap();
+        console.write(INTERRUPT_FLAG, 0x00);
+
+        let registers = [INTERRUPT_FLAG, LY, STAT, 0xFF04, 0xFF05];
+        let before: Vec<_> = registers.iter().map(|&r| console.read(r)).collect();
+
+        // Far enough to go into VBlank, with the timer ticking away
+        for _ in 0..500 {
+            console.step_instruction().unwrap();
+        }
+        assert_ne!(console.read(INTERRUPT_FLAG).unwrap() & 0x01, 0);
+
+        for _ in 0..500 {
+            assert!(console.undo_instruction());
+        }
+        let after: Vec<_> = registers.iter().map(|&r| console.read(r)).collect();
+        assert_eq!(after, before);
+
+        // Undoing a stop gets the CPU going again
+        console.cpu.registers.pc = 0x200;
+        console.step_instruction().unwrap();
+        assert!(console.cpu.is_stopped());
+        assert!(console.undo_instruction());
+        assert!(!console.cpu.is_stopped());
+        assert_eq!(console.cpu.registers.pc, 0x200);
+    }
+
+    #[test]
+    fn sixteen_bit_inc_and_dec_leave_flags_alone() {
+        let mut memory = FlatMemory::from_program(&[
+            0x03,   // inc BC
+            0x0B,   // dec BC
+        ]);
+        let mut cpu = Cpu::init();
+        cpu.registers.set_bc(0xFFFF);
+        cpu.registers.f.0 = 0xF0;
+
+        // Unlike the 8-bit version, this shouldn't set Z even though BC wraps around to 0
+        cpu.step(&mut memory).unwrap();
+        cpu.step(&mut memory).unwrap();
+        assert_eq!(cpu.registers.get_bc(), 0x0000);
+        assert_eq!(cpu.registers.f.0, 0xF0);
+
+        cpu.registers.f.0 = 0x00;
+        cpu.step(&mut memory).unwrap();
+        cpu.step(&mut memory).unwrap();
+        assert_eq!(cpu.registers.get_bc(), 0xFFFF);
+        assert_eq!(cpu.registers.f.0, 0x00);
+    }
+
+    #[test]
+    fn screen_is_available_after_a_frame() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[
+            0x18, 0xFE,         // jr -2
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        console.run_frame().unwrap();
+
+        assert_eq!(console.screen().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(console.screen_rgb(&MonoShadeColors::GREY).len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+    }
+
+    #[test]
+    fn brightness_adjusts_rgb_output_only() {
+        let mut console = Console::start(None);
+        let shade = console.screen()[0];
+        let unadjusted = MonoShadeColors::GREEN.rgb(shade);
+
+        let rgb = console.screen_rgb(&MonoShadeColors::GREEN);
+        assert_eq!(&rgb[..3], &unadjusted);
+
+        console.set_brightness(0.0);
+        assert!(console.screen_rgb(&MonoShadeColors::GREEN).iter().all(|&c| c == 0));
+        assert_eq!(console.screen()[0], shade);
+
+        // Out of range values get clamped
+        console.set_brightness(-3.0);
+        assert_eq!(console.brightness(), 0.0);
+        console.set_contrast(10.0);
+        assert_eq!(console.contrast(), 2.0);
+
+        console.set_brightness(1.0);
+        console.set_contrast(1.0);
+        assert_eq!(&console.screen_rgb(&MonoShadeColors::GREEN)[..3], &unadjusted);
+    }
+
+    #[test]
+    fn stop_skips_its_second_byte_and_stops_the_cpu() {
+        let mut memory = FlatMemory::from_program(&[
+            0x10, 0x00, // stop
+            0x3C,       // inc A
+        ]);
+        let mut cpu = Cpu::init();
+
+        // OpRead, DataRead, Exec
+        for _ in 0..3 {
+            cpu.step(&mut memory).unwrap();
+        }
+        assert_eq!(cpu.registers.pc, 2);
+        assert!(cpu.is_stopped());
+
+        // The CPU shouldn't go any further
+        for _ in 0..3 {
+            cpu.step(&mut memory).unwrap();
+        }
+        assert_eq!(cpu.registers.pc, 2);
+        assert_eq!(cpu.registers.a.0, 0);
+    }
+
+    #[test]
+    fn tiles_survive_a_decode_encode_round_trip() {
+        // A quick and dirty xorshift so the tiles are "random" but the same every run
+        let mut seed = 0x2545_F491u32;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        };
+
+        for _ in 0..16 {
+            let mut tile = [0u8; TILE_SIZE];
+            tile.iter_mut().for_each(|b| *b = next());
+
+            assert_eq!(encode_tile(&decode_tile(&tile)), tile);
+        }
+
+        // The top row of this tile is colors 0, 1, 2, 3, 0, 1, 2, 3
+        let mut tile = [0u8; TILE_SIZE];
+        tile[0] = 0b0101_0101;
+        tile[1] = 0b0011_0011;
+        assert_eq!(decode_tile(&tile)[0], [0, 1, 2, 3, 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn unmapped_io_reads_as_open_bus() {
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![0; 0x8000]));
+
+        assert_eq!(console.read(0xFF03), Some(0xFF));
+        console.write(0xFF03, 0x12);
+        assert_eq!(console.read(0xFF03), Some(0xFF));
+        console.write(0xFF0A, 0x00);
+        assert_eq!(console.read(0xFF0A), Some(0xFF));
+
+        // Mapped registers still hold onto what's written
+        console.write(0xFF06, 0x12);
+        assert_eq!(console.read(0xFF06), Some(0x12));
+    }
+
+    #[test]
+    fn sbc_borrows_through_a_full_byte() {
+        let mut registers = Registers::init();
+
+        // 0x00 - 0xFF - 1 = -0x100, which wraps all the way around to 0
+        registers.a.0 = 0x00;
+        registers.set_flags(Some(false), Some(false), Some(false), Some(true));
+        registers.sbc(0xFF);
+        assert_eq!(registers.a.0, 0x00);
+        assert!(registers.zero() && registers.neg() && registers.half_carry() && registers.carry());
+
+        // 0xFF - 0xFF - 1 = -1
+        registers.a.0 = 0xFF;
+        registers.set_flags(Some(false), Some(false), Some(false), Some(true));
+        registers.sbc(0xFF);
+        assert_eq!(registers.a.0, 0xFF);
+        assert!(!registers.zero() && registers.half_carry() && registers.carry());
+
+        // 0xFF - 0xFE - 1 = 0, with no borrows at all
+        registers.a.0 = 0xFF;
+        registers.set_flags(Some(false), Some(false), Some(false), Some(true));
+        registers.sbc(0xFE);
+        assert_eq!(registers.a.0, 0x00);
+        assert!(registers.zero() && !registers.half_carry() && !registers.carry());
+    }
+
+    #[test]
+    fn unsupported_mbcs_are_reported() {
+        assert!(supported_mbc_kinds().iter().all(|kind| kind.is_supported()));
+        assert!(MbcKind::Mbc1.is_supported());
+        assert!(!MbcKind::Mbc6.is_supported());
+
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x20; // MBC6
+
+        match Console::from_rom_bytes(rom) {
+            Err(e) => assert_eq!(e, GbError::UnsupportedMbc(MbcKind::Mbc6)),
+            Ok(_) => panic!("MBC6 cartridge was accepted"),
+        }
+
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x01; // MBC1
+
+        let console = Console::from_rom_bytes_lenient(rom, |_| {}).unwrap();
+        assert_eq!(console.bus.cartridge.unwrap().mbc.kind(), MbcKind::Mbc1);
+    }
+
+    #[test]
+    fn lyc_interrupt_fires_once_per_frame() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[
+            0x18, 0xFE,         // jr -2
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        console.write(LYC, 72);
+        console.alter(STAT, |stat| stat | 0x40);
+
+        let mut lines = vec![];
+        let end = console.cpu.cycles + CYCLES_PER_FRAME;
+        while console.cpu.cycles < end {
+            console.step_instruction().unwrap();
+
+            if console.read(INTERRUPT_FLAG).unwrap() & 0x02 != 0 {
+                lines.push(console.read(LY).unwrap());
+                console.alter(INTERRUPT_FLAG, |f| f & !0x02);
+            }
+        }
+
+        assert_eq!(lines, vec![72]);
+    }
+
+    /// Draws every line of a checkerboard-ish background with `render_line` and compares it
+    /// against the tiles decoded by hand
+    fn check_background_renderer(render_line: fn(u8, &Vram, &LcdRegisters, &mut [u8])) {
+        let mut chr_ram = vec![0u8; 0x1800];
+        for (i, b) in chr_ram[16..48].iter_mut().enumerate() {
+            *b = (i as u8).wrapping_mul(37) ^ 0x5A;
+        }
+
+        let mut bg_data = vec![0u8; 0x800];
+        for (i, tile) in bg_data[..0x400].iter_mut().enumerate() {
+            *tile = ((i % 32 + i / 32) % 3) as u8;
+        }
+
+        let vram = Vram { chr_ram: &chr_ram, bg_data: &bg_data };
+        let registers = LcdRegisters { lcdc: 0x91, scy: 5, scx: 3, lyc: 0, bgp: 0xE4, obp0: 0, obp1: 0 };
+
+        let mut line = [0u8; SCREEN_WIDTH];
+        for ly in 0..SCREEN_HEIGHT as u8 {
+            render_line(ly, &vram, &registers, &mut line);
+
+            for (x, &pixel) in line.iter().enumerate() {
+                let (bx, by) = (x + 3, ly as usize + 5);
+                let tile = bg_data[(by / 8) * 32 + bx / 8] as usize;
+                let mut data = [0u8; TILE_SIZE];
+                data.copy_from_slice(&chr_ram[tile * 16..tile * 16 + 16]);
+
+                // BGP = 0xE4 maps each color to the shade with the same number
+                assert_eq!(pixel, decode_tile(&data)[by % 8][bx % 8], "at ({}, {})", x, ly);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ppu-scanline")]
+    fn scanline_renderer_draws_the_background() {
+        check_background_renderer(crate::classic::scanline::render_line);
+    }
+
+    #[test]
+    #[cfg(feature = "ppu-fifo")]
+    fn fifo_renderer_draws_the_background() {
+        check_background_renderer(crate::classic::fifo::render_line);
+    }
+
+    #[test]
+    #[cfg(feature = "ppu-scanline")]
+    fn scanline_renderer_follows_the_tile_data_area() {
+        use crate::classic::scanline::render_line;
+
+        // Tile 0 from 0x8000 is solid color 1, tile 0 from 0x9000 is solid color 2, and tile 0x80
+        // (from 0x8800, either way) is solid color 3
+        let mut chr_ram = vec![0u8; 0x1800];
+        for row in 0..8 {
+            chr_ram[row * 2] = 0xFF;
+            chr_ram[0x1000 + row * 2 + 1] = 0xFF;
+            chr_ram[0x0800 + row * 2] = 0xFF;
+            chr_ram[0x0800 + row * 2 + 1] = 0xFF;
+        }
+
+        let draw = |tile: u8, lcdc: u8| {
+            let bg_data = vec![tile; 0x800];
+            let vram = Vram { chr_ram: &chr_ram, bg_data: &bg_data };
+            let registers = LcdRegisters { lcdc, scy: 3, scx: 5, lyc: 0, bgp: 0xE4, obp0: 0, obp1: 0 };
+
+            let mut line = [0xFFu8; SCREEN_WIDTH];
+            render_line(10, &vram, &registers, &mut line);
+            line
+        };
+
+        assert_eq!(draw(0x00, 0x91), [1; SCREEN_WIDTH]);
+        assert_eq!(draw(0x00, 0x81), [2; SCREEN_WIDTH]);
+        assert_eq!(draw(0x80, 0x91), [3; SCREEN_WIDTH]);
+        assert_eq!(draw(0x80, 0x81), [3; SCREEN_WIDTH]);
+    }
+
+    #[test]
+    fn serial_transfers_shift_out_a_byte_and_interrupt() {
+        use std::{cell::RefCell, rc::Rc};
+
+        // Nothing but nops
+        let mut console = Console::start(Some(Cartridge::new_blank(vec![0; 0x2000])));
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        {
+            let sent = sent.clone();
+            console.on_serial_out(move |byte| sent.borrow_mut().push(byte));
+        }
+
+        console.write(0xFF01, 0x42);
+        console.write(0xFF02, 0x81);
+
+        // It takes 8 bit-times to go out
+        console.run_until(|_| false, 4000).unwrap();
+        assert!(sent.borrow().is_empty());
+        assert_eq!(console.read(0xFF02).unwrap() & 0x80, 0x80);
+
+        console.run_until(|_| false, 100).unwrap();
+        assert_eq!(*sent.borrow(), vec![0x42]);
+
+        // With nothing plugged in, what comes back is all 1s
+        assert_eq!(console.read(0xFF01), Some(0xFF));
+        assert_eq!(console.read(0xFF02).unwrap() & 0x80, 0);
+        assert_ne!(console.read(INTERRUPT_FLAG).unwrap() & 0x08, 0);
+    }
+
+    #[test]
+    fn printer_prints_what_it_was_sent() {
+        use std::{cell::RefCell, rc::Rc};
+
+        fn send_packet(printer: &mut impl SerialLink, command: u8, data: &[u8]) -> u8 {
+            let mut body = vec![command, 0x00, data.len() as u8, (data.len() >> 8) as u8];
+            body.extend_from_slice(data);
+            let checksum = body.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+
+            printer.exchange(0x88);
+            printer.exchange(0x33);
+            for &b in body.iter() {
+                printer.exchange(b);
+            }
+            printer.exchange(checksum as u8);
+            printer.exchange((checksum >> 8) as u8);
+
+            assert_eq!(printer.exchange(0x00), 0x81);
+            printer.exchange(0x00)
+        }
+
+        let printed: Rc<RefCell<Option<PrintedImage>>> = Rc::new(RefCell::new(None));
+        let mut printer = {
+            let printed = printed.clone();
+            Printer::new(move |image: &PrintedImage| *printed.borrow_mut() = Some(image.clone()))
+        };
+
+        // 2 rows of 20 tiles, all blank except the very first pixel, which is color 3
+        let mut tiles = vec![0u8; 640];
+        tiles[0] = 0x80;
+        tiles[1] = 0x80;
+
+        assert_eq!(send_packet(&mut printer, 0x01, &[]), 0x00);
+        assert_eq!(send_packet(&mut printer, 0x04, &tiles), 0x08);
+        send_packet(&mut printer, 0x04, &[]);
+
+        // Palette 0x1B flips the shades around
+        assert_eq!(send_packet(&mut printer, 0x02, &[0x01, 0x00, 0x1B, 0x40]), 0x00);
+
+        let image = printed.borrow_mut().take().expect("nothing was printed");
+        assert_eq!((image.width, image.height), (160, 16));
+        assert_eq!(image.pixels[0], 0);
+        assert_eq!(image.pixels[1], 3);
+    }
+
+    #[test]
+    fn cartridge_ram_is_allocated_despite_a_zero_size_header() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x03; // MBC1 + RAM + Battery
+        rom[0x149] = 0x00; // ...but no RAM
+
+        let mut warnings = vec![];
+        let mut cartridge = Cartridge::from_bytes_with_warnings(rom, |w| warnings.push(w.to_string()))
+            .unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(cartridge.ram_size, 0x2000);
+        cartridge.mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(cartridge.mbc.write_ram(0x10, 0x42), Ok(1));
+        assert_eq!(cartridge.mbc.read_ram(0x10), Some(0x42));
+    }
+
+    #[test]
+    fn rom_and_ram_carts_keep_their_header_ram_size() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x08; // ROM + RAM
+        rom[0x149] = 0x02; // 8 KiB
+
+        let mut warnings = vec![];
+        let cartridge = Cartridge::from_bytes_with_warnings(rom, |w| warnings.push(w.to_string()))
+            .unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(cartridge.ram_size, 0x2000);
+        assert_eq!(cartridge.ram_banks, 1);
+
+        // The number of banks still comes from the size when the MBC has its own say
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x06; // MBC2 + Battery, with 512 half-bytes built in
+        let cartridge = Cartridge::from_bytes_with_warnings(rom, |_| {}).unwrap();
+        assert_eq!(cartridge.ram_size, 0x200);
+        assert_eq!(cartridge.ram_banks, 1);
+    }
+
+    #[test]
+    fn cycle_counter_adds_up_instruction_costs() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x10A].copy_from_slice(&[
+            0x00,               // nop              4
+            0x3E, 0x05,         // ld A, $05        8
+            0x47,               // ld B, A          4
+            0xB7,               // or A             4
+            0x20, 0x00,         // jr nz, +0        12 (taken)
+            0xEA, 0x00, 0xC0,   // ld ($C000), A    16
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        assert_eq!(console.cycles(), 0);
+
+        for _ in 0..6 {
+            console.step_instruction().unwrap();
+        }
+        assert_eq!(console.cycles(), 4 + 8 + 4 + 4 + 12 + 16);
+
+        // Going back in time doesn't turn back the clock
+        console.undo_instruction();
+        assert_eq!(console.cycles(), 48);
+    }
+
+    #[test]
+    fn ly_cannot_be_written() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[
+            0x18, 0xFE,         // jr -2
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        console.run_until(|c| c.read(LY) == Some(3), 10_000).unwrap();
+
+        console.write(LY, 0x50);
+        assert_eq!(console.read(LY), Some(3));
+        assert_eq!(console.ppu.ly(), 3);
+    }
+
+    #[test]
+    fn stop_switches_speed_when_prepared() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[
+            0x10, 0x00,         // stop
+            0x00,               // nop
+        ]);
+
+        let mut console = Console::new_with_model(Model::Cgb, Cartridge::new_blank(rom));
+        assert_eq!(console.key1().current_speed(), Speed::Normal);
+
+        console.write(KEY1, 0x01);
+        assert!(console.key1().prepare_switch());
+
+        console.step_instruction().unwrap();
+        assert_eq!(console.key1().current_speed(), Speed::Double);
+        assert!(!console.key1().prepare_switch());
+        assert!(!console.cpu.is_stopped());
+        assert_eq!(console.read(KEY1), Some(0xFE));
+
+        // There's no KEY1 on the DMG, so stop really stops
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x10, 0x00]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        console.write(KEY1, 0x01);
+        assert_eq!(console.read(KEY1), Some(0xFF));
+        console.step_instruction().unwrap();
+        assert!(console.cpu.is_stopped());
+    }
+
+    #[test]
+    fn fixed_checksums_validate() {
+        let mut rom = valid_rom();
+        rom[0x134..0x13A].copy_from_slice(b"PATCHD");
+        rom[0x14D] = 0x12; // wrong
+
+        assert!(!Cartridge::from_bytes(rom.clone()).unwrap().is_valid());
+
+        Cartridge::fix_checksums(&mut rom);
+        let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        cartridge.validate().unwrap();
+
+        let sum = rom.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16))
+            .wrapping_sub(rom[0x14E] as u16)
+            .wrapping_sub(rom[0x14F] as u16);
+        assert_eq!(cartridge.global_checksum, sum);
+    }
+
+    #[test]
+    fn step_state_shows_each_transition() {
+        let mut memory = FlatMemory::from_program(&[
+            0x01, 0x34, 0x12,   // ld BC, $1234
+        ]);
+        let mut cpu = Cpu::init();
+        assert_eq!(cpu.state(), &CpuState::OpRead(OpRead::General));
+
+        assert_eq!(cpu.step_state(&mut memory), Ok(&CpuState::DataRead(DataRead::ShortLo)));
+        assert_eq!(cpu.step_state(&mut memory), Ok(&CpuState::DataRead(DataRead::ShortHi)));
+        assert_eq!(cpu.step_state(&mut memory), Ok(&CpuState::Exec));
+        assert_eq!(cpu.step_state(&mut memory), Ok(&CpuState::OpRead(OpRead::General)));
+        assert_eq!(cpu.registers().get_bc(), 0x1234);
+    }
+
+    #[test]
+    fn sixteen_bit_immediates_are_little_endian() {
+        let mut memory = FlatMemory::from_program(&[
+            0x21, 0x34, 0x12,   // ld HL, $1234
+        ]);
+        let mut cpu = Cpu::init();
+
+        for _ in 0..4 {
+            cpu.step(&mut memory).unwrap();
+        }
+        assert_eq!(cpu.registers().get_hl(), 0x1234);
+
+        let mut memory = FlatMemory::from_program(&[
+            0xC3, 0x50, 0xC3,   // jp $C350
+        ]);
+        let mut cpu = Cpu::init();
+
+        for _ in 0..4 {
+            cpu.step(&mut memory).unwrap();
+        }
+        assert_eq!(cpu.registers().pc, 0xC350);
+    }
+
+    #[test]
+    fn frame_skip_runs_extra_frames_without_drawing_them() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[
+            0x18, 0xFE,         // jr -2
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        console.set_frame_skip(2);
+        console.run_frame().unwrap();
+
+        assert!(console.cycles() >= 3 * CYCLES_PER_FRAME);
+        assert!(console.cycles() < 3 * CYCLES_PER_FRAME + 16);
+        assert_eq!(console.ppu.frames_drawn(), 1);
+    }
+
+    #[test]
+    fn frames_carry_their_overrun_into_the_next() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[
+            0xC3, 0x00, 0x01,   // jp $0100 (16 cycles, which doesn't divide a frame evenly)
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+
+        let start = console.cycles();
+        assert_eq!(console.run_frame().unwrap().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        let first = console.cycles() - start;
+        console.run_frame().unwrap();
+        let second = console.cycles() - start - first;
+
+        for &frame in [first, second].iter() {
+            assert!(frame + 16 > CYCLES_PER_FRAME && frame < CYCLES_PER_FRAME + 16, "{}", frame);
+        }
+
+        // However many frames go by, they never get more than an instruction out
+        for _ in 0..100 {
+            console.run_frame().unwrap();
+        }
+        assert!(console.cycles() - start - 102 * CYCLES_PER_FRAME < 16);
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn frames_are_traced_as_spans() {
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex};
+        use tracing::{Event, Metadata, Subscriber};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+
+        #[derive(Default)]
+        struct Fields(HashMap<&'static str, u64>);
+
+        impl Visit for Fields {
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                self.0.insert(field.name(), value);
+            }
+
+            fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+        }
+
+        // Keeps the fields of every span it's given, in order, so they can be checked afterwards
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<(&'static str, Fields)>>>);
+
+        impl Subscriber for Capture {
+            fn enabled(&self, _: &Metadata) -> bool { true }
+
+            fn new_span(&self, span: &Attributes) -> Id {
+                let mut fields = Fields::default();
+                span.record(&mut fields);
+
+                let mut spans = self.0.lock().unwrap();
+                spans.push((span.metadata().name(), fields));
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, span: &Id, values: &Record) {
+                values.record(&mut self.0.lock().unwrap()[span.into_u64() as usize - 1].1);
+            }
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[
+            0xC3, 0x00, 0x01,   // jp $0100 (16 cycles)
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        let capture = Capture::default();
+
+        let mut expected = Vec::new();
+        tracing::subscriber::with_default(capture.clone(), || {
+            for _ in 0..2 {
+                let (frame, start) = (console.frame_count(), console.cycles());
+                console.run_frame().unwrap();
+                expected.push((frame, console.cycles() - start));
+            }
+        });
+
+        let spans = capture.0.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        for ((name, fields), &(frame, cycles)) in spans.iter().zip(expected.iter()) {
+            assert_eq!(*name, "frame");
+            assert_eq!(fields.0["frame"], frame);
+            assert_eq!(fields.0["cycles"], cycles);
+            assert_eq!(fields.0["instructions"], cycles / 16);
+        }
+    }
+
+    #[test]
+    fn blank_cartridges_run_their_program() {
+        let mut console = Console::start(Some(Cartridge::new_blank(vec![
+            0x3E, 0x2A,         // ld A, $2A
+            0x47,               // ld B, A
+        ])));
+
+        console.step_instruction().unwrap();
+        console.step_instruction().unwrap();
+        assert_eq!(console.cpu.registers().b.0, 0x2A);
+    }
+
+    #[test]
+    fn oversized_roms_are_rejected() {
+        let rom = vec![0u8; 16 * 1024 * 1024];
+
+        match Cartridge::from_bytes(rom.clone()) {
+            Err(e) => assert_eq!(e, GbError::RomTooLarge(16 * 1024 * 1024)),
+            Ok(_) => panic!("16 MiB ROM was accepted"),
+        }
+
+        assert!(Cartridge::from_bytes_with_max_size(rom, 32 * 1024 * 1024).is_ok());
+    }
+
+    #[test]
+    fn shifts_carry_out_the_bit_they_shift_off() {
+        // Each case is the second byte of the prefixed instruction, the value in B going in, and
+        // what B and F should be coming out
+        let cases = [
+            (0x20, 0x80, 0x00, 0x90),   // sla B
+            (0x20, 0x41, 0x82, 0x00),   // sla B
+            (0x28, 0xFF, 0xFF, 0x10),   // sra B
+            (0x28, 0x82, 0xC1, 0x00),   // sra B
+            (0x28, 0x81, 0xC0, 0x10),   // sra B
+            (0x28, 0x02, 0x01, 0x00),   // sra B
+            (0x28, 0x01, 0x00, 0x90),   // sra B
+            (0x38, 0x01, 0x00, 0x90),   // srl B
+            (0x38, 0x82, 0x41, 0x00),   // srl B
+        ];
+
+        for &(op, input, output, flags) in cases.iter() {
+            let mut memory = FlatMemory::from_program(&[0xCB, op]);
+            let mut cpu = Cpu::init();
+            cpu.registers.b.0 = input;
+            cpu.registers.f.0 = 0x00;
+
+            for _ in 0..3 {
+                cpu.step(&mut memory).unwrap();
+            }
+            assert_eq!(cpu.registers().b.0, output, "CB {:02X} on {:02X}", op, input);
+            assert_eq!(cpu.registers().f.0, flags, "CB {:02X} on {:02X}", op, input);
+        }
+    }
+
+    #[test]
+    fn prefixed_instructions_write_back_their_results() {
+        // Runs a prefixed instruction with the given register values (B, C, A, F) and hands back
+        // what they are afterwards
+        fn run(op: u8, (b, c, a, f): (u8, u8, u8, u8)) -> (u8, u8, u8, u8) {
+            let mut memory = FlatMemory::from_program(&[0xCB, op]);
+            let mut cpu = Cpu::init();
+            cpu.registers.b.0 = b;
+            cpu.registers.c.0 = c;
+            cpu.registers.a.0 = a;
+            cpu.registers.f.0 = f;
+
+            for _ in 0..3 {
+                cpu.step(&mut memory).unwrap();
+            }
+
+            let registers = cpu.registers();
+            (registers.b.0, registers.c.0, registers.a.0, registers.f.0)
+        }
+
+        // swap B
+        assert_eq!(run(0x30, (0x1F, 0, 0, 0x70)), (0xF1, 0, 0, 0x00));
+        assert_eq!(run(0x30, (0x00, 0, 0, 0x00)), (0x00, 0, 0, 0x80));
+
+        // bit 7, A sets Z when the bit is clear, keeps C, and leaves A alone
+        assert_eq!(run(0x7F, (0, 0, 0x7F, 0x10)), (0, 0, 0x7F, 0xB0));
+        assert_eq!(run(0x7F, (0, 0, 0x80, 0x00)), (0, 0, 0x80, 0x20));
+
+        // res 0, C leaves the flags alone
+        assert_eq!(run(0x81, (0, 0xFF, 0, 0xF0)), (0, 0xFE, 0, 0xF0));
+
+        // sla A moves bit 7 into the carry
+        assert_eq!(run(0x27, (0, 0, 0x81, 0x00)), (0, 0, 0x02, 0x10));
+        assert_eq!(run(0x27, (0, 0, 0x40, 0x10)), (0, 0, 0x80, 0x00));
+    }
+
+    #[test]
+    fn layers_can_have_their_own_palettes() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[
+            0x18, 0xFE,         // jr -2
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+
+        // Tile 1 is solid color 3, and the background is all tile 0 (color 0)
+        for i in 0..16 {
+            console.write(0x8010 + i, 0xFF);
+        }
+
+        // One sprite using OBP0 in the top left corner, and one using OBP1 next to it
+        for (i, &b) in [16, 8, 1, 0x00, 16, 16, 1, 0x10].iter().enumerate() {
+            console.write(0xFE00 + i, b);
+        }
+        console.write(0xFF40, 0x93); // LCDC: turn the sprites on
+        console.write(0xFF47, 0xE4); // BGP
+        console.write(0xFF48, 0xE4); // OBP0
+        console.write(0xFF49, 0xE4); // OBP1
+
+        let red = [[0xFF, 0, 0], [0xAA, 0, 0], [0x55, 0, 0], [0x11, 0, 0]];
+        let blue = [[0, 0, 0xFF], [0, 0, 0xAA], [0, 0, 0x55], [0, 0, 0x11]];
+        console.set_layer_palette(Layer::Obj0, red);
+        console.set_layer_palette(Layer::Obj1, blue);
+        console.run_frame().unwrap();
+
+        let rgb = console.screen_rgb(&MonoShadeColors::GREY);
+        let pixel = |x: usize, y: usize| &rgb[(y * SCREEN_WIDTH + x) * 3..(y * SCREEN_WIDTH + x) * 3 + 3];
+
+        assert_eq!(pixel(0, 0), &red[3]);
+        assert_eq!(pixel(7, 7), &red[3]);
+        assert_eq!(pixel(8, 0), &blue[3]);
+        assert_eq!(pixel(20, 0), &MonoShadeColors::GREY.0[0]);
+        assert_eq!(pixel(0, 8), &MonoShadeColors::GREY.0[0]);
+        assert_eq!(console.ppu.layers()[0], Layer::Obj0);
+        assert_eq!(console.ppu.layers()[20], Layer::Background);
+
+        // Without their own palettes, sprites are drawn just like everything else
+        console.clear_layer_palette(Layer::Obj0);
+        console.clear_layer_palette(Layer::Obj1);
+        let rgb = console.screen_rgb(&MonoShadeColors::GREY);
+        assert_eq!(&rgb[..3], &MonoShadeColors::GREY.0[3]);
+    }
+
+    #[test]
+    fn camera_captures_the_injected_frame() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0xFC; // GameBoy Camera
+        rom[0x149] = 0x04; // 128 KiB of RAM
+
+        let mut console = Console::from_rom_bytes_lenient(rom, |_| {}).unwrap();
+        assert!(Console::start(Some(Cartridge::new_blank(vec![]))).set_camera_frame(&[0; CAMERA_WIDTH * CAMERA_HEIGHT]).is_none());
+
+        // Left half black, right half white
+        let mut frame = [0xFFu8; CAMERA_WIDTH * CAMERA_HEIGHT];
+        for row in frame.chunks_mut(CAMERA_WIDTH) {
+            row[..CAMERA_WIDTH / 2].iter_mut().for_each(|p| *p = 0);
+        }
+        console.set_camera_frame(&frame).unwrap();
+
+        // Select the registers, set every threshold in the dithering matrix, and take a picture
+        console.write(0x4000, 0x10);
+        for register in 0x06..0x36 {
+            console.write(0xA000 + register, 0x80);
+        }
+        console.write(0xA000, 0x01);
+        assert_eq!(console.read(0xA000), Some(0x00));
+
+        // The picture lands in RAM bank 0 as tiles: the first tile is all black, and the last
+        // tile on the top row is all white
+        console.write(0x4000, 0x00);
+        let tile = |i: usize| -> [u8; 16] {
+            let mut data = [0; 16];
+            for (j, b) in data.iter_mut().enumerate() {
+                *b = console.read(0xA000 + CAMERA_IMAGE_START + i * 16 + j).unwrap();
+            }
+            data
+        };
+
+        assert_eq!(decode_tile(&tile(0)), [[3; 8]; 8]);
+        assert_eq!(decode_tile(&tile(CAMERA_WIDTH / 8 - 1)), [[0; 8]; 8]);
+    }
+
+    #[test]
+    fn oam_overlay_outlines_sprites() {
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![0; 0x8000]));
+
+        // A sprite with its top left corner at (20, 30), and one that's entirely off screen
+        for (i, &b) in [46, 28, 0, 0, 0, 0, 0, 0].iter().enumerate() {
+            console.write(0xFE00 + i, b);
+        }
+
+        let red = [0xFF, 0, 0];
+        let rgb = console.screen_rgb_with_oam_overlay(&MonoShadeColors::GREY, red);
+        let outlined = |x: usize, y: usize| rgb[(y * SCREEN_WIDTH + x) * 3..(y * SCREEN_WIDTH + x) * 3 + 3] == red;
+
+        for x in 20..28 {
+            assert!(outlined(x, 30) && outlined(x, 37));
+        }
+        for y in 30..38 {
+            assert!(outlined(20, y) && outlined(27, y));
+        }
+        assert!(!outlined(21, 31) && !outlined(19, 30) && !outlined(20, 38));
+
+        let boxed = rgb.chunks(3).filter(|&pixel| pixel == red).count();
+        assert_eq!(boxed, 28);
+    }
+
+    #[test]
+    fn powering_off_the_apu_clears_and_locks_its_registers() {
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![0; 0x8000]));
+
+        // Set up channel 1 and the wave RAM
+        console.write(0xFF12, 0xF3);
+        console.write(0xFF13, 0x42);
+        console.write(0xFF30, 0x12);
+
+        console.write(NR52, 0x00);
+        assert_eq!(console.read(NR52), Some(0x70));
+        for register in 0xFF10..=0xFF25 {
+            if !is_unmapped_io(register, Model::Dmg) {
+                assert_eq!(console.read(register), Some(0x00), "{:04X}", register);
+            }
+        }
+        assert_eq!(console.read(0xFF30), Some(0x12));
+
+        // Writes don't stick, apart from the length counters
+        console.write(0xFF12, 0xF3);
+        assert_eq!(console.read(0xFF12), Some(0x00));
+        console.write(0xFF11, 0xBF);
+        assert_eq!(console.read(0xFF11), Some(0x3F));
+
+        console.write(NR52, 0x80);
+        console.write(0xFF12, 0xF3);
+        assert_eq!(console.read(0xFF12), Some(0xF3));
+
+        // The CGB doesn't make an exception for the length counters
+        let mut console = Console::new_with_model(Model::Cgb, Cartridge::new_blank(vec![0; 0x8000]));
+        console.write(NR52, 0x00);
+        console.write(0xFF11, 0xBF);
+        assert_eq!(console.read(0xFF11), Some(0x00));
+    }
+
+    #[test]
+    fn input_state_reports_held_buttons() {
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![0; 0x8000]));
+        assert!(console.input_state().is_empty());
+
+        console.press(Button::A);
+        console.press(Button::B);
+        let held = console.input_state();
+        assert!(held.contains(Button::A) && held.contains(Button::B));
+        assert_eq!(held.iter().collect::<Vec<_>>(), vec![Button::A, Button::B]);
+
+        console.release(Button::A);
+        assert_eq!(console.input_state(), ButtonSet(1 << Button::B as u8));
+    }
+
+    #[test]
+    fn reti_services_pending_interrupts_right_away() {
+        let mut memory = FlatMemory::from_program(&[
+            0xD9,   // reti
+        ]);
+        let mut cpu = Cpu::init();
+
+        // Return to 0x0202 with a VBlank interrupt waiting
+        cpu.registers.sp.load(0xC000);
+        memory.0[0xC000] = 0x02;
+        memory.0[0xC001] = 0x02;
+        memory.0[0xFFFF] = 0x01;
+        memory.0[0xFF0F] = 0x01;
+
+        cpu.step(&mut memory).unwrap();
+        cpu.step(&mut memory).unwrap();
+        assert!(cpu.ime());
+        assert_eq!(cpu.registers().pc, 0x0202);
+
+        // The very next step goes to the VBlank handler instead of running the code at 0x0202
+        cpu.step(&mut memory).unwrap();
+        assert_eq!(cpu.registers().pc, 0x0040);
+        assert!(!cpu.ime());
+        assert_eq!(memory.0[0xFF0F], 0x00);
+    }
+
+    #[test]
+    fn add_sets_zero_from_the_result() {
+        let mut registers = Registers::init();
+
+        // 0xFF + 0x01 wraps to 0, carrying out of both nibbles
+        registers.a.0 = 0xFF;
+        registers.f.0 = 0x40;
+        registers.add(0x01);
+        assert_eq!(registers.a.0, 0x00);
+        assert!(registers.zero() && !registers.neg() && registers.half_carry() && registers.carry());
+
+        registers.add(0x01);
+        assert_eq!(registers.a.0, 0x01);
+        assert!(!registers.zero() && !registers.neg() && !registers.half_carry() && !registers.carry());
+
+        // The same goes for adc, even when the carry is what tips it over
+        registers.a.0 = 0x00;
+        registers.set_flags(Some(false), Some(false), Some(false), Some(true));
+        registers.adc(0xFF);
+        assert_eq!(registers.a.0, 0x00);
+        assert!(registers.zero() && registers.half_carry() && registers.carry());
+    }
+
+    #[test]
+    fn accumulator_rotations_carry_the_bit_that_wraps_around() {
+        let mut registers = Registers::init();
+
+        registers.a.0 = 0x01;
+        registers.f.0 = 0x00;
+        registers.rrca();
+        assert_eq!(registers.a.0, 0x80);
+        assert!(registers.carry());
+
+        registers.rrca();
+        assert_eq!(registers.a.0, 0x40);
+        assert!(!registers.carry());
+
+        registers.a.0 = 0x80;
+        registers.rlca();
+        assert_eq!(registers.a.0, 0x01);
+        assert!(registers.carry());
+
+        registers.rlca();
+        assert_eq!(registers.a.0, 0x02);
+        assert!(!registers.carry());
+
+        // Through the carry, the old carry goes in and the bit that falls off comes out
+        registers.a.0 = 0x01;
+        registers.f.0 = 0x00;
+        registers.rra();
+        assert_eq!(registers.a.0, 0x00);
+        assert!(registers.carry());
+
+        registers.rra();
+        assert_eq!(registers.a.0, 0x80);
+        assert!(!registers.carry());
+    }
+
+    #[test]
+    fn register_pairs_round_trip() {
+        let mut registers = Registers::init();
+
+        registers.set_de(0x1234);
+        assert_eq!((registers.d.0, registers.e.0), (0x12, 0x34));
+        registers.c.0 = 0xFF;
+        assert_eq!(registers.get_de(), 0x1234);
+
+        // Every pair at once, each byte different, so mixing up any two registers shows
+        registers.set_af(0x1120);
+        registers.set_bc(0x3344);
+        registers.set_de(0x5566);
+        registers.set_hl(0x7788);
+
+        assert_eq!(registers.get_af(), 0x1120);
+        assert_eq!(registers.get_bc(), 0x3344);
+        assert_eq!(registers.get_de(), 0x5566);
+        assert_eq!(registers.get_hl(), 0x7788);
+    }
+
+    #[test]
+    fn set_flags_leaves_unspecified_flags_alone() {
+        let mut registers = Registers::init();
+        registers.f.0 = 0xF0;
+
+        registers.set_flags(Some(false), None, None, Some(false));
+        assert_eq!(registers.f.0, 0x60);
+
+        registers.set_flags(None, Some(false), None, Some(true));
+        assert_eq!(registers.f.0, 0x30);
+    }
+
+    #[test]
+    fn lenient_boot_accepts_a_blank_logo() {
+        let mut rom = vec![0u8; 0x8000];
+        Cartridge::fix_checksums(&mut rom);
+
+        match Console::from_rom_bytes(rom.clone()) {
+            Err(GbError::InvalidRom(_)) => {},
+            Err(e) => panic!("wrong error: {}", e),
+            Ok(_) => panic!("cartridge with no logo was accepted"),
+        }
+
+        let mut warnings = vec![];
+        Console::from_rom_bytes_lenient(rom.clone(), |w| warnings.push(w.to_string())).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("Nintendo graphic"));
+
+        // With the logo in place, it boots without complaint either way
+        let rom = valid_rom();
+        Console::from_rom_bytes(rom.clone()).unwrap();
+        Console::from_rom_bytes_lenient(rom, |w| panic!("unexpected warning: {}", w)).unwrap();
+
+        // Tiny test programs don't even have room for a header, which is only worth a warning
+        let mut warnings = vec![];
+        Console::from_rom_bytes_lenient(vec![0; 0x100], |w| warnings.push(w.to_string())).unwrap();
+        assert!(warnings.iter().any(|w| w.contains("too short")), "{:?}", warnings);
+
+        match Console::from_rom_bytes(vec![0; 0x100]) {
+            Err(GbError::InvalidRom(e)) => assert!(e.contains("too short"), "{}", e),
+            Err(e) => panic!("wrong error: {}", e),
+            Ok(_) => panic!("cartridge with no header was accepted"),
+        }
+    }
+
+    #[test]
+    fn lcd_registers_are_read_through_the_bus() {
+        let mut memory = FlatMemory::new();
+        memory.0[0xFF40] = 0x91;
+        memory.0[0xFF42] = 0x12;
+        memory.0[0xFF43] = 0x34;
+        memory.0[0xFF47] = 0xE4;
+        memory.0[0xFF49] = 0x1B;
+
+        let registers = LcdRegisters::read_from(&memory);
+        assert_eq!(registers, LcdRegisters { lcdc: 0x91, scy: 0x12, scx: 0x34, lyc: 0, bgp: 0xE4, obp0: 0, obp1: 0x1B });
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![0; 0x8000]));
+        console.write(0xFF42, 0x56);
+        console.write(0xFF43, 0x78);
+        let registers = LcdRegisters::read_from(&console);
+        assert_eq!((registers.scy, registers.scx), (0x56, 0x78));
+    }
+
+    #[test]
+    fn opcode_profiling_counts_executed_opcodes() {
+        // The multiplication program from `test_multiplication`, but 2 times 64
+        let program = vec![
+            0x3E, 0x02,         // ld A, $02
+            0x4F,               // ld C, A
+            0x06, 0x40,         // ld B, $40
+            0x05,               // dec B
+            // loop:
+            0x81,               // add C
+            0x05,               // dec B
+            0xC2, 0x06, 0x00    // jp nz, loop
+        ];
+
+        let mut console = Console::start(Some(Cartridge::new_blank(program.clone())));
+        assert!(console.opcode_histogram().iter().all(|&n| n == 0));
+
+        console.enable_opcode_profiling();
+        console.run_until(|c| c.cpu.registers().pc as usize == program.len(), 100_000).unwrap();
+        assert_eq!(console.cpu.registers().a.0, 128);
+
+        let histogram = console.opcode_histogram();
+        assert_eq!(histogram[0x81], 63);
+        assert_eq!(histogram[0x05], 64);
+        assert_eq!(histogram[0xC2], 63);
+        assert_eq!(histogram[0x3E] + histogram[0x4F] + histogram[0x06], 3);
+        assert_eq!(histogram.iter().sum::<u64>(), 63 + 64 + 63 + 3);
+    }
+
+    #[test]
+    fn prefixed_instructions_on_hl_take_longer() {
+        assert_eq!(Instruction::prefixed(0x30, "swap B").cycles, (8, 8));
+        assert_eq!(Instruction::prefixed(0x36, "swap (HL)").cycles, (16, 16));
+        assert_eq!(Instruction::prefixed(0x46, "bit 0, (HL)").cycles, (12, 12));
+        assert_eq!(Instruction::prefixed(0xC6, "set 0, (HL)").cycles, (16, 16));
+
+        let mut memory = FlatMemory::from_program(&[
+            0xCB, 0x30,     // swap B
+            0xCB, 0x36,     // swap (HL)
+        ]);
+        let mut cpu = Cpu::init();
+        cpu.registers.set_hl(0xC000);
+        memory.0[0xC000] = 0x12;
+
+        for expected in [8, 16].iter() {
+            let start = cpu.cycles;
+            for _ in 0..3 {
+                cpu.step(&mut memory).unwrap();
+            }
+            assert_eq!(cpu.cycles - start, *expected);
+        }
+        assert_eq!(memory.0[0xC000], 0x21);
+    }
+
+    #[test]
+    fn consoles_can_be_configured() {
+        let rom = valid_rom();
+
+        let config = ConsoleConfig {
+            model: Model::Cgb,
+            frame_skip: 2,
+            brightness: 0.5,
+            socd: Socd::Allow,
+            vram_access_strict: true,
+            ..ConsoleConfig::default()
+        };
+
+        let console = Console::from_rom_bytes_with_config(rom.clone(), config).unwrap();
+        assert_eq!(console.model(), Model::Cgb);
+        assert_eq!(console.frame_skip(), 2);
+        assert_eq!(console.brightness(), 0.5);
+        assert_eq!(console.contrast(), 1.0);
+        assert_eq!(console.bus.joypad.socd(), Socd::Allow);
+        assert!(console.bus.vram_access_strict);
+
+        let console = Console::from_rom_bytes(rom).unwrap();
+        assert_eq!(console.model(), Model::Dmg);
+        assert_eq!(console.frame_skip(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn console_configs_round_trip_through_serde() {
+        let rom = valid_rom();
+
+        let config = ConsoleConfig {
+            model: Model::Cgb,
+            frame_skip: 3,
+            contrast: 1.5,
+            socd: Socd::LastWins,
+            layer_palettes: [Some(MonoShadeColors::GREEN), None, None, None],
+            ..ConsoleConfig::default()
+        };
+
+        let json = serde_json::to_string(&config).unwrap();
+        let loaded: ConsoleConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, config);
+
+        let console = Console::from_rom_bytes_with_config(rom, loaded).unwrap();
+        assert_eq!(console.model(), Model::Cgb);
+        assert_eq!(console.frame_skip(), 3);
+        assert_eq!(console.contrast(), 1.5);
+        assert_eq!(console.bus.joypad.socd(), Socd::LastWins);
+
+        // The background is all color 0, and gets its own palette instead of the one asked for
+        let rgb = console.screen_rgb(&MonoShadeColors::GREY);
+        let green = MonoShadeColors::GREEN.adjusted(console.brightness(), console.contrast());
+        assert_eq!(rgb[..3], green.0[0]);
+    }
+
+    #[test]
+    fn bcd_addition_with_daa() {
+        let mut registers = Registers::init();
+
+        // 09 + 09 = 18
+        registers.a.0 = 0x09;
+        registers.add(0x09);
+        registers.daa();
+        assert_eq!(registers.a.0, 0x18);
+        assert!(!registers.carry());
+
+        // 1999 + 0001 = 2000, one byte at a time with the carry going between them
+        registers.a.0 = 0x99;
+        registers.add(0x01);
+        registers.daa();
+        assert_eq!(registers.a.0, 0x00);
+        assert!(registers.carry() && registers.zero());
+
+        registers.a.0 = 0x19;
+        registers.adc(0x00);
+        registers.daa();
+        assert_eq!(registers.a.0, 0x20);
+        assert!(!registers.carry());
+
+        // 99 + 99 = 198, carrying out of the top digit
+        registers.a.0 = 0x99;
+        registers.add(0x99);
+        registers.daa();
+        assert_eq!(registers.a.0, 0x98);
+        assert!(registers.carry());
+
+        // And back down: 20 - 01 = 19
+        registers.a.0 = 0x20;
+        registers.sub(0x01);
+        registers.daa();
+        assert_eq!(registers.a.0, 0x19);
+    }
+
+    #[test]
+    fn running_frames_advances_the_rtc() {
+        use std::time::{Duration, SystemTime};
+
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[
+            0x18, 0xFE,         // jr -2
+        ]);
+        rom[0x147] = 0x10; // MBC3+TIMER+RAM+BATTERY
+        rom[0x149] = 0x02;
+
+        let mut console = Console::from_rom_bytes_lenient(rom, |_| {}).unwrap();
+        let rtc = |console: &Console| match &console.bus.cartridge.as_ref().unwrap().mbc {
+            MBC::MBC3(mbc) => mbc.rtc,
+            _ => panic!("not an MBC3"),
+        };
+
+        for _ in 0..FRAMES_PER_SECOND - 1 {
+            console.run_frame().unwrap();
+        }
+        assert_eq!(rtc(&console).seconds, 0);
+        console.run_frame().unwrap();
+        assert_eq!(rtc(&console).seconds, 1);
+
+        // Advancing by hand rolls over into the other registers
+        console.advance_rtc(59 + 59 * 60 + 23 * 3600).unwrap();
+        let time = rtc(&console);
+        assert_eq!((time.days, time.hours, time.minutes, time.seconds), (1, 0, 0, 0));
+
+        // So does catching up with real time, as if the save had been sitting for a minute and a half
+        console.sync_rtc_to(SystemTime::now() - Duration::from_secs(90)).unwrap();
+        let time = rtc(&console);
+        assert_eq!((time.days, time.hours, time.minutes, time.seconds), (1, 0, 1, 30));
+
+        // The host's clock going backwards doesn't take the cartridge's with it
+        console.sync_rtc_to(SystemTime::now() + Duration::from_secs(90)).unwrap();
+        assert_eq!(rtc(&console).minutes, 1);
+
+        console.set_rtc_auto_advance(false);
+        for _ in 0..FRAMES_PER_SECOND {
+            console.run_frame().unwrap();
+        }
+        assert_eq!(rtc(&console).seconds, 30);
+
+        assert!(Console::start(Some(Cartridge::new_blank(vec![]))).advance_rtc(1).is_none());
+    }
+
+    #[test]
+    fn halt_bug_reads_the_next_opcode_twice() {
+        let mut memory = FlatMemory::from_program(&[
+            0x76,   // halt
+            0x3C,   // inc A
+        ]);
+        let mut cpu = Cpu::init();
+        cpu.registers.a.0 = 0;
+
+        // Interrupts are disabled, but there's one waiting
+        memory.0[0xFFFF] = 0x04;
+        memory.0[0xFF0F] = 0x04;
+
+        for _ in 0..2 {
+            cpu.step(&mut memory).unwrap();
+        }
+        assert!(!cpu.is_halted());
+
+        for _ in 0..4 {
+            cpu.step(&mut memory).unwrap();
+        }
+        assert_eq!(cpu.registers().a.0, 2);
+        assert_eq!(cpu.registers().pc, 2);
+    }
+
+    #[test]
+    fn dump_writes_offsets_and_bytes() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+
+        let mut out = vec![];
+        cartridge.dump_to(&mut out, false, 0x130..0x150).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0x00000130 00 00 00 00 54 45 53 54 "));
+        assert!(lines[1].starts_with("0x00000140 "));
+
+        let mut out = vec![];
+        cartridge.dump_to(&mut out, true, 0x134..0x139).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "0x00000134 T E S T . \n");
+    }
+
+    #[test]
+    fn add_and_adc_carry_out_of_each_nibble() {
+        let mut registers = Registers::init();
+
+        // Carries out of both nibbles
+        registers.a.0 = 0xFF;
+        registers.add(0x01);
+        assert_eq!(registers.a.0, 0x00);
+        assert!(registers.half_carry() && registers.carry());
+
+        // Only out of the bottom one
+        registers.a.0 = 0x0F;
+        registers.add(0x01);
+        assert_eq!(registers.a.0, 0x10);
+        assert!(registers.half_carry() && !registers.carry());
+
+        // A carry that doesn't wrap all the way around to 0
+        registers.a.0 = 0x80;
+        registers.add(0x90);
+        assert_eq!(registers.a.0, 0x10);
+        assert!(!registers.half_carry() && registers.carry());
+
+        // The carry going in is what pushes it over
+        registers.a.0 = 0xFE;
+        registers.set_flags(Some(false), Some(false), Some(false), Some(true));
+        registers.adc(0x01);
+        assert_eq!(registers.a.0, 0x00);
+        assert!(registers.zero() && registers.half_carry() && registers.carry());
+
+        registers.a.0 = 0x10;
+        registers.set_flags(Some(false), Some(false), Some(false), Some(true));
+        registers.adc(0xFF);
+        assert_eq!(registers.a.0, 0x10);
+        assert!(registers.half_carry() && registers.carry());
+    }
+
+    #[test]
+    fn absolute_loads_and_stores_round_trip() {
+        let mut memory = FlatMemory::from_program(&[
+            0xEA, 0x00, 0xC0,   // ld ($C000), A
+            0x3E, 0x00,         // ld A, $00
+            0xFA, 0x00, 0xC0,   // ld A, ($C000)
+        ]);
+        let mut cpu = Cpu::init();
+        cpu.registers.a.0 = 0x42;
+
+        let mut steps = 0;
+        while cpu.registers.pc != 0x08 || cpu.state == CpuState::Exec {
+            cpu.step(&mut memory).unwrap();
+
+            steps += 1;
+            assert!(steps < 100, "program never finished");
+        }
+
+        // If the address were decoded big-endian these would both go to $00C0 instead
+        assert_eq!(memory.read(0xC000), Some(0x42));
+        assert_eq!(memory.read(0x00C0), Some(0x00));
+        assert_eq!(cpu.registers.a.0, 0x42);
+    }
+
+    #[test]
+    fn strict_vram_access_drops_writes_while_drawing() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[
+            0x18, 0xFE,         // jr -2
+        ]);
+
+        for &strict in [false, true].iter() {
+            let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom.clone()));
+            console.set_vram_access_strict(strict);
+
+            while console.read(STAT).unwrap() & 0x03 != 3 {
+                console.step_instruction().unwrap();
+            }
+
+            console.write(0x8000, 0x42);
+            console.write(0xFE00, 0x42);
+            assert_eq!(console.bus.chr_ram[0], if strict { 0x00 } else { 0x42 });
+            assert_eq!(console.bus.oam[0], if strict { 0x00 } else { 0x42 });
+            assert_eq!(console.read(0x8000), Some(if strict { 0xFF } else { 0x42 }));
+
+            // Once the line's been drawn it's fair game again
+            while console.read(STAT).unwrap() & 0x03 != 0 {
+                console.step_instruction().unwrap();
+            }
+
+            console.write(0x8000, 0x24);
+            assert_eq!(console.read(0x8000), Some(0x24));
+        }
+    }
+
+    #[test]
+    fn half_carry_depends_on_the_operands() {
+        assert!(Registers::half_carry_occurred(0x08, 0x08, false));
+        assert!(!Registers::half_carry_occurred(0x01, 0x01, false));
+        assert!(Registers::half_carry_occurred(0x07, 0x08, true));
+        assert!(Registers::half_borrow_occurred(0x10, 0x01, false));
+        assert!(!Registers::half_borrow_occurred(0x1F, 0x0F, false));
+
+        let mut registers = Registers::init();
+        registers.a.0 = 0x08;
+        registers.add(0x08);
+        assert!(registers.half_carry());
+        registers.a.0 = 0x01;
+        registers.add(0x01);
+        assert!(!registers.half_carry());
+
+        // cp doesn't touch A, but sets the flags like sub
+        registers.a.0 = 0x10;
+        registers.cp(0x01);
+        assert_eq!(registers.a.0, 0x10);
+        assert!(registers.neg() && registers.half_carry() && !registers.carry() && !registers.zero());
+        registers.cp(0x20);
+        assert!(registers.carry() && !registers.half_carry());
+
+        // 8-bit inc and dec set H too (but leave C alone)
+        let mut memory = FlatMemory::from_program(&[
+            0x3C,   // inc A
+            0x3D,   // dec A
+        ]);
+        let mut cpu = Cpu::init();
+        cpu.registers.a.0 = 0x0F;
+        cpu.registers.f.0 = 0x10;
+
+        cpu.step(&mut memory).unwrap();
+        cpu.step(&mut memory).unwrap();
+        assert_eq!(cpu.registers.a.0, 0x10);
+        assert!(cpu.registers.half_carry() && !cpu.registers.neg() && cpu.registers.carry());
+
+        cpu.step(&mut memory).unwrap();
+        cpu.step(&mut memory).unwrap();
+        assert_eq!(cpu.registers.a.0, 0x0F);
+        assert!(cpu.registers.half_carry() && cpu.registers.neg() && cpu.registers.carry());
+    }
+
+    #[test]
+    fn cp_with_a_bigger_operand_sets_carry() {
+        let mut registers = Registers::init();
+        registers.a.0 = 0x10;
+        registers.cp(0x20);
+
+        assert!(registers.carry());
+        assert!(!registers.zero());
+        assert_eq!(registers.a.0, 0x10);
+
+        registers.cp(0x10);
+        assert!(registers.zero() && !registers.carry());
+    }
+
+    #[test]
+    fn wav_recording_captures_a_tone() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[
+            0x18, 0xFE,         // jr -2
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+
+        // Channel 1 on both sides at full volume, playing a 50% duty square wave at about 440Hz
+        console.write(NR52, 0x80);
+        console.write(NR50, 0x77);
+        console.write(NR51, 0x11);
+        console.write(NR11, 0x80);
+        console.write(NR11 + 1, 0xF0);
+        console.write(NR11 + 2, 0xD6);
+        console.write(NR11 + 3, 0x86);
+
+        let path = std::env::temp_dir().join("gbars_wav_recording_captures_a_tone.wav");
+        console.start_wav_recording(&path, 8000).unwrap();
+        for _ in 0..FRAMES_PER_SECOND {
+            console.run_frame().unwrap();
+        }
+        console.stop_wav_recording().unwrap();
+        assert!(!console.is_recording_wav());
+
+        let wav = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let u16_at = |i: usize| u16::from_le_bytes([wav[i], wav[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([wav[i], wav[i + 1], wav[i + 2], wav[i + 3]]);
+
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, wav.len() - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u16_at(20), 1);      // PCM
+        assert_eq!(u16_at(22), 2);      // stereo
+        assert_eq!(u32_at(24), 8000);
+        assert_eq!(u16_at(34), 16);
+        assert_eq!(&wav[36..40], b"data");
+
+        // 60 frames is a hair over a second
+        let data_size = u32_at(40) as usize;
+        assert_eq!(data_size, wav.len() - 44);
+        assert!((8000 * 4..8100 * 4).contains(&data_size), "{} bytes of samples", data_size);
+
+        let samples: Vec<i16> = wav[44..].chunks(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+        assert!(samples.iter().any(|&s| s > 0));
+        assert!(samples.iter().any(|&s| s < 0));
+        assert!(samples.chunks(2).all(|lr| lr[0] == lr[1]));
+    }
+
+    #[test]
+    fn memory_bus_reads_slices() {
+        let mut console = Console::from_rom_bytes_lenient(vec![0; 0x8000], |_| {}).unwrap();
+        for i in 0..0x10 {
+            console.write(0x8000 + i, i as u8 * 3);
+        }
+
+        let vram = console.memory_bus().read_slice(0x8000, 0x10);
+        assert_eq!(vram, (0..0x10).map(|i| i * 3).collect::<Vec<u8>>());
+
+        // The unused area past OAM can't be read
+        assert_eq!(console.memory_bus().read_slice(0xFE9F, 2), vec![0x00, 0xFF]);
+
+        // Nor can anything past the end of the address space
+        console.write(0xFFFF, 0x1F);
+        let end = console.memory_bus().read_slice(0xFFF0, 0x20);
+        assert_eq!(end.len(), 0x20);
+        assert_eq!(end[0xF], 0x1F);
+        assert!(end[0x10..].iter().all(|&b| b == 0xFF));
+        assert_eq!(console.memory_bus().read_slice(usize::MAX, 2), vec![0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn cartridge_ram_banks_are_separate() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x03; // MBC1 + RAM + Battery
+        rom[0x149] = 0x03; // 4 banks of 8 KiB
+        let mut mbc = Cartridge::from_bytes(rom).unwrap().mbc;
+
+        // Nothing gets through until the RAM's turned on
+        assert_eq!(mbc.write_ram(0x0123, 0x42), Ok(0));
+        assert_eq!(mbc.read_ram(0x0123), Some(0xFF));
+
+        mbc.write_rom(0x0000, 0x0A);    // enable RAM
+        mbc.write_rom(0x6000, 0x01);    // RAM banking mode
+        mbc.write_rom(0x4000, 0x01);    // bank 1
+        assert_eq!(mbc.write_ram(0x0123, 0x42), Ok(1));
+        assert_eq!(mbc.read_ram(0x0123), Some(0x42));
+
+        mbc.write_rom(0x4000, 0x00);    // bank 0
+        assert_eq!(mbc.read_ram(0x0123), Some(0x00));
+        mbc.write_ram(0x0123, 0x24).unwrap();
+
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_ram(0x0123), Some(0x42));
+
+        mbc.write_rom(0x0000, 0x00);    // disable RAM
+        assert_eq!(mbc.read_ram(0x0123), Some(0xFF));
+
+        // Cartridges without RAM always read open bus
+        let rom_only = Cartridge::from_bytes(vec![0; 0x8000]).unwrap().mbc;
+        assert_eq!(rom_only.read_ram(0x0000), Some(0xFF));
+    }
+
+    #[test]
+    fn push_and_pop_move_sp_like_the_hardware() {
+        let mut memory = FlatMemory::from_program(&[
+            0xC5,   // push BC
+            0xD1,   // pop DE
+        ]);
+        let mut cpu = Cpu::init();
+        cpu.registers.sp.load(0xD000);
+        cpu.registers.set_bc(0x1234);
+
+        let mut steps = 0;
+        while cpu.registers.pc != 0x01 || cpu.state == CpuState::Exec {
+            cpu.step(&mut memory).unwrap();
+
+            steps += 1;
+            assert!(steps < 100, "push never finished");
+        }
+
+        assert_eq!(cpu.registers.sp.0, 0xCFFE);
+        assert_eq!(memory.read(0xCFFF), Some(0x12));
+        assert_eq!(memory.read(0xCFFE), Some(0x34));
+        assert_eq!(memory.read(0xD000), Some(0x00));
+
+        while cpu.registers.pc != 0x02 || cpu.state == CpuState::Exec {
+            cpu.step(&mut memory).unwrap();
+
+            steps += 1;
+            assert!(steps < 100, "pop never finished");
+        }
+
+        assert_eq!(cpu.registers.sp.0, 0xD000);
+        assert_eq!(cpu.registers.get_de(), 0x1234);
+    }
+
+    #[test]
+    fn ram_is_addressable_to_the_end() {
+        let mut ram = RAM::new(0x2000);
+        assert_eq!(ram.size(), 0x2000);
+
+        assert_eq!(ram.write_byte(0x1FFF, 0x42), Ok(1));
+        assert_eq!(ram.read_byte(0x1FFF), Some(0x42));
+
+        assert!(ram.write_byte(0x2000, 0x42).is_err());
+        assert!(ram.write_bytes(0x1FFE, &[1, 2, 3]).is_err());
+        assert_eq!(ram.write_bytes(0x1FFE, &[1, 2]), Ok(2));
+    }
+
+    #[test]
+    fn pending_interrupts_are_reported_apart_from_ime() {
+        let mut memory = FlatMemory::new();
+        let cpu = Cpu::init();
+
+        // Requested but not enabled
+        memory.write(0xFF0F, 0x05);
+        assert_eq!(cpu.pending_interrupts(&memory).pending, 0);
+
+        memory.write(0xFFFF, 0x04);
+        let flags = cpu.pending_interrupts(&memory);
+        assert!(flags.contains(Interrupt::Timer));
+        assert!(!flags.contains(Interrupt::VBlank));
+        assert_eq!(flags.iter().collect::<Vec<_>>(), vec![Interrupt::Timer]);
+
+        // Pending, but it won't fire without IME
+        assert!(!flags.ime);
+        assert_eq!(flags.next(), None);
+
+        let mut cpu = cpu;
+        cpu.ime = true;
+        assert_eq!(cpu.pending_interrupts(&memory).next(), Some(Interrupt::Timer));
+        assert_eq!(Interrupt::Timer.vector(), 0x50);
+    }
+
+    #[test]
+    fn stack_round_trips_values_and_return_addresses() {
+        let mut memory = FlatMemory::from_program(&[
+            0xE5,               // push HL
+            0xC1,               // pop BC
+            0xCD, 0x10, 0x00,   // call $0010
+            0x18, 0xFE,         // jr -2
+        ]);
+        memory.0[0x10] = 0xC9;  // ret
+        let mut cpu = Cpu::init();
+        cpu.registers.sp.load(0xFFFE);
+        cpu.registers.set_hl(0xBEEF);
+
+        let mut steps = 0;
+        while cpu.registers.pc != 0x05 || cpu.state == CpuState::Exec {
+            cpu.step(&mut memory).unwrap();
+
+            steps += 1;
+            assert!(steps < 100, "never returned from the call");
+        }
+
+        assert_eq!(cpu.registers.get_bc(), 0xBEEF);
+        assert_eq!(cpu.registers.sp.0, 0xFFFE);
+
+        // The return address went on the stack low byte first
+        assert_eq!(memory.read(0xFFFC), Some(0x05));
+        assert_eq!(memory.read(0xFFFD), Some(0x00));
+    }
+
+    #[test]
+    fn apu_makes_samples_at_the_chosen_rate() {
+        let mut apu = Apu::default();
+        assert_eq!(apu.sample_rate(), DEFAULT_SAMPLE_RATE);
+        apu.set_sample_rate(48000);
+
+        // Instructions take all sorts of different times, so tick unevenly for a second
+        let registers = ApuRegisters::default();
+        let (mut cycles, mut samples) = (0, 0);
+        for &step in [4, 8, 12, 16, 20, 24].iter().cycle() {
+            if cycles + step > CLOCK_SPEED as u64 {
+                break;
+            }
+
+            cycles += step;
+            samples += apu.sample_batch(step, &registers).len() / 2;
+        }
+        samples += apu.sample_batch(CLOCK_SPEED as u64 - cycles, &registers).len() / 2;
+
+        assert!((47999..=48001).contains(&samples), "{} samples", samples);
+    }
+
+    #[test]
+    fn save_states_stream_through_readers_and_writers() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x03; // MBC1 + RAM + Battery
+        rom[0x149] = 0x02; // 8 KiB
+        rom[0x100..0x10B].copy_from_slice(&[
+            0x3E, 0x0A,         // ld A, $0A
+            0xEA, 0x00, 0x00,   // ld ($0000), A    (enable RAM)
+            0xEA, 0x34, 0xA0,   // ld ($A034), A
+            0x3C,               // inc A
+            0x18, 0xFD,         // jr -3
+        ]);
+
+        let mut console = Console::from_rom_bytes_lenient(rom.clone(), |_| {}).unwrap();
+        for _ in 0..10 {
+            console.step_instruction().unwrap();
+        }
+
+        let mut state = Vec::new();
+        console.save_state_to_writer(&mut state).unwrap();
+        assert_eq!(console.save_state(), state);
+
+        let mut loaded = Console::from_rom_bytes_lenient(rom, |_| {}).unwrap();
+        loaded.load_state_from_reader(&mut &state[..]).unwrap();
+
+        assert_eq!(*loaded.cpu.registers(), *console.cpu.registers());
+        assert_eq!(loaded.cycles(), console.cycles());
+        assert_eq!(loaded.read(0xA034), Some(0x0A));
+        assert_eq!(loaded.read(LY), console.read(LY));
+
+        // They carry on the same way from there
+        for _ in 0..10 {
+            console.step_instruction().unwrap();
+            loaded.step_instruction().unwrap();
+        }
+        assert_eq!(*loaded.cpu.registers(), *console.cpu.registers());
+
+        // A truncated state is rejected without touching the console
+        let registers = *loaded.cpu.registers();
+        assert!(loaded.load_state(&state[..state.len() - 1]).is_err());
+        assert_eq!(*loaded.cpu.registers(), registers);
+
+        // And so is one for another kind of cartridge
+        let mut other = Console::from_rom_bytes_lenient(vec![0; 0x8000], |_| {}).unwrap();
+        assert!(other.load_state(&state).is_err());
+    }
+
+    #[test]
+    fn halt_waits_for_an_interrupt() {
+        let mut memory = FlatMemory::from_program(&[
+            0x76,   // halt
+            0x3C,   // inc A
+        ]);
+        memory.write(0xFFFF, 0x04);
+        let mut cpu = Cpu::init();
+        cpu.registers.a.0 = 0;
+
+        for _ in 0..10 {
+            cpu.step(&mut memory).unwrap();
+        }
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.registers.pc, 0x01);
+        assert_eq!(cpu.registers.a.0, 0);
+
+        // With IME off, the timer interrupt just wakes it up without being serviced
+        let cycles = cpu.cycles;
+        memory.write(0xFF0F, 0x04);
+        for _ in 0..3 {
+            cpu.step(&mut memory).unwrap();
+        }
+        assert!(!cpu.is_halted());
+        assert!(cpu.cycles > cycles);
+        assert_eq!(cpu.registers.a.0, 1);
+        assert_eq!(memory.read(0xFF0F), Some(0x04));
+    }
+
+    #[test]
+    fn vblank_interrupt_jumps_to_its_vector() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[
+            0xFB,               // ei
+            0x00,               // nop
+            0x18, 0xFE,         // jr -2
+        ]);
+        rom[0x40..0x42].copy_from_slice(&[
+            0x3E, 0x42,         // ld A, $42
+        ]);
+
+        let mut console = Console::from_rom_bytes_lenient(rom, |_| {}).unwrap();
+        console.write(0xFFFF, 0x05);
+        assert_eq!(console.read(0xFFFF), Some(0x05));
+
+        // The boot ROM leaves VBlank requested
+        console.write(INTERRUPT_FLAG, 0x00);
+
+        for _ in 0..3 {
+            console.step_instruction().unwrap();
+        }
+        assert_eq!(console.cpu.registers.pc, 0x102);
+        assert!(console.cpu.ime());
+
+        let sp = console.cpu.registers.sp.0;
+        console.write(INTERRUPT_FLAG, 0x01);
+        console.step_instruction().unwrap();
+
+        // Jumping to the handler happens on the way to fetching the next instruction, so the
+        // handler's first instruction has run by the time step_instruction returns
+        assert_eq!(console.cpu.registers.pc, 0x42);
+        assert_eq!(console.cpu.registers.a.0, 0x42);
+        assert_eq!(console.cpu.registers.sp.0, sp.wrapping_sub(2));
+        assert_eq!(console.read(sp.wrapping_sub(2) as usize), Some(0x02));
+        assert_eq!(console.read(sp.wrapping_sub(1) as usize), Some(0x01));
+        assert_eq!(console.read(INTERRUPT_FLAG).unwrap() & 0x01, 0);
+        assert!(!console.cpu.ime());
+    }
+
+    #[test]
+    fn daa_matches_the_reference_table() {
+        // (A, F before, A after, F after)
+        let table = [
+            (0x00, 0x00, 0x00, 0x80),
+            (0x09, 0x00, 0x09, 0x00),
+            (0x0A, 0x00, 0x10, 0x00),
+            (0x0F, 0x20, 0x15, 0x00),
+            (0x99, 0x00, 0x99, 0x00),
+            (0x9A, 0x00, 0x00, 0x90),
+            (0xA0, 0x00, 0x00, 0x90),
+            (0xFF, 0x00, 0x65, 0x10),
+            (0x00, 0x10, 0x60, 0x10),
+            (0x12, 0x30, 0x78, 0x10),
+            (0x9A, 0x20, 0x00, 0x90),
+            (0x3C, 0x10, 0xA2, 0x10),
+            (0x00, 0x40, 0x00, 0xC0),
+            (0x0F, 0x60, 0x09, 0x40),
+            (0xFA, 0x70, 0x94, 0x50),
+            (0x60, 0x50, 0x00, 0xD0),
+            (0x66, 0x70, 0x00, 0xD0),
+            (0x45, 0x40, 0x45, 0x40),
+            (0x9B, 0x60, 0x95, 0x40),
+            (0x00, 0x70, 0x9A, 0x50),
+        ];
+
+        let mut registers = Registers::init();
+        for &(a, f, expected_a, expected_f) in table.iter() {
+            registers.a.0 = a;
+            registers.f.0 = f;
+            registers.daa();
+
+            assert_eq!(
+                (registers.a.0, registers.f.0), (expected_a, expected_f),
+                "daa on A={:02X} F={:02X}", a, f
+            );
+        }
+
+        // 58 + 46 + a carry in is 105, which carries out of BCD
+        registers.a.0 = 0x58;
+        registers.set_flags(Some(false), Some(false), Some(false), Some(true));
+        registers.adc(0x46);
+        registers.daa();
+        assert_eq!(registers.a.0, 0x05);
+        assert!(registers.carry());
+
+        // 10 - 1 - a borrow in is 8
+        registers.a.0 = 0x10;
+        registers.sbc(0x01);
+        registers.daa();
+        assert_eq!(registers.a.0, 0x08);
+        assert!(!registers.carry());
+
+        // 3 - 5 borrows out of BCD, leaving 98
+        registers.a.0 = 0x03;
+        registers.sub(0x05);
+        registers.daa();
+        assert_eq!(registers.a.0, 0x98);
+        assert!(registers.carry());
+    }
+
+    #[test]
+    fn timer_overflow_requests_an_interrupt() {
+        let mut timer = Timer::init();
+        timer.write(TAC, 0x05);     // on, every 16 cycles
+        timer.write(TMA, 0x80);
+        timer.write(TIMA, 0xFE);
+
+        assert!(!timer.step(16));
+        assert_eq!(timer.tima, 0xFF);
+        assert!(!timer.step(15));
+        assert!(timer.step(1));
+        assert_eq!(timer.tima, 0x80);
+
+        // DIV counts regardless, and any write resets it
+        timer.step(DIV_PERIOD * 3 - 32);
+        assert_eq!(timer.read(DIV), Some(3));
+        timer.write(DIV, 0x42);
+        assert_eq!(timer.read(DIV), Some(0));
+
+        // Turned off, TIMA stays put
+        timer.write(TAC, 0x01);
+        assert!(!timer.step(1024));
+        assert_eq!(timer.read(TAC), Some(0xF9));
+
+        // And through the console, each instruction's cycles count towards it
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[
+            0x18, 0xFE,         // jr -2
+        ]);
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        console.write(INTERRUPT_FLAG, 0x00);
+        console.write(TAC, 0x05);
+        console.write(TIMA, 0xF0);
+
+        let start = console.cycles();
+        while console.read(INTERRUPT_FLAG).unwrap() & 0x04 == 0 {
+            console.step_instruction().unwrap();
+            assert!(console.cycles() - start < 1000, "the timer never overflowed");
+        }
+
+        // It overflows during whichever jr (12 cycles each) takes it past 16 ticks
+        let cycles = console.cycles() - start;
+        assert!((16 * 0x10..16 * 0x10 + 12).contains(&cycles), "overflowed after {} cycles", cycles);
+    }
+
+    #[test]
+    fn header_offsets_are_where_the_pan_docs_say() {
+        assert_eq!((LOGO_START, LOGO_END), (0x104, 0x134));
+        assert_eq!(LOGO_END - LOGO_START, NINTENDO_GRAPHIC.len());
+        assert_eq!((TITLE_START, TITLE_END), (0x134, 0x143));
+        assert_eq!(CART_TYPE, 0x147);
+        assert_eq!(ROM_SIZE, 0x148);
+        assert_eq!(RAM_SIZE, 0x149);
+        assert_eq!(DEST_CODE, 0x14A);
+        assert_eq!(HEADER_CHECKSUM, 0x14D);
+        assert_eq!(GLOBAL_CHECKSUM, 0x14E);
+        assert_eq!(HEADER_END, 0x150);
+    }
+
+    #[test]
+    fn pop_af_only_restores_the_top_nibble_of_f() {
+        let mut memory = FlatMemory::from_program(&[
+            0xC5,   // push BC
+            0xF1,   // pop AF
+        ]);
+        let mut cpu = Cpu::init();
+        cpu.registers.sp.load(0xD000);
+        cpu.registers.set_bc(0x12FF);
+
+        let mut steps = 0;
+        while cpu.registers.pc != 0x02 || cpu.state == CpuState::Exec {
+            cpu.step(&mut memory).unwrap();
+
+            steps += 1;
+            assert!(steps < 100, "pop never finished");
+        }
+
+        assert_eq!(cpu.registers.sp.0, 0xD000);
+        assert_eq!(cpu.registers.get_af(), 0x12F0);
+    }
+
+    #[test]
+    fn request_interrupt_sets_its_bit_in_if() {
+        let mut memory = FlatMemory::new();
+        memory.request_interrupt(Interrupt::VBlank);
+        assert_eq!(memory.read(0xFF0F), Some(0x01));
+
+        memory.request_interrupt(Interrupt::Joypad);
+        assert_eq!(memory.read(0xFF0F), Some(0x11));
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![]));
+        console.write(INTERRUPT_FLAG, 0x00);
+        console.request_interrupt(Interrupt::Timer);
+        assert_eq!(console.read(INTERRUPT_FLAG).unwrap() & 0x1F, 0x04);
+    }
+
+    #[test]
+    fn frame_count_goes_up_once_per_frame() {
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![0; 0x8000]));
+        assert_eq!(console.frame_count(), 0);
+
+        for frames in 1..=5 {
+            console.run_frame().unwrap();
+            assert_eq!(console.frame_count(), frames);
+        }
+
+        assert!(console.measure_fps(0.05) > 0.0);
+    }
+
+    #[test]
+    fn rst_and_jp_hl_land_where_they_should() {
+        let mut memory = FlatMemory::from_program(&[
+            0xEF,   // rst $28
+        ]);
+        let mut cpu = Cpu::init();
+        cpu.registers.sp.load(0xD000);
+
+        let mut steps = 0;
+        while cpu.registers.pc != 0x28 || cpu.state == CpuState::Exec {
+            cpu.step(&mut memory).unwrap();
+
+            steps += 1;
+            assert!(steps < 100, "rst never finished");
+        }
+
+        // The address after the rst went on the stack
+        assert_eq!(cpu.registers.sp.0, 0xCFFE);
+        assert_eq!(memory.read(0xCFFE), Some(0x01));
+        assert_eq!(memory.read(0xCFFF), Some(0x00));
+
+        let mut memory = FlatMemory::from_program(&[
+            0xE9,   // jp (HL)
+        ]);
+        let mut cpu = Cpu::init();
+        cpu.registers.set_hl(0x4000);
+
+        let mut steps = 0;
+        while cpu.registers.pc != 0x4000 || cpu.state == CpuState::Exec {
+            cpu.step(&mut memory).unwrap();
+
+            steps += 1;
+            assert!(steps < 100, "jp (HL) never finished");
+        }
+    }
+
+    #[test]
+    fn battery_backed_ram_round_trips_through_a_save_file() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x03; // MBC1 + RAM + Battery
+        rom[0x149] = 0x03; // 4 banks of 8 KiB
+        let path = std::env::temp_dir().join("gbars_battery_backed_ram.sav");
+        let path = path.to_str().unwrap();
+
+        let mut cart = Cartridge::from_bytes(rom.clone()).unwrap();
+        cart.mbc.write_rom(0x0000, 0x0A);    // enable RAM
+        cart.mbc.write_rom(0x6000, 0x01);    // RAM banking mode
+        cart.mbc.write_rom(0x4000, 0x02);    // bank 2
+        cart.mbc.write_ram(0x0123, 0x42).unwrap();
+        cart.save_ram(path).unwrap();
+
+        let mut fresh = Cartridge::from_bytes(rom).unwrap();
+        fresh.load_ram(path).unwrap();
+        let _ = std::fs::remove_file(path);
+        assert_eq!(fresh.mbc.ram().unwrap()[..], cart.mbc.ram().unwrap()[..]);
+        assert_eq!(fresh.mbc.ram().unwrap()[2 * 0x2000 + 0x0123], 0x42);
+
+        // Without a battery there's nothing to save, so no file gets made
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x02; // MBC1 + RAM
+        rom[0x149] = 0x02;
+        let path = std::env::temp_dir().join("gbars_no_battery.sav");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let mut cart = Cartridge::from_bytes(rom).unwrap();
+        cart.save_ram(path).unwrap();
+        assert!(!std::path::Path::new(path).exists());
+        assert!(cart.load_ram(path).is_ok());
+    }
+
+    #[test]
+    fn stop_waits_for_a_button() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[
+            0x10, 0x00, // stop
+            0x3C,       // inc A
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        console.step_instruction().unwrap();
+        assert!(console.cpu.is_stopped());
+
+        let a = console.cpu.registers().a.0;
+        for _ in 0..3 {
+            console.run_frame().unwrap();
+        }
+
+        assert!(console.cpu.is_stopped());
+        assert_eq!(console.cpu.registers.pc, 0x102);
+        assert_eq!(console.read(LY), Some(0));
+
+        console.press(Button::A);
+        assert!(!console.cpu.is_stopped());
+        assert_ne!(console.read(INTERRUPT_FLAG).unwrap() & 0x10, 0);
+
+        console.step_instruction().unwrap();
+        assert_eq!(console.cpu.registers.pc, 0x103);
+        assert_eq!(console.cpu.registers().a.0, a.wrapping_add(1));
+    }
+
+    #[test]
+    fn frozen_ram_patches_outlast_the_game() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x106].copy_from_slice(&[
+            0x21, 0x00, 0xC0,   // ld HL,$C000
+            0x34,               // inc (HL)
+            0x18, 0xFD,         // jr -3
+        ]);
+
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(rom));
+        console.apply_ram_patch(0xC000, 0x63, true);
+        assert_eq!(console.read(0xC000), Some(0x63));
+
+        console.run_frame().unwrap();
+        assert_eq!(console.read(0xC000), Some(0x63));
+
+        // Once it's let go, the game gets its way
+        console.apply_ram_patch(0xC000, 0x63, false);
+        console.run_frame().unwrap();
+        assert_ne!(console.read(0xC000), Some(0x63));
+    }
+
+    #[test]
+    fn bad_global_checksums_still_validate() {
+        let mut rom = valid_rom();
+        rom[0x0150] = 0x42;
+        Cartridge::fix_checksums(&mut rom);
+
+        let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        assert!(cartridge.is_valid());
+        assert!(cartridge.verify_global_checksum());
+
+        rom[0x0150] = 0x24;
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert!(cartridge.is_valid());
+        assert!(!cartridge.verify_global_checksum());
+    }
+
+    #[test]
+    fn header_checksum_wraps_instead_of_overflowing() {
+        // Every byte is 0xFF, so a plain sum would overflow on the second one. Taking away 0xFF
+        // and then 1 wraps all the way around, leaving a checksum of 0.
+        let mut rom = valid_rom();
+        rom[TITLE_START..HEADER_CHECKSUM].iter_mut().for_each(|b| *b = 0xFF);
+        rom[HEADER_CHECKSUM] = 0x00;
+
+        Cartridge::from_bytes(rom.clone()).unwrap().validate().unwrap();
+
+        rom[TITLE_START] = 0xFE;
+        let error = Cartridge::from_bytes(rom).unwrap().validate().unwrap_err();
+        assert!(error.contains("header checksum"), "{}", error);
+        assert!(error.contains("0x01"), "{}", error);
+    }
+
+    #[test]
+    fn reg16_wraps_at_both_ends() {
+        let mut sp = Reg16(0xFFFF);
+        sp.inc();
+        assert_eq!(sp, Reg16(0x0000));
+        sp.dec();
+        assert_eq!(sp, Reg16(0xFFFF));
+
+        sp.load(0x0005);
+        assert_eq!(sp.wrapping_add(-6), 0xFFFF);
+        assert_eq!(sp.wrapping_add(-128), 0xFF85);
+        sp.load(0xFFF0);
+        assert_eq!(sp.wrapping_add(0x7F), 0x006F);
+    }
+
+    #[test]
+    fn ld_nn_sp_stores_sp_little_endian() {
+        let mut memory = FlatMemory::from_program(&[
+            0x08, 0x00, 0xC0,   // ld ($C000),SP
+        ]);
+        let mut cpu = Cpu::init();
+        cpu.registers.sp.load(0xBEEF);
+
+        let mut steps = 0;
+        while cpu.registers.pc != 0x03 || cpu.state == CpuState::Exec {
+            cpu.step(&mut memory).unwrap();
+
+            steps += 1;
+            assert!(steps < 100, "ld never finished");
+        }
+
+        assert_eq!(memory.read(0xC000), Some(0xEF));
+        assert_eq!(memory.read(0xC001), Some(0xBE));
+    }
+
+    #[test]
+    fn destination_comes_from_the_header() {
+        let mut rom = vec![0u8; 0x8000];
+        assert_eq!(Cartridge::from_bytes(rom.clone()).unwrap().destination, Destination::Japan);
+
+        rom[DEST_CODE] = 0x01;
+        let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        assert_eq!(cartridge.destination, Destination::Overseas);
+        assert_eq!(cartridge.destination.to_string(), "Non-Japanese");
+
+        rom[DEST_CODE] = 0x33;
+        assert_eq!(Cartridge::from_bytes(rom).unwrap().destination, Destination::Unknown);
+    }
+
+    #[test]
+    fn disassembles_instructions_of_each_length() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x150..0x15A].copy_from_slice(&[
+            0x00,               // nop
+            0x06, 0x42,         // ld B, $42
+            0xC3, 0x50, 0xC3,   // jp $C350
+            0x18, 0xFE,         // jr -2
+            0xCB, 0x7C,         // bit 7, H
+        ]);
+        let mbc = Cartridge::from_bytes(rom).unwrap().mbc;
+
+        let (nop, len) = disassemble_at(&mbc, 0x150);
+        assert_eq!((nop.disassemble().as_str(), len), ("nop", 1));
+
+        let (ld, len) = disassemble_at(&mbc, 0x151);
+        assert_eq!((ld.disassemble().as_str(), len), ("ld B, $42", 2));
+
+        let (jp, len) = disassemble_at(&mbc, 0x153);
+        assert_eq!((jp.disassemble().as_str(), len), ("jp $C350", 3));
+
+        let (jr, len) = disassemble_at(&mbc, 0x156);
+        assert_eq!(len, 2);
+        assert_eq!(jr.disassemble(), "jr -2");
+        assert_eq!(jr.disassemble_at_pc(0x156), "jr -2 ; $0156");
+
+        let (bit, len) = disassemble_at(&mbc, 0x158);
+        assert_eq!((bit.disassemble().as_str(), len), ("bit 7, H", 2));
+
+        let mut ld_bc = Instruction::from_opcode(0x01);
+        ld_bc.arg = Arg::Data16(0x1234);
+        assert_eq!(ld_bc.disassemble(), "ld BC, $1234");
+        assert_eq!(Instruction::from_opcode(0xD3).disassemble(), "db $D3");
+    }
+
+    #[test]
+    fn step_reports_the_cycles_an_instruction_took() {
+        // Steps through one whole instruction, adding up the cycles as it goes
+        fn run_instruction(cpu: &mut Cpu, memory: &mut FlatMemory) -> u64 {
+            let mut cycles = 0;
+            loop {
+                let executing = cpu.state == CpuState::Exec;
+                cycles += cpu.step(memory).unwrap();
+
+                if executing {
+                    return cycles;
+                }
+            }
+        }
+
+        let mut memory = FlatMemory::from_program(&[
+            0x20, 0x00, // jr nz, 0
+        ]);
+
+        let mut cpu = Cpu::init();
+        cpu.registers.set_flags(Some(false), None, None, None);
+        assert_eq!(run_instruction(&mut cpu, &mut memory), 12);
+
+        let mut cpu = Cpu::init();
+        cpu.registers.set_flags(Some(true), None, None, None);
+        assert_eq!(run_instruction(&mut cpu, &mut memory), 8);
+
+        // Fetching doesn't take any time until the instruction's executed
+        let mut cpu = Cpu::init();
+        assert_eq!(cpu.step(&mut memory), Ok(0));
+        assert_eq!(cpu.step(&mut memory), Ok(0));
+        assert_eq!(cpu.step(&mut memory), Ok(12));
+    }
+
+    #[test]
+    fn overlapping_sprites_are_ordered_by_model() {
+        // Tile 1 is solid color 1, and tile 2 is solid color 2
+        let mut chr_ram = vec![0u8; 0x1800];
+        for row in 0..8 {
+            chr_ram[16 + row * 2] = 0xFF;
+            chr_ram[32 + row * 2 + 1] = 0xFF;
+        }
+        let bg_data = vec![0u8; 0x800];
+        let vram = Vram { chr_ram: &chr_ram, bg_data: &bg_data };
+
+        let registers = LcdRegisters { lcdc: 0x82, scy: 0, scx: 0, lyc: 0xFF, bgp: 0xE4, obp0: 0xE4, obp1: 0xE4 };
+
+        // Draws the first line with the given sprites and hands back what's at x = 4
+        let draw = |model: Model, sprites: &[[u8; 4]]| {
+            let mut oam = vec![0u8; 0xA0];
+            for (i, sprite) in sprites.iter().enumerate() {
+                oam[i * 4..i * 4 + 4].copy_from_slice(sprite);
+            }
+
+            let mut ppu = Ppu::init();
+            ppu.set_model(model);
+            ppu.tick(456, &vram, &oam, &registers);
+            ppu.screen()[4]
+        };
+
+        // The second sprite in OAM is further left, so it's on top on the DMG but not on the CGB
+        let staggered = [[16, 12, 1, 0], [16, 8, 2, 0]];
+        assert_eq!(draw(Model::Dmg, &staggered), 2);
+        assert_eq!(draw(Model::Cgb, &staggered), 1);
+
+        // Level with each other, the first one in OAM wins on both
+        let level = [[16, 8, 1, 0], [16, 8, 2, 0]];
+        assert_eq!(draw(Model::Dmg, &level), 1);
+        assert_eq!(draw(Model::Cgb, &level), 1);
+    }
+
+    #[test]
+    fn lcd_registers_are_mapped() {
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![0; 0x8000]));
+
+        console.write(BGP, 0x1B);
+        assert_eq!(console.read(BGP), Some(0x1B));
+
+        let ly = console.read(LY).unwrap();
+        console.write(LY, ly.wrapping_add(1));
+        assert_eq!(console.read(LY), Some(ly));
+
+        // The PPU's mode can't be written over, and bit 7 always reads as 1
+        let stat = console.read(STAT).unwrap();
+        console.write(STAT, 0x40);
+        assert_eq!(console.read(STAT), Some(0xC0 | (stat & 0x07)));
+
+        console.write(LYC, ly);
+        assert_ne!(console.read(STAT).unwrap() & 0x04, 0);
+        console.write(LYC, ly.wrapping_add(1));
+        assert_eq!(console.read(STAT).unwrap() & 0x04, 0);
+    }
+
+    #[test]
+    fn a_frame_has_one_vblank_and_an_hblank_per_line() {
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![0; 0x8000]));
+        console.write(STAT, 0x08); // interrupt on HBlank
+        console.write(INTERRUPT_FLAG, 0x00);
+
+        // Start at the top of a frame
+        while console.read(LY) != Some(0) {
+            console.step_instruction().unwrap();
+        }
+        console.write(INTERRUPT_FLAG, 0x00);
+
+        let (mut vblanks, mut hblanks) = (0, 0);
+        let mut wrapped = false;
+        let end = console.cycles() + CYCLES_PER_FRAME;
+        while console.cycles() < end {
+            let ly = console.read(LY).unwrap();
+            console.step_instruction().unwrap();
+            wrapped |= ly == 153 && console.read(LY) == Some(0);
+
+            let requested = console.read(INTERRUPT_FLAG).unwrap();
+            vblanks += requested & 0x01;
+            hblanks += (requested & 0x02) >> 1;
+            console.write(INTERRUPT_FLAG, 0x00);
+        }
+
+        assert!(wrapped);
+        assert_eq!(vblanks, 1);
+        assert_eq!(hblanks, SCREEN_HEIGHT as u8);
+    }
+
+    #[test]
+    fn the_ppu_stands_still_while_the_lcd_is_off() {
+        let mut console = Console::new_with_model(Model::Dmg, Cartridge::new_blank(vec![0; 0x8000]));
+        console.write(STAT, 0x78); // interrupt on everything
+        console.write(LYC, 0x00);
+        console.write(LCDC, 0x11);
+        console.write(INTERRUPT_FLAG, 0x00);
+
+        let end = console.cycles() + 2 * CYCLES_PER_FRAME;
+        while console.cycles() < end {
+            console.step_instruction().unwrap();
+            assert_eq!(console.read(LY), Some(0));
+            assert_eq!(console.read(STAT).unwrap() & 0x03, 0);
+        }
+        assert_eq!(console.read(INTERRUPT_FLAG).unwrap() & 0x03, 0);
+
+        // Turning it back on starts from the top of the screen
+        console.write(LCDC, 0x91);
+        console.run_frame().unwrap();
+        assert_ne!(console.read(INTERRUPT_FLAG).unwrap() & 0x01, 0);
+    }
+
+    #[test]
+    fn mbc3_clock_is_read_through_the_latch() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x10; // MBC3 + Timer + RAM + Battery
+        rom[0x149] = 0x03;
+        let mut mbc = Cartridge::from_bytes(rom).unwrap().mbc;
+
+        mbc.write_rom(0x0000, 0x0A);    // enable RAM and the clock
+        mbc.write_rom(0x4000, 0x08);    // seconds
+        mbc.write_ram(0x0000, 42).unwrap();
+
+        // Nothing shows up until the clock's latched
+        assert_eq!(mbc.read_ram(0x0000), Some(0));
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), Some(0));
+
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), Some(42));
+
+        // The latched time stays put while the clock keeps going
+        if let MBC::MBC3(mbc3) = &mut mbc {
+            mbc3.rtc.advance(30);
+        }
+        for _ in 0..3 {
+            assert_eq!(mbc.read_ram(0x0000), Some(42));
+        }
+
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), Some(12));
+        mbc.write_rom(0x4000, 0x09);    // minutes
+        assert_eq!(mbc.read_ram(0x0000), Some(1));
+
+        // Halting the clock shows up in bit 6 of the last register
+        mbc.write_rom(0x4000, 0x0C);
+        mbc.write_ram(0x0000, 0x41).unwrap();
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), Some(0x41));
+        if let MBC::MBC3(mbc3) = &mbc {
+            assert!(mbc3.rtc.halted);
+            assert_eq!(mbc3.rtc.days, 0x100);
+        }
+
+        // Banks 0-7 are still plain RAM
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0x0000, 0x99).unwrap();
+        assert_eq!(mbc.read_ram(0x0000), Some(0x99));
+    }
+
+    #[test]
+    fn mbc1_upper_bits_go_where_the_mode_says() {
+        // 2 MiB of ROM, with each bank starting with its own number
+        let mut rom = vec![0u8; 0x20_0000];
+        for bank in 0..0x80 {
+            rom[bank * 0x4000 + 0x10] = bank as u8;
+        }
+        rom[0x147] = 0x03; // MBC1 + RAM + Battery
+        rom[0x148] = 0x06; // 2 MiB
+        rom[0x149] = 0x03; // 4 banks of 8 KiB
+        let mut mbc = Cartridge::from_bytes(rom).unwrap().mbc;
+
+        // ROM bank 0x21: 0x01 in the lower register, 0x01 in the upper one
+        mbc.write_rom(0x2000, 0x01);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x4010), Some(0x21));
+
+        // Only the bottom two bits of the upper register count
+        mbc.write_rom(0x4000, 0xFE);
+        assert_eq!(mbc.read_rom(0x4010), Some(0x41));
+
+        // In RAM banking mode the same register picks the RAM bank, and the ROM bank loses its
+        // upper bits
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_rom(0x4010), Some(0x01));
+
+        mbc.write_ram(0x0042, 0x99).unwrap();
+        assert_eq!(mbc.ram().unwrap()[3 * 0x2000 + 0x0042], 0x99);
+    }
+
+    #[test]
+    fn mbc1_bank_numbers_wrap_to_the_rom_size() {
+        // 64 KiB of ROM, with each bank starting with its own number
+        let mut rom = vec![0u8; 0x1_0000];
+        for bank in 0..4 {
+            rom[bank * 0x4000 + 0x10] = bank as u8;
+        }
+        rom[0x147] = 0x01; // MBC1
+        rom[0x148] = 0x01; // 64 KiB
+        let mut console = Console::start(Some(Cartridge::from_bytes(rom).unwrap()));
+
+        // There's no bank 5, so it's bank 1
+        console.write(0x2000, 0x05);
+        assert_eq!(console.read(0x4010), Some(0x01));
+    }
+
+    #[test]
+    fn mbc1_ram_is_bank_0_in_rom_banking_mode() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x03; // MBC1 + RAM + Battery
+        rom[0x149] = 0x03; // 4 banks of 8 KiB
+        let mut console = Console::start(Some(Cartridge::from_bytes(rom).unwrap()));
+        let ram = |console: &Console| console.bus.cartridge.as_ref().unwrap().mbc.ram().unwrap()[0];
+
+        console.write(0x0000, 0x0A);
+        console.write(0xA000, 0x42);
+        assert_eq!(ram(&console), 0x42);
+
+        // The bank register is ignored until RAM banking mode is switched on
+        console.write(0x4000, 0x02);
+        console.write(0xA000, 0x43);
+        assert_eq!(ram(&console), 0x43);
+    }
+
+    #[test]
+    fn mbc2_registers_are_picked_by_address_bit_8() {
+        let mut rom = vec![0u8; 0x4_0000];
+        for bank in 0..0x10 {
+            rom[bank * 0x4000 + 0x10] = bank as u8;
+        }
+        rom[0x147] = 0x06; // MBC2 + Battery
+        rom[0x148] = 0x03; // 256 KiB
+        let mut mbc = Cartridge::from_bytes(rom).unwrap().mbc;
+
+        // Bit 8 clear means the RAM enable register, so the bank doesn't change
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4010), Some(0x01));
+
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(mbc.read_rom(0x4010), Some(0x05));
+
+        // Bank 0 can't be put in the switchable area
+        mbc.write_rom(0x2100, 0x00);
+        assert_eq!(mbc.read_rom(0x4010), Some(0x01));
+
+        // And with bit 8 set, the RAM enable register is left alone
+        mbc.write_rom(0x0100, 0x0A);
+        assert_eq!(mbc.read_ram(0x0000), Some(0xFF));
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(0x0000), Some(0xF0));
+    }
+
+    #[test]
+    fn mbc5_selects_any_of_512_banks() {
+        // 8 MiB of ROM, with each bank starting with its own number
+        let mut rom = vec![0u8; 0x80_0000];
+        for bank in 0..0x200 {
+            rom[bank * 0x4000 + 0x10] = bank as u8;
+            rom[bank * 0x4000 + 0x11] = (bank >> 8) as u8;
+        }
+        rom[0x147] = 0x1B; // MBC5 + RAM + Battery
+        rom[0x148] = 0x08; // 8 MiB
+        rom[0x149] = 0x04; // 16 banks of 8 KiB
+        let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        let mut mbc = cartridge.mbc;
+
+        mbc.write_rom(0x2000, 0x00);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!((mbc.read_rom(0x4010), mbc.read_rom(0x4011)), (Some(0x00), Some(0x01)));
+
+        // Changing the low bits keeps bit 8
+        mbc.write_rom(0x2000, 0x23);
+        assert_eq!((mbc.read_rom(0x4010), mbc.read_rom(0x4011)), (Some(0x23), Some(0x01)));
+
+        // Bank 0 can be selected directly
+        mbc.write_rom(0x3000, 0x00);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4010), mbc.read_rom(0x0010));
+
+        // Bit 3 of the RAM bank register is the rumble motor on carts that have one
+        mbc.write_rom(0x4000, 0x0B);
+        if let MBC::MBC5(mbc5) = &mbc {
+            assert_eq!(mbc5.active_ram_bank, 0x0B);
+        }
+
+        rom[0x147] = 0x1E; // MBC5 + Rumble + RAM + Battery
+        let mut mbc = Cartridge::from_bytes(rom).unwrap().mbc;
+        mbc.write_rom(0x4000, 0x0B);
+        if let MBC::MBC5(mbc5) = &mbc {
+            assert_eq!(mbc5.active_ram_bank, 0x03);
+        }
+
+        // On a smaller ROM, the 9-bit bank number wraps around rather than running off the end
+        let mut rom = vec![0u8; 0x10_0000];
+        for bank in 0..0x40 {
+            rom[bank * 0x4000 + 0x10] = bank as u8;
+        }
+        rom[0x147] = 0x19; // MBC5
+        rom[0x148] = 0x05; // 1 MiB
+        let mut console = Console::start(Some(Cartridge::from_bytes(rom).unwrap()));
+        console.write(0x3000, 0x01);
+        console.write(0x2000, 0x45);
+        assert_eq!(console.read(0x4010), Some(0x05));
+    }
+
+    #[test]
+    fn save_state_replays_the_rest_of_a_program() {
+        // The multiplication program from `test_multiplication`
+        let program = vec![
+            0x3E, 0x02,         // ld A, $02
+            0x4F,               // ld C, A
+            0x06, 0x04,         // ld B, $04
+            0x05,               // dec B
+            // loop:
+            0x81,               // add C
+            0x05,               // dec B
+            0xC2, 0x06, 0x00    // jp nz, loop
+        ];
+        let done = |c: &Console| c.cpu.registers().pc as usize == program.len();
+
+        let mut console = Console::start(Some(Cartridge::new_blank(program.clone())));
+        for _ in 0..6 {
+            console.step_instruction().unwrap();
+        }
+        let state = console.save_state();
+        let partway = *console.cpu.registers();
+
+        assert_eq!(console.run_until(done, 10_000), Ok(true));
+        assert_eq!(console.cpu.registers().a.0, 8);
+
+        console.load_state(&state).unwrap();
+        assert_eq!(*console.cpu.registers(), partway);
+        assert_eq!(console.run_until(done, 10_000), Ok(true));
+        assert_eq!(console.cpu.registers().a.0, 8);
+
+        // A state from some other version of the format is turned away without touching anything
+        let mut future = state.clone();
+        future[4] += 1;
+        assert!(console.load_state(&future).is_err());
+        assert_eq!(console.cpu.registers().a.0, 8);
+    }
+
     // #[test]
     // fn test_division() {
     //     let mut cpu = Cpu::init();
@@ -0,0 +1,501 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::vec::Vec;
+
+// The FIFO renderer wins if both are turned on, since you'd only ask for it if you wanted accuracy
+#[cfg(feature = "ppu-fifo")]
+use super::fifo::render_line;
+#[cfg(all(feature = "ppu-scanline", not(feature = "ppu-fifo")))]
+use super::scanline::render_line;
+
+use super::console::{Model, LCDC, SCY, SCX, LYC, BGP, OBP0, OBP1};
+use super::memory::MemoryBus;
+
+#[cfg(not(any(feature = "ppu-scanline", feature = "ppu-fifo")))]
+compile_error!("At least one of the `ppu-scanline` or `ppu-fifo` features must be enabled");
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// The screen refreshes at about 59.7 Hz, which works out to 70224 clock cycles per frame: 154
+/// lines (144 visible plus 10 of vertical blank) of 456 cycles each
+pub const CYCLES_PER_LINE: u64 = 456;
+pub const LINES_PER_FRAME: u8 = 154;
+pub const CYCLES_PER_FRAME: u64 = CYCLES_PER_LINE * LINES_PER_FRAME as u64;
+/// How long modes 2 and 3 last at the start of each visible line
+pub const OAM_SCAN_CYCLES: u64 = 80;
+pub const DRAWING_CYCLES: u64 = 172;
+
+/// The PPU only has room to draw 10 sprites on any given line. During the OAM scan it just takes
+/// the first 10 sprites (in OAM order) that overlap the line and ignores the rest, which is why
+/// games with lots of sprites flicker: they shuffle OAM around every frame so that a different set
+/// of sprites gets dropped each time.
+pub const MAX_SPRITES_PER_LINE: usize = 10;
+
+/// Each sprite takes up 4 bytes of OAM: Y position, X position, tile number, and attributes
+pub const SPRITE_SIZE: usize = 4;
+
+/// The layers that make up the picture. Each pixel on the screen comes from exactly one of them,
+/// which lets each layer be colored differently. Sprites are split up by which of the two sprite
+/// palettes (OBP0 or OBP1) they use. The window isn't drawn yet, so for now nothing comes out of it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Layer {
+    Background,
+    Window,
+    Obj0,
+    Obj1,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 4] = [Layer::Background, Layer::Window, Layer::Obj0, Layer::Obj1];
+}
+
+/// The four shades of the monochrome screen as RGB, from lightest (color 0) to darkest (color 3).
+/// The original DMG screen was famously green, but plenty of people prefer plain greys.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MonoShadeColors(pub [[u8; 3]; 4]);
+
+impl MonoShadeColors {
+    pub const GREEN: Self = Self([
+        [0x9B, 0xBC, 0x0F],
+        [0x8B, 0xAC, 0x0F],
+        [0x30, 0x62, 0x30],
+        [0x0F, 0x38, 0x0F],
+    ]);
+
+    pub const GREY: Self = Self([
+        [0xFF, 0xFF, 0xFF],
+        [0xAA, 0xAA, 0xAA],
+        [0x55, 0x55, 0x55],
+        [0x00, 0x00, 0x00],
+    ]);
+
+    pub fn rgb(&self, shade: u8) -> [u8; 3] {
+        self.0[(shade & 0b11) as usize]
+    }
+
+    /// The same colors with contrast and then brightness applied. Contrast pushes each channel
+    /// away from (or pulls it towards) the middle, and brightness scales it towards black. 1.0
+    /// leaves things as they are for both.
+    pub fn adjusted(&self, brightness: f32, contrast: f32) -> Self {
+        let mut colors = *self;
+
+        for channel in colors.0.iter_mut().flat_map(|rgb| rgb.iter_mut()) {
+            let c = (*channel as f32 - 128.0) * contrast + 128.0;
+            let c = c * brightness + 0.5;
+            *channel = if c < 0.0 { 0 } else if c > 255.0 { 255 } else { c as u8 };
+        }
+
+        colors
+    }
+}
+
+impl Default for MonoShadeColors {
+    fn default() -> Self { Self::GREEN }
+}
+
+/// Video RAM, as the PPU sees it
+pub struct Vram<'a> {
+    // Tile data, 0x8000-0x97FF
+    pub chr_ram: &'a [u8],
+    // The two background maps, 0x9800-0x9FFF
+    pub bg_data: &'a [u8],
+}
+
+/// The LCD registers that affect how a line gets drawn
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct LcdRegisters {
+    pub lcdc: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+}
+
+impl LcdRegisters {
+    /// Reads the LCD registers through a memory bus, the same way the CPU sees them
+    pub fn read_from<M: MemoryBus>(bus: &M) -> Self {
+        let read = |offset| bus.read(offset).unwrap_or(0);
+
+        Self {
+            lcdc: read(LCDC),
+            scy: read(SCY),
+            scx: read(SCX),
+            lyc: read(LYC),
+            bgp: read(BGP),
+            obp0: read(OBP0),
+            obp1: read(OBP1),
+        }
+    }
+}
+
+impl<'a> Vram<'a> {
+    /// Looks up the background tile at (`tile_x`, `tile_y`) in the 32x32 tile background map and
+    /// returns the two bytes making up row `row` of it. LCDC bit 3 picks which of the two maps is
+    /// used, and bit 4 picks how tile numbers are turned into addresses: either unsigned from
+    /// 0x8000, or signed from 0x9000.
+    pub fn bg_tile_row(&self, lcdc: u8, tile_x: u8, tile_y: u8, row: u8) -> (u8, u8) {
+        let map = if lcdc & 0x08 != 0 { 0x400 } else { 0 };
+        let tile = self.bg_data[map + (tile_y as usize % 32) * 32 + tile_x as usize % 32];
+
+        let address = if lcdc & 0x10 != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8 as isize) * 16) as usize
+        } + row as usize * 2;
+
+        (self.chr_ram[address], self.chr_ram[address + 1])
+    }
+
+    /// The color number (0-3, before the palette) of the background at column `x` of line `ly`,
+    /// taking scrolling into account
+    pub fn bg_color(&self, registers: &LcdRegisters, x: u8, ly: u8) -> u8 {
+        let x = x.wrapping_add(registers.scx);
+        let y = ly.wrapping_add(registers.scy);
+        let (lo, hi) = self.bg_tile_row(registers.lcdc, x / 8, y / 8, y % 8);
+
+        color_at(lo, hi, 7 - x % 8)
+    }
+
+    /// The two bytes making up row `row` of a sprite's tile. Sprites always take their tiles from
+    /// 0x8000 onwards, and tall sprites ignore the bottom bit of the tile number since they use a
+    /// pair of tiles.
+    pub fn sprite_tile_row(&self, sprite: &Sprite, row: u8, tall: bool) -> (u8, u8) {
+        let height = if tall { 16 } else { 8 };
+        let tile = if tall { sprite.tile & 0xFE } else { sprite.tile };
+        let row = if sprite.attributes & 0x40 != 0 { height - 1 - row } else { row };
+        let address = tile as usize * 16 + row as usize * 2;
+
+        (self.chr_ram[address], self.chr_ram[address + 1])
+    }
+}
+
+/// Picks the color number of pixel `bit` (7 is the leftmost) out of a row of tile data
+pub fn color_at(lo: u8, hi: u8, bit: u8) -> u8 {
+    (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1)
+}
+
+/// Picks color `color` (0-3) out of a palette register like BGP
+pub fn shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0b11
+}
+
+/// A single entry in OAM
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Sprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+    pub oam_index: usize,
+}
+
+impl Sprite {
+    pub fn from_oam(oam: &[u8], oam_index: usize) -> Self {
+        let entry = &oam[oam_index * SPRITE_SIZE..(oam_index + 1) * SPRITE_SIZE];
+
+        Self {
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            attributes: entry[3],
+            oam_index,
+        }
+    }
+
+    /// Sprite coordinates are offset so that sprites can be partially (or completely) hidden off
+    /// the top and left of the screen: a Y of 16 puts the top of the sprite on line 0, and an X of
+    /// 8 puts its left edge in column 0.
+    pub fn on_line(&self, ly: u8, height: u8) -> bool {
+        let top = self.y as i16 - 16;
+        let ly = ly as i16;
+
+        top <= ly && ly < top + height as i16
+    }
+}
+
+/// Draws a 1 pixel outline in `color` around every sprite in `oam` that's at least partly on the
+/// screen, over the top of an RGB screen (3 bytes per pixel, like `Ppu::screen_rgb` gives). This
+/// is for debugging, so it outlines every sprite, even ones the 10-per-line limit would drop.
+pub fn draw_sprite_outlines(rgb: &mut [u8], oam: &[u8], tall_sprites: bool, color: [u8; 3]) {
+    let height = if tall_sprites { 16 } else { 8 };
+
+    let mut plot = |x: i16, y: i16| {
+        if (0..SCREEN_WIDTH as i16).contains(&x) && (0..SCREEN_HEIGHT as i16).contains(&y) {
+            let i = (y as usize * SCREEN_WIDTH + x as usize) * 3;
+            rgb[i..i + 3].copy_from_slice(&color);
+        }
+    };
+
+    for i in 0..oam.len() / SPRITE_SIZE {
+        let sprite = Sprite::from_oam(oam, i);
+        let left = sprite.x as i16 - 8;
+        let top = sprite.y as i16 - 16;
+        let (right, bottom) = (left + 7, top + height - 1);
+
+        for x in left..=right {
+            plot(x, top);
+            plot(x, bottom);
+        }
+
+        for y in top..=bottom {
+            plot(left, y);
+            plot(right, y);
+        }
+    }
+}
+
+/// The Picture Processing Unit, which draws the screen line by line
+pub struct Ppu {
+    // The line currently being drawn (LY), and how far into it we are
+    pub(crate) ly: u8,
+    pub(crate) line_cycles: u64,
+    // Whether LY matched LYC the last time we checked
+    pub(crate) coincidence: bool,
+    // Whether the LCD is switched on (LCDC bit 7). While it's off, nothing moves.
+    pub(crate) lcd_on: bool,
+    // Lines are only drawn while this is set, so frames can be skipped
+    rendering: bool,
+    // How many whole frames have been drawn
+    frames_drawn: u64,
+    // One byte per pixel holding its shade (0-3), row by row from the top left
+    screen: Vec<u8>,
+    // Which layer each pixel of the screen came from
+    layers: Vec<Layer>,
+    // How many sprites overlapped each line but were left out by the OAM scan. This is purely for
+    // debugging flicker; the hardware doesn't keep track of it.
+    sprites_dropped: [u8; SCREEN_HEIGHT],
+    // Which GameBoy this is, which decides which of two overlapping sprites gets drawn on top
+    model: Model,
+}
+
+impl Ppu {
+    pub fn init() -> Self {
+        Self {
+            ly: 0,
+            line_cycles: 0,
+            coincidence: false,
+            lcd_on: true,
+            rendering: true,
+            frames_drawn: 0,
+            screen: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            layers: vec![Layer::Background; SCREEN_WIDTH * SCREEN_HEIGHT],
+            sprites_dropped: [0; SCREEN_HEIGHT],
+            model: Model::Dmg,
+        }
+    }
+
+    pub fn ly(&self) -> u8 { self.ly }
+
+    /// Stops the PPU in its tracks, the way the LCD goes off while the CPU is stopped. It starts
+    /// again from the top of the screen once it's ticked again.
+    pub fn turn_off(&mut self) {
+        self.ly = 0;
+        self.line_cycles = 0;
+        self.lcd_on = false;
+    }
+
+    /// Whether the LCD was on (LCDC bit 7) as of the last `tick`
+    pub fn lcd_on(&self) -> bool { self.lcd_on }
+
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    /// Turns drawing on or off. The PPU keeps going through the motions either way (LY still
+    /// counts up and interrupts still happen), it just doesn't touch the screen.
+    pub fn set_rendering(&mut self, rendering: bool) {
+        self.rendering = rendering;
+    }
+
+    /// How many frames have been drawn all the way to the bottom of the screen
+    pub fn frames_drawn(&self) -> u64 { self.frames_drawn }
+
+    /// Whether LY matches LYC as of the last `tick`, which is what STAT bit 2 reports
+    pub fn coincidence(&self) -> bool { self.coincidence }
+
+    /// Which part of the line the PPU is on, as reported in the bottom two bits of STAT: 2 while
+    /// it scans OAM, 3 while it draws, 0 for the rest of the line (HBlank), and 1 for the lines
+    /// below the screen (VBlank). Drawing really takes a variable amount of time, but we go with
+    /// the shortest. With the LCD off, it's always 0.
+    pub fn mode(&self) -> u8 {
+        if !self.lcd_on {
+            0
+        } else if self.ly as usize >= SCREEN_HEIGHT {
+            1
+        } else if self.line_cycles < OAM_SCAN_CYCLES {
+            2
+        } else if self.line_cycles < OAM_SCAN_CYCLES + DRAWING_CYCLES {
+            3
+        } else {
+            0
+        }
+    }
+
+    /// Moves the PPU along by some number of clock cycles, advancing LY at the end of each line
+    /// and drawing each visible line (background, then the sprites in `oam` over the top of it)
+    /// as it finishes. Returns true if LY has just come to equal
+    /// LYC. Games use this to get a STAT interrupt on a particular line (for raster effects, like
+    /// a status bar that doesn't scroll), so it should only fire once when LY gets there, not over
+    /// and over for as long as they're equal.
+    ///
+    /// If LCDC bit 7 is clear, the LCD is off, so the PPU holds LY at 0 and does nothing. Games
+    /// turn it off to get at VRAM freely, and it starts again from the top once they turn it on.
+    pub fn tick(&mut self, cycles: u64, vram: &Vram, oam: &[u8], registers: &LcdRegisters) -> bool {
+        if registers.lcdc & 0x80 == 0 {
+            self.turn_off();
+            return false;
+        }
+
+        self.lcd_on = true;
+        self.line_cycles += cycles;
+
+        while self.line_cycles >= CYCLES_PER_LINE {
+            self.line_cycles -= CYCLES_PER_LINE;
+
+            if self.rendering && (self.ly as usize) < SCREEN_HEIGHT {
+                let start = self.ly as usize * SCREEN_WIDTH;
+                render_line(self.ly, vram, registers, &mut self.screen[start..start + SCREEN_WIDTH]);
+                self.layers[start..start + SCREEN_WIDTH].iter_mut().for_each(|layer| *layer = Layer::Background);
+                self.draw_sprites(self.ly, vram, oam, registers);
+
+                if self.ly as usize == SCREEN_HEIGHT - 1 {
+                    self.frames_drawn += 1;
+                }
+            }
+
+            self.ly = (self.ly + 1) % LINES_PER_FRAME;
+        }
+
+        let was_coincident = self.coincidence;
+        self.coincidence = self.ly == registers.lyc;
+
+        self.coincidence && !was_coincident
+    }
+
+    /// The most recently drawn screen, as shades 0-3
+    pub fn screen(&self) -> &[u8] {
+        &self.screen
+    }
+
+    /// Which layer each pixel of the screen came from, in the same order as `screen`
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// The screen converted to RGB using the given colors, 3 bytes per pixel
+    pub fn screen_rgb(&self, colors: &MonoShadeColors) -> Vec<u8> {
+        self.screen.iter()
+            .flat_map(|&shade| colors.rgb(shade).to_vec())
+            .collect()
+    }
+
+    /// Same as `screen_rgb`, but each pixel is colored with the palette for the layer it came
+    /// from, in the same order as `Layer::ALL`
+    pub fn screen_rgb_layered(&self, palettes: &[MonoShadeColors; 4]) -> Vec<u8> {
+        self.screen.iter()
+            .zip(self.layers.iter())
+            .flat_map(|(&shade, &layer)| palettes[layer as usize].rgb(shade).to_vec())
+            .collect()
+    }
+
+    /// Draws the sprites on line `ly` over the background that's already there. Where sprites
+    /// overlap, the one furthest to the left wins, and if they're at the same X, the one earlier
+    /// in OAM does. So they're drawn from lowest priority to highest, each on top of the last.
+    fn draw_sprites(&mut self, ly: u8, vram: &Vram, oam: &[u8], registers: &LcdRegisters) {
+        // Sprites are turned off by LCDC bit 1
+        if registers.lcdc & 0x02 == 0 {
+            return;
+        }
+
+        let tall = registers.lcdc & 0x04 != 0;
+        let mut sprites = self.scan_oam(ly, oam, tall);
+
+        // Where sprites overlap, the DMG draws the one furthest to the left on top, going by OAM
+        // order when they're level. The CGB only goes by OAM order, which they're already in.
+        if self.model != Model::Cgb {
+            sprites.sort_by_key(|sprite| (sprite.x, sprite.oam_index));
+        }
+
+        let start = ly as usize * SCREEN_WIDTH;
+
+        for sprite in sprites.iter().rev() {
+            let row = (ly as i16 - (sprite.y as i16 - 16)) as u8;
+            let (lo, hi) = vram.sprite_tile_row(sprite, row, tall);
+
+            let (palette, layer) = if sprite.attributes & 0x10 != 0 {
+                (registers.obp1, Layer::Obj1)
+            } else {
+                (registers.obp0, Layer::Obj0)
+            };
+
+            for i in 0..8u8 {
+                let x = sprite.x as i16 - 8 + i as i16;
+                if x < 0 || x >= SCREEN_WIDTH as i16 {
+                    continue;
+                }
+
+                let bit = if sprite.attributes & 0x20 != 0 { i } else { 7 - i };
+                let color = color_at(lo, hi, bit);
+
+                // Color 0 is see-through
+                if color == 0 {
+                    continue;
+                }
+
+                // Attribute bit 7 puts the sprite behind background colors 1-3
+                if sprite.attributes & 0x80 != 0
+                    && registers.lcdc & 0x01 != 0
+                    && vram.bg_color(registers, x as u8, ly) != 0 {
+                    continue;
+                }
+
+                self.screen[start + x as usize] = shade(palette, color);
+                self.layers[start + x as usize] = layer;
+            }
+        }
+    }
+
+    /// Searches OAM for the sprites that should be drawn on line `ly`. Sprites are either 8x8 or
+    /// 8x16 (`tall_sprites`), depending on bit 2 of LCDC. Only the first 10 candidates are
+    /// returned; the number of sprites that didn't make the cut is recorded for the line.
+    pub fn scan_oam(&mut self, ly: u8, oam: &[u8], tall_sprites: bool) -> Vec<Sprite> {
+        let height = if tall_sprites { 16 } else { 8 };
+
+        let mut sprites = Vec::with_capacity(MAX_SPRITES_PER_LINE);
+        let mut dropped = 0u8;
+
+        for i in 0..oam.len() / SPRITE_SIZE {
+            let sprite = Sprite::from_oam(oam, i);
+
+            if !sprite.on_line(ly, height) {
+                continue;
+            }
+
+            if sprites.len() < MAX_SPRITES_PER_LINE {
+                sprites.push(sprite);
+            } else {
+                dropped += 1;
+            }
+        }
+
+        if let Some(n) = self.sprites_dropped.get_mut(ly as usize) {
+            *n = dropped;
+        }
+
+        sprites
+    }
+
+    /// The number of sprites that overlapped line `ly` in the last OAM scan of that line but were
+    /// not drawn because of the 10-sprites-per-line limit.
+    pub fn sprites_dropped_on_line(&self, ly: u8) -> u8 {
+        self.sprites_dropped.get(ly as usize).copied().unwrap_or(0)
+    }
+
+    /// The largest number of sprites dropped on any one line
+    pub fn max_sprites_dropped(&self) -> u8 {
+        self.sprites_dropped.iter().copied().max().unwrap_or(0)
+    }
+}
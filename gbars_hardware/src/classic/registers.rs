@@ -1,6 +1,7 @@
 use bitmatch::bitmatch;
 use core::ops::{Add, AddAssign, Sub, SubAssign, BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Deref, DerefMut};
 use super::utils::{wrapping_inc_16, wrapping_dec_16};
+use super::console::Model;
 
 /// The Zilog Z80 has an accumulator (A) and flag (F) register, along with 6 general-purpose
 /// registers (B, C, D, E, H, and L). All of these are 8-bit but can double up as AF, BC, DE, and
@@ -8,6 +9,7 @@ use super::utils::{wrapping_inc_16, wrapping_dec_16};
 /// store the low byte. (The way I remember this is to consider HL: H for High, L for Low.) There
 /// are of course the two pointer registers SP (for the stack pointer) and PC (for the program
 /// counter/instruction pointer).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Registers {
     pub a: Reg8, // accumulator
     pub f: Reg8, // flags: ZNHC0000
@@ -17,7 +19,7 @@ pub struct Registers {
     pub e: Reg8,
     pub h: Reg8,
     pub l: Reg8,
-    pub sp: u16, // stack pointer
+    pub sp: Reg16, // stack pointer
     pub pc: u16, // program counter
 }
 
@@ -32,19 +34,45 @@ impl Registers {
             e: Reg8(0),
             h: Reg8(0),
             l: Reg8(0),
-            sp: 0,
+            sp: Reg16(0),
             pc: 0
         }
     }
+
+    /// The values left in the registers by the boot ROM. A in particular is how games tell which
+    /// model they're running on: 0x01 on the original GameBoy, 0xFF on the Pocket, and 0x11 on the
+    /// Color.
+    pub fn post_boot(model: Model) -> Self {
+        let (a, f, b, c, d, e, h, l) = match model {
+            Model::Dmg => (0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Mgb => (0xFF, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+        };
+
+        Self {
+            a: Reg8(a),
+            f: Reg8(f),
+            b: Reg8(b),
+            c: Reg8(c),
+            d: Reg8(d),
+            e: Reg8(e),
+            h: Reg8(h),
+            l: Reg8(l),
+            sp: Reg16(0xFFFE),
+            pc: 0x0100
+        }
+    }
 }
 
 pub trait Register<Size> : DerefMut {
     fn load(&mut self, data: Size);
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Reg8(pub u8);
-pub struct Reg16(u16);
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Reg16(pub u16);
 
 #[derive(Copy, Clone)]
 pub struct Accumulator(u8);
@@ -134,7 +162,7 @@ impl Registers {
 
     #[bitmatch]
     pub fn get_de(&self) -> u16 {
-        let (d, e) = (self.d.0, self.c.0);
+        let (d, e) = (self.d.0, self.e.0);
         bitpack!("dddddddd_eeeeeeee") as u16
     }
 
@@ -193,55 +221,58 @@ impl Registers {
 }
 
 impl Registers {
+    /// Done in wider arithmetic, so a carry out of the top bit just shows up as a result over 0xFF
     pub fn add(&mut self, data: u8) {
-        let before = self.a.0;
-        self.a += data;
-        let after = self.a.0;
+        let (a, data) = (self.a.0 as u16, data as u16);
+        let result = a + data;
+        self.a.0 = result as u8;
 
         self.set_flags(
             Some(self.a.0 == 0),
             Some(false),
-            Some(Self::half_carry_occurred(before, after)),
-            Some(before > after)
+            Some(Self::half_carry_occurred(a as u8, data as u8, false)),
+            Some(result > 0xFF)
         );
     }
 
+    /// Like `sbc`, this is done in wider arithmetic so that `data + carry` can't overflow
     pub fn adc(&mut self, data: u8) {
-        let before = self.a.0;
-        self.a += data + self.carry_bit();
-        let after = self.a.0;
+        let (a, data, carry) = (self.a.0 as u16, data as u16, self.carry_bit() as u16);
+        let result = a + data + carry;
+        self.a.0 = result as u8;
 
         self.set_flags(
             Some(self.a.0 == 0),
             Some(false),
-            Some(Self::half_carry_occurred(before, after)),
-            Some(before > after)
+            Some(Self::half_carry_occurred(a as u8, data as u8, carry == 1)),
+            Some(result > 0xFF)
         );
     }
 
     pub fn sub(&mut self, data: u8) {
         let before = self.a.0;
-        self.a -= data;
-        let after = self.a.0;
+        self.a.0 = before.wrapping_sub(data);
 
         self.set_flags(
             Some(self.a.0 == 0),
             Some(true),
-            Some(Self::half_borrow_occurred(before, after)),
-            Some(before < after)
+            Some(Self::half_borrow_occurred(before, data, false)),
+            Some(data > before)
         );
     }
 
+    /// `data + carry` doesn't fit in a u8 when data is 0xFF and the carry is set, so this one is
+    /// done in wider arithmetic where a borrow just shows up as a negative result.
     pub fn sbc(&mut self, data: u8) {
-        let before = self.a.0;
-        self.a -= data + self.carry_bit();
-        let after = self.a.0;
+        let (a, data, carry) = (self.a.0 as i16, data as i16, self.carry_bit() as i16);
+        let result = a - data - carry;
+        self.a.0 = result as u8;
 
         self.set_flags(
             Some(self.a.0 == 0),
             Some(true),
-            Some(Self::half_borrow_occurred(before, after)),
-            Some(before < after)
+            Some(Self::half_borrow_occurred(a as u8, data as u8, carry == 1)),
+            Some(result < 0)
         );
     }
 
@@ -278,14 +309,15 @@ impl Registers {
         );
     }
 
+    /// A subtraction that only keeps the flags
     pub fn cp(&mut self, data: u8) {
-        let result = self.a.0 - data;
+        let result = self.a.0.wrapping_sub(data);
 
         self.set_flags(
             Some(result == 0),
             Some(true),
-            Some(Self::half_carry_occurred(self.a.0, result)),
-            Some(result > self.a.0)
+            Some(Self::half_borrow_occurred(self.a.0, data, false)),
+            Some(data > self.a.0)
         );
     }
 
@@ -293,28 +325,28 @@ impl Registers {
     /// instruction into a binary-coded decimal operation. It does this by checking the carry, half-
     /// carry, and neg flags (it is, in fact, the only instruction that checks the latter two).
     ///
-    /// Essentially, it checks each nibble of A and if that nibble is greater than 9 (the largest
-    /// number that can be represented as a single decimal digit) it adds 6 to that nibble and that
-    /// turns it into a single decimal digit. The result is a byte whose high and low nibbles
-    /// represent the 10's and 1's place of a decimal number, respectively.
+    /// It turns the result of adding or subtracting two BCD numbers back into BCD. Any digit that
+    /// went past 9 (or carried/borrowed out of, going by H and C) gets 6 added to (or taken away
+    /// from) it to skip over the hex digits A-F.
     pub fn daa(&mut self) {
-        let mut new_carry = false;
+        let mut carry = self.carry();
+
         if self.neg() { // previous instruction was a subtraction
-            if self.carry() || self.a.0 > 0x99 {
-                self.a += 0x60;
-                new_carry = true;
+            if self.carry() {
+                self.a.0 = self.a.0.wrapping_sub(0x60);
             }
 
-            if self.half_carry() || (self.a.0 & 0x0F) > 0x09 {
-                self.a.0 += 0x06;
+            if self.half_carry() {
+                self.a.0 = self.a.0.wrapping_sub(0x06);
             }
         } else {
-            if self.carry() {
-                self.a.0 -= 0x60;
+            if self.carry() || self.a.0 > 0x99 {
+                self.a.0 = self.a.0.wrapping_add(0x60);
+                carry = true;
             }
 
-            if self.half_carry() {
-                self.a.0 -= 0x06;
+            if self.half_carry() || (self.a.0 & 0x0F) > 0x09 {
+                self.a.0 = self.a.0.wrapping_add(0x06);
             }
         }
 
@@ -322,7 +354,7 @@ impl Registers {
             Some(self.a.0 == 0),
             None,
             Some(false),
-            Some(new_carry)
+            Some(carry)
         );
     }
 
@@ -338,13 +370,15 @@ impl Registers {
     }
 
     pub fn rlca(&mut self) {
+        // The carry gets whatever wraps around, which is bit 7 going in
+        let carry = self.a.0 & 0x80 != 0;
         self.a.rot_left();
 
         self.set_flags(
             Some(false),
             Some(false),
             Some(false),
-            Some(self.a.0 & 1 == 1)
+            Some(carry)
         );
     }
 
@@ -363,13 +397,15 @@ impl Registers {
     }
 
     pub fn rrca(&mut self) {
+        // Likewise, bit 0 going in
+        let carry = self.a.0 & 1 != 0;
         self.a.rot_right();
 
         self.set_flags(
             Some(false),
             Some(false),
             Some(false),
-            Some(self.a.0 & 0x80 == 1)
+            Some(carry)
         )
     }
 
@@ -387,17 +423,17 @@ impl Registers {
         );
     }
 
+    /// Sets or clears each of the flags (Z, N, H, and C, which live in the top nibble of F). A flag
+    /// given as None is left the way it was, since plenty of instructions only touch some of them.
     pub fn set_flags(&mut self, z: Option<bool>, n: Option<bool>, h: Option<bool>, c: Option<bool>) {
-        let mut f = 0;
-        for flag in [z, n, h, c].iter() {
+        let mut f = self.f.0;
+        for (flag, bit) in [z, n, h, c].iter().zip([7, 6, 5, 4].iter()) {
             if let Some(b) = flag {
-                f |= if *b { 1 } else { 0 };
+                f = (f & !(1 << bit)) | ((*b as u8) << bit);
             }
-
-            f <<= 1;
         }
 
-        self.f = Reg8(f << 3);
+        self.f = Reg8(f & 0xF0);
     }
 
     #[bitmatch]
@@ -427,8 +463,9 @@ impl Registers {
     pub fn carry(&self) -> bool { self.carry_bit() == 1 }
 
     /// A half-carry is triggered when there's a carry from the 3rd to 4th bit for 8-bit or
-    /// from the 11th to 12th for 16-bit. The way to check this is if the sum of the 4 least-
-    /// significant bits of the values before and after the computation carries.
+    /// from the 11th to 12th for 16-bit. It depends on what was added together, not on the result
+    /// (which has already lost the carry), so the way to check it is to add the 4 least-significant
+    /// bits of the two operands (and the carry going in, for `adc`) and see if that carries.
     ///
     /// ex:
     ///
@@ -455,32 +492,22 @@ impl Registers {
     ///                ^
     ///                |
     ///                +------ no carry from adding lower nibbles => no half-carry occurred
-    pub fn half_carry_occurred(b: u8, a: u8) -> bool {
-        ((b & 0x0F) + (a & 0x0F)) & 0x10 == 0x10
+    pub fn half_carry_occurred(a: u8, b: u8, carry: bool) -> bool {
+        (a & 0x0F) + (b & 0x0F) + carry as u8 > 0x0F
     }
 
     /// A half-borrow is the inverse of a half-carry. It's triggered when the 4th bit is borrowed
-    /// by the 3rd bit in 8-bit arithmetic or the 12th by the 11th in 16-bit. This can be worked
-    /// out by similar logic as with finding a half-carry, but you must flip the bits of the
-    /// bottom nibble of the value before subtraction.
+    /// by the 3rd bit in 8-bit arithmetic or the 12th by the 11th in 16-bit, which happens when
+    /// the bottom nibble being taken away (plus the borrow going in, for `sbc`) is bigger than
+    /// the bottom nibble it's being taken from.
     ///
     /// 0b0001_0110 (22) - 0b0000_1010 (10) = 0b0000_1100 (12)
-    /// (The 3rd bit borrows from the 4th so a half-borrow occurs)
-    ///
-    /// ~0110 + 1100 = 1001 + 1100 = (1) 0101
-    ///                               ^
-    ///                               |
-    ///                               +------- carries from adding the nibbles so half-borrow occurred
+    /// (0110 < 1010, so the 3rd bit borrows from the 4th and a half-borrow occurs)
     ///
     /// 0b0001_1100 (28) - 0b0000_1010 (10) = 0b0001_0010 (18)
-    /// (The 3rd bit does not borrow from the 4th and so a half-borrow doesn't occur)
-    ///
-    /// ~1100 + 1010 = 0011 + 1010 = (0) 1101
-    ///                               ^
-    ///                               |
-    ///                               +------- no carry from adding the nibbles so no half-borrow occurred
-    pub fn half_borrow_occurred(b: u8, a: u8) -> bool {
-        ((!b & 0x0F) + (a & 0x0F)) & 0x10 == 0x10
+    /// (1100 >= 1010, so the 3rd bit does not borrow from the 4th and a half-borrow doesn't occur)
+    pub fn half_borrow_occurred(a: u8, b: u8, borrow: bool) -> bool {
+        (a & 0x0F) < (b & 0x0F) + borrow as u8
     }
 }
 
@@ -523,6 +550,25 @@ impl Reg8 {
     }
 }
 
+impl Reg16 {
+    pub fn load(&mut self, data: u16) {
+        self.0 = data;
+    }
+
+    pub fn inc(&mut self) {
+        self.0 = wrapping_inc_16(self.0);
+    }
+
+    pub fn dec(&mut self) {
+        self.0 = wrapping_dec_16(self.0);
+    }
+
+    /// Adds a signed offset, the way `add SP,e8` and `ld HL,SP+e8` do, wrapping around either end
+    pub fn wrapping_add(self, offset: i8) -> u16 {
+        self.0.wrapping_add(offset as i16 as u16)
+    }
+}
+
 impl Add for Reg8 {
     type Output = Self;
 
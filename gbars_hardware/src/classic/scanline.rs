@@ -0,0 +1,15 @@
+use super::ppu::{Vram, LcdRegisters, shade};
+
+/// Draws line `ly` of the background all in one go. This is about as cheap as rendering gets, but
+/// since everything on the line is drawn at once, mid-line register changes aren't picked up.
+pub fn render_line(ly: u8, vram: &Vram, registers: &LcdRegisters, line: &mut [u8]) {
+    // With the background turned off (LCDC bit 0) the DMG just shows color 0
+    if registers.lcdc & 0x01 == 0 {
+        line.iter_mut().for_each(|pixel| *pixel = 0);
+        return;
+    }
+
+    for (x, pixel) in line.iter_mut().enumerate() {
+        *pixel = shade(registers.bgp, vram.bg_color(registers, x as u8, ly));
+    }
+}
@@ -0,0 +1,273 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::vec::Vec;
+
+use super::tile::TILE_SIZE;
+
+/// Something on the other end of the link cable. Serial transfers are really an exchange: as the
+/// GameBoy shifts a byte out, the other side shifts a byte in at the same time.
+pub trait SerialLink {
+    /// Takes the byte the GameBoy sent and returns the one it gets back
+    fn exchange(&mut self, data: u8) -> u8;
+}
+
+/// An image that came out of the printer, one shade (0-3) per pixel
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PrintedImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// The printer paper is 160 pixels (20 tiles) wide
+pub const PRINTER_WIDTH: usize = 160;
+
+// Commands the GameBoy can send the printer
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+
+// Bits of the status byte the printer sends back at the end of each packet
+const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+const STATUS_UNPROCESSED_DATA: u8 = 0x08;
+
+/// Where we are in the packet currently being received
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLo,
+    LengthHi,
+    Data,
+    ChecksumLo,
+    ChecksumHi,
+    Alive,
+    Status,
+}
+
+/// The GameBoy Printer. Everything sent to it comes in packets that look like this:
+///
+/// | 0x88 0x33 | command | compression | length (LE) | data... | checksum (LE) | 0x00 0x00 |
+///
+/// The checksum is the sum of everything from the command to the end of the data. While the last
+/// two bytes go out, the printer answers with 0x81 (to say it's connected) and then its status.
+///
+/// Games send a data packet per 2 rows of tiles, then an empty data packet, then a print packet
+/// with the palette to use. Once the image is printed, it's handed to `on_print`.
+pub struct Printer<F: FnMut(&PrintedImage)> {
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    packet: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    // Tile data that's been sent but not printed yet
+    image_data: Vec<u8>,
+    on_print: F,
+}
+
+impl<F: FnMut(&PrintedImage)> Printer<F> {
+    pub fn new(on_print: F) -> Self {
+        Self {
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            image_data: Vec::new(),
+            on_print,
+        }
+    }
+
+    /// Carries out a packet once all of it (including the checksum) has arrived
+    fn process_packet(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        let data = if self.compressed {
+            decompress(&self.packet)
+        } else {
+            core::mem::take(&mut self.packet)
+        };
+
+        match self.command {
+            INIT => {
+                self.image_data.clear();
+                self.status = 0;
+            },
+
+            DATA => {
+                self.image_data.extend_from_slice(&data);
+                if !self.image_data.is_empty() {
+                    self.status |= STATUS_UNPROCESSED_DATA;
+                }
+            },
+
+            // The print packet has 4 bytes: the number of sheets, the margins, the palette, and
+            // the exposure. Only the palette matters here.
+            PRINT => {
+                let palette = data.get(2).copied().unwrap_or(0xE4);
+                let image = self.render(palette);
+
+                (self.on_print)(&image);
+
+                self.image_data.clear();
+                self.status &= !STATUS_UNPROCESSED_DATA;
+            },
+
+            // Anything else (like 0x0F, which just asks for the status) doesn't do anything
+            _ => {}
+        }
+    }
+
+    /// Lays the received tiles out 20 to a row and turns them into shades with `palette`
+    fn render(&self, palette: u8) -> PrintedImage {
+        let tiles_per_row = PRINTER_WIDTH / 8;
+        let tile_rows = self.image_data.len() / (TILE_SIZE * tiles_per_row);
+        let height = tile_rows * 8;
+
+        let mut pixels = vec![0; PRINTER_WIDTH * height];
+
+        for (i, tile) in self.image_data.chunks_exact(TILE_SIZE).enumerate().take(tile_rows * tiles_per_row) {
+            let (tile_x, tile_y) = (i % tiles_per_row, i / tiles_per_row);
+
+            for row in 0..8 {
+                let (lo, hi) = (tile[row * 2], tile[row * 2 + 1]);
+
+                for col in 0..8 {
+                    let bit = 7 - col;
+                    let color = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+                    let (x, y) = (tile_x * 8 + col, tile_y * 8 + row);
+
+                    pixels[y * PRINTER_WIDTH + x] = (palette >> (color * 2)) & 0b11;
+                }
+            }
+        }
+
+        PrintedImage {
+            width: PRINTER_WIDTH,
+            height,
+            pixels,
+        }
+    }
+}
+
+impl<F: FnMut(&PrintedImage)> SerialLink for Printer<F> {
+    fn exchange(&mut self, data: u8) -> u8 {
+        let mut reply = 0x00;
+
+        self.state = match self.state {
+            PacketState::Magic1 => if data == 0x88 {
+                PacketState::Magic2
+            } else {
+                PacketState::Magic1
+            },
+
+            PacketState::Magic2 => if data == 0x33 {
+                PacketState::Command
+            } else {
+                PacketState::Magic1
+            },
+
+            PacketState::Command => {
+                self.command = data;
+                self.checksum = data as u16;
+                self.packet.clear();
+                PacketState::Compression
+            },
+
+            PacketState::Compression => {
+                self.compressed = data & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                PacketState::LengthLo
+            },
+
+            PacketState::LengthLo => {
+                self.length = data as usize;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                PacketState::LengthHi
+            },
+
+            PacketState::LengthHi => {
+                self.length |= (data as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+
+                if self.length == 0 {
+                    PacketState::ChecksumLo
+                } else {
+                    PacketState::Data
+                }
+            },
+
+            PacketState::Data => {
+                self.packet.push(data);
+                self.checksum = self.checksum.wrapping_add(data as u16);
+
+                if self.packet.len() == self.length {
+                    PacketState::ChecksumLo
+                } else {
+                    PacketState::Data
+                }
+            },
+
+            PacketState::ChecksumLo => {
+                self.received_checksum = data as u16;
+                PacketState::ChecksumHi
+            },
+
+            PacketState::ChecksumHi => {
+                self.received_checksum |= (data as u16) << 8;
+                self.process_packet();
+                PacketState::Alive
+            },
+
+            PacketState::Alive => {
+                reply = 0x81;
+                PacketState::Status
+            },
+
+            PacketState::Status => {
+                reply = self.status;
+                PacketState::Magic1
+            },
+        };
+
+        reply
+    }
+}
+
+/// Undoes the printer's run-length compression. Each run starts with a byte: if its top bit is
+/// set, the next byte is repeated `(n & 0x7F) + 2` times, and otherwise the next `n + 1` bytes are
+/// copied as-is.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let n = data[i];
+        i += 1;
+
+        if n & 0x80 != 0 {
+            if let Some(&byte) = data.get(i) {
+                out.resize(out.len() + (n & 0x7F) as usize + 2, byte);
+            }
+            i += 1;
+        } else {
+            let end = (i + n as usize + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+
+    out
+}
@@ -0,0 +1,411 @@
+use std::io::{self, Read, Write};
+
+use super::{
+    console::Console,
+    cpu::{CpuState, OpRead},
+    memory::{MBC, MbcMode, Rtc},
+    registers::Reg8,
+    timer::Timer,
+};
+
+/// Every save state starts with this, then the version of the format it's in
+const MAGIC: &[u8; 4] = b"GBST";
+const VERSION: u8 = 1;
+
+/// Which kind of MBC a state was saved from, so it doesn't get loaded into the wrong cartridge
+const NO_CARTRIDGE: u8 = 0;
+
+fn mbc_tag(mbc: &MBC) -> u8 {
+    match mbc {
+        MBC::RomOnly(_) => 1,
+        MBC::MBC1(_) => 2,
+        MBC::MBC2(_) => 3,
+        MBC::MBC3(_) => 4,
+        MBC::MBC5(_) => 5,
+        MBC::PocketCamera(_) => 6,
+    }
+}
+
+/// Writes the state of the console out as it goes, without putting it all together in memory
+/// first. Everything's little-endian, and every block of memory is preceded by its length.
+///
+/// States are taken between instructions (which is the only place `Console` ever stops), so the
+/// CPU's progress through the current instruction isn't saved.
+pub fn write_state<W: Write>(console: &Console, w: &mut W) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION])?;
+
+    let cpu = &console.cpu;
+    let registers = &cpu.registers;
+    w.write_all(&[
+        registers.a.0, registers.f.0,
+        registers.b.0, registers.c.0,
+        registers.d.0, registers.e.0,
+        registers.h.0, registers.l.0,
+    ])?;
+    w.write_all(&registers.sp.0.to_le_bytes())?;
+    w.write_all(&registers.pc.to_le_bytes())?;
+    w.write_all(&[pack_bools(&[
+        cpu.ime,
+        cpu.disable_interrupts,
+        cpu.enable_interrupts,
+        cpu.stopped,
+        cpu.halted,
+        cpu.halt_bug,
+    ])])?;
+    w.write_all(&cpu.cycles.to_le_bytes())?;
+
+    let bus = &console.bus;
+    for memory in [&bus.chr_ram, &bus.bg_data, &bus.wram, &bus.oam, &bus.hardware, &bus.hi_ram].iter() {
+        write_block(w, memory)?;
+    }
+    w.write_all(&[bus.ie])?;
+
+    let timer = &bus.timer;
+    w.write_all(&[timer.div, timer.tima, timer.tma, timer.tac])?;
+    w.write_all(&(timer.div_cycles as u32).to_le_bytes())?;
+    w.write_all(&(timer.tima_cycles as u32).to_le_bytes())?;
+
+    let ppu = &console.ppu;
+    w.write_all(&[ppu.ly])?;
+    w.write_all(&ppu.line_cycles.to_le_bytes())?;
+    w.write_all(&[ppu.coincidence as u8])?;
+
+    let mbc = match bus.cartridge.as_ref() {
+        Some(cartridge) => &cartridge.mbc,
+        None => return w.write_all(&[NO_CARTRIDGE]),
+    };
+    w.write_all(&[mbc_tag(mbc)])?;
+
+    let (rom_bank, ram_bank, ram_enabled, ram, extra) = match mbc {
+        MBC::RomOnly(_) => return Ok(()),
+        MBC::MBC1(mbc) => (
+            mbc.active_rom_bank, mbc.active_ram_bank, mbc.ram_enabled, &mbc.ram,
+            vec![(mbc.mode == MbcMode::RamSelect) as u8],
+        ),
+        MBC::MBC2(mbc) => (mbc.active_rom_bank, mbc.active_ram_bank, mbc.ram_enabled, &mbc.ram, vec![]),
+        MBC::MBC3(mbc) => (
+            mbc.active_rom_bank, mbc.active_ram_bank, mbc.ram_and_timer_enabled, &mbc.ram,
+            [&pack_rtc(&mbc.rtc)[..], &pack_rtc(&mbc.latched_rtc)[..], &[mbc.latch_armed as u8]].concat(),
+        ),
+        MBC::MBC5(mbc) => (mbc.active_rom_bank, mbc.active_ram_bank, mbc.ram_enabled, &mbc.ram, vec![]),
+        MBC::PocketCamera(mbc) => {
+            let mut extra = vec![mbc.registers_selected as u8];
+            extra.extend_from_slice(&mbc.registers);
+            (mbc.active_rom_bank, mbc.active_ram_bank, mbc.ram_enabled, &mbc.ram, extra)
+        },
+    };
+
+    w.write_all(&(rom_bank as u32).to_le_bytes())?;
+    w.write_all(&(ram_bank as u32).to_le_bytes())?;
+    w.write_all(&[ram_enabled as u8])?;
+    write_block(w, &extra)?;
+    write_block(w, ram)
+}
+
+/// A save state that's been read in and checked over, ready to be put into a console. Nothing's
+/// changed until the whole thing has been read, so a broken state can't leave a console half
+/// loaded.
+pub struct SaveState {
+    registers: [u8; 8],
+    sp: u16,
+    pc: u16,
+    cpu_flags: [bool; 6],
+    cycles: u64,
+    memory: [Vec<u8>; 6],
+    ie: u8,
+    timer: Timer,
+    ly: u8,
+    line_cycles: u64,
+    coincidence: bool,
+    cartridge: Option<CartridgeState>,
+}
+
+struct CartridgeState {
+    tag: u8,
+    rom_bank: usize,
+    ram_bank: usize,
+    ram_enabled: bool,
+    extra: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+pub fn read_state<R: Read>(r: &mut R) -> Result<SaveState, String> {
+    read_state_inner(r).map_err(|e| format!("Could not read save state: {}", e))
+}
+
+fn read_state_inner<R: Read>(r: &mut R) -> io::Result<SaveState> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a save state"));
+    }
+
+    let version = read_u8(r)?;
+    if version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported version {}", version),
+        ));
+    }
+
+    let mut registers = [0; 8];
+    r.read_exact(&mut registers)?;
+    let sp = read_u16(r)?;
+    let pc = read_u16(r)?;
+    let cpu_flags = unpack_bools(read_u8(r)?);
+    let cycles = read_u64(r)?;
+
+    let memory = [
+        read_block(r)?, read_block(r)?, read_block(r)?,
+        read_block(r)?, read_block(r)?, read_block(r)?,
+    ];
+    let ie = read_u8(r)?;
+
+    let mut timer_registers = [0; 4];
+    r.read_exact(&mut timer_registers)?;
+    let [div, tima, tma, tac] = timer_registers;
+    let timer = Timer {
+        div,
+        tima,
+        tma,
+        tac,
+        div_cycles: read_u32(r)? as usize,
+        tima_cycles: read_u32(r)? as usize,
+    };
+
+    let ly = read_u8(r)?;
+    let line_cycles = read_u64(r)?;
+    let coincidence = read_u8(r)? != 0;
+
+    let cartridge = match read_u8(r)? {
+        NO_CARTRIDGE => None,
+        // Cartridges without an MBC have nothing else to save
+        tag @ 1 => Some(CartridgeState {
+            tag,
+            rom_bank: 0,
+            ram_bank: 0,
+            ram_enabled: false,
+            extra: vec![],
+            ram: vec![],
+        }),
+        tag => Some(CartridgeState {
+            tag,
+            rom_bank: read_u32(r)? as usize,
+            ram_bank: read_u32(r)? as usize,
+            ram_enabled: read_u8(r)? != 0,
+            extra: read_block(r)?,
+            ram: read_block(r)?,
+        }),
+    };
+
+    Ok(SaveState {
+        registers,
+        sp,
+        pc,
+        cpu_flags,
+        cycles,
+        memory,
+        ie,
+        timer,
+        ly,
+        line_cycles,
+        coincidence,
+        cartridge,
+    })
+}
+
+impl SaveState {
+    /// Puts the state into `console`. It has to be running the same kind of cartridge, with the
+    /// same amount of RAM, as the console the state was saved from.
+    pub fn restore(self, console: &mut Console) -> Result<(), String> {
+        let bus = &console.bus;
+        let sizes = [&bus.chr_ram, &bus.bg_data, &bus.wram, &bus.oam, &bus.hardware, &bus.hi_ram]
+            .iter()
+            .map(|memory| memory.len())
+            .collect::<Vec<_>>();
+        if self.memory.iter().map(|memory| memory.len()).ne(sizes) {
+            return Err("Save state has the wrong amount of memory for this console".to_string());
+        }
+
+        match (self.cartridge.as_ref(), bus.cartridge.as_ref()) {
+            (None, None) => {},
+            (Some(state), Some(cartridge)) => {
+                if state.tag != mbc_tag(&cartridge.mbc) {
+                    return Err("Save state is for a different kind of cartridge".to_string());
+                }
+
+                let expected_extra = match &cartridge.mbc {
+                    MBC::MBC1(_) => 1,
+                    MBC::MBC3(_) => 2 * RTC_SIZE + 1,
+                    MBC::PocketCamera(mbc) => 1 + mbc.registers.len(),
+                    _ => 0,
+                };
+                let ram_size = cartridge.mbc.ram().map_or(0, |ram| ram.len());
+                if state.extra.len() != expected_extra || state.ram.len() != ram_size {
+                    return Err("Save state doesn't match the cartridge's RAM".to_string());
+                }
+            },
+            _ => return Err("Save state is for a different cartridge".to_string()),
+        }
+
+        let cpu = &mut console.cpu;
+        let [a, f, b, c, d, e, h, l] = self.registers;
+        cpu.registers.a = Reg8(a);
+        cpu.registers.f = Reg8(f & 0xF0);
+        cpu.registers.b = Reg8(b);
+        cpu.registers.c = Reg8(c);
+        cpu.registers.d = Reg8(d);
+        cpu.registers.e = Reg8(e);
+        cpu.registers.h = Reg8(h);
+        cpu.registers.l = Reg8(l);
+        cpu.registers.sp.load(self.sp);
+        cpu.registers.pc = self.pc;
+
+        let [ime, disable_interrupts, enable_interrupts, stopped, halted, halt_bug] = self.cpu_flags;
+        cpu.ime = ime;
+        cpu.disable_interrupts = disable_interrupts;
+        cpu.enable_interrupts = enable_interrupts;
+        cpu.stopped = stopped;
+        cpu.halted = halted;
+        cpu.halt_bug = halt_bug;
+        cpu.cycles = self.cycles;
+        cpu.state = CpuState::OpRead(OpRead::General);
+
+        let bus = &mut console.bus;
+        let [chr_ram, bg_data, wram, oam, hardware, hi_ram] = self.memory;
+        bus.chr_ram = chr_ram;
+        bus.bg_data = bg_data;
+        bus.wram = wram;
+        bus.oam = oam;
+        bus.hardware = hardware;
+        bus.hi_ram = hi_ram;
+        bus.ie = self.ie;
+        bus.timer = self.timer;
+
+        console.ppu.ly = self.ly;
+        console.ppu.line_cycles = self.line_cycles;
+        console.ppu.coincidence = self.coincidence;
+
+        if let (Some(state), Some(cartridge)) = (self.cartridge, bus.cartridge.as_mut()) {
+            let CartridgeState { rom_bank, ram_bank, ram_enabled, extra, ram, .. } = state;
+
+            match &mut cartridge.mbc {
+                MBC::RomOnly(_) => {},
+                MBC::MBC1(mbc) => {
+                    mbc.active_rom_bank = rom_bank;
+                    mbc.active_ram_bank = ram_bank;
+                    mbc.ram_enabled = ram_enabled;
+                    mbc.mode = if extra[0] != 0 { MbcMode::RamSelect } else { MbcMode::RomSelect };
+                    mbc.ram.copy_from_slice(&ram);
+                },
+                MBC::MBC2(mbc) => {
+                    mbc.active_rom_bank = rom_bank;
+                    mbc.active_ram_bank = ram_bank;
+                    mbc.ram_enabled = ram_enabled;
+                    mbc.ram.copy_from_slice(&ram);
+                },
+                MBC::MBC3(mbc) => {
+                    mbc.active_rom_bank = rom_bank;
+                    mbc.active_ram_bank = ram_bank;
+                    mbc.ram_and_timer_enabled = ram_enabled;
+                    mbc.rtc = unpack_rtc(&extra[..RTC_SIZE]);
+                    mbc.latched_rtc = unpack_rtc(&extra[RTC_SIZE..2 * RTC_SIZE]);
+                    mbc.latch_armed = extra[2 * RTC_SIZE] != 0;
+                    mbc.ram.copy_from_slice(&ram);
+                },
+                MBC::MBC5(mbc) => {
+                    mbc.active_rom_bank = rom_bank;
+                    mbc.active_ram_bank = ram_bank;
+                    mbc.ram_enabled = ram_enabled;
+                    mbc.ram.copy_from_slice(&ram);
+                },
+                MBC::PocketCamera(mbc) => {
+                    mbc.active_rom_bank = rom_bank;
+                    mbc.active_ram_bank = ram_bank;
+                    mbc.ram_enabled = ram_enabled;
+                    mbc.registers_selected = extra[0] != 0;
+                    mbc.registers.copy_from_slice(&extra[1..]);
+                    mbc.ram.copy_from_slice(&ram);
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const RTC_SIZE: usize = 6;
+
+fn pack_rtc(rtc: &Rtc) -> [u8; RTC_SIZE] {
+    let days = rtc.days.to_le_bytes();
+    [rtc.seconds, rtc.minutes, rtc.hours, days[0], days[1], pack_bools(&[rtc.halted, rtc.day_carry])]
+}
+
+fn unpack_rtc(bytes: &[u8]) -> Rtc {
+    let [halted, day_carry] = unpack_bools(bytes[5]);
+
+    Rtc {
+        seconds: bytes[0],
+        minutes: bytes[1],
+        hours: bytes[2],
+        days: u16::from_le_bytes([bytes[3], bytes[4]]),
+        halted,
+        day_carry,
+    }
+}
+
+/// Packs up to 8 flags into a byte, the first one in bit 0
+fn pack_bools(bools: &[bool]) -> u8 {
+    bools.iter().enumerate().fold(0, |byte, (bit, &b)| byte | ((b as u8) << bit))
+}
+
+fn unpack_bools<const N: usize>(byte: u8) -> [bool; N] {
+    let mut bools = [false; N];
+    for (bit, b) in bools.iter_mut().enumerate() {
+        *b = byte & (1 << bit) != 0;
+    }
+    bools
+}
+
+fn write_block<W: Write>(w: &mut W, block: &[u8]) -> io::Result<()> {
+    w.write_all(&(block.len() as u32).to_le_bytes())?;
+    w.write_all(block)
+}
+
+fn read_block<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u32(r)? as usize;
+
+    // Read it bit by bit rather than trusting the length enough to allocate it all up front
+    let mut block = Vec::new();
+    r.take(len as u64).read_to_end(&mut block)?;
+    if block.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(block)
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    r.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
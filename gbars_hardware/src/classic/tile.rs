@@ -0,0 +1,38 @@
+/// Each tile is 8x8 pixels at 2 bits per pixel, so 16 bytes
+pub const TILE_SIZE: usize = 16;
+
+/// Tiles are stored as 8 rows of 2 bytes each. Rather than packing each pixel's 2 bits together,
+/// the bits are split into two "bit planes": the first byte of a row holds the low bit of every
+/// pixel and the second byte holds the high bit, with the leftmost pixel in bit 7. So to get the
+/// color index of a pixel you have to pick a bit out of each byte and glue them back together.
+pub fn decode_tile(data: &[u8; TILE_SIZE]) -> [[u8; 8]; 8] {
+    let mut grid = [[0; 8]; 8];
+
+    for (row, pixels) in grid.iter_mut().enumerate() {
+        let lo = data[row * 2];
+        let hi = data[row * 2 + 1];
+
+        for (col, pixel) in pixels.iter_mut().enumerate() {
+            let bit = 7 - col;
+            *pixel = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+        }
+    }
+
+    grid
+}
+
+/// The reverse of `decode_tile`: splits an 8x8 grid of color indices (0-3) back into bit planes.
+/// Only the low 2 bits of each index are used.
+pub fn encode_tile(grid: &[[u8; 8]; 8]) -> [u8; TILE_SIZE] {
+    let mut data = [0; TILE_SIZE];
+
+    for (row, pixels) in grid.iter().enumerate() {
+        for (col, &pixel) in pixels.iter().enumerate() {
+            let bit = 7 - col;
+            data[row * 2] |= (pixel & 1) << bit;
+            data[row * 2 + 1] |= ((pixel >> 1) & 1) << bit;
+        }
+    }
+
+    data
+}
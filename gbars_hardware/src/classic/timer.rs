@@ -0,0 +1,116 @@
+/// The timer's registers
+pub const DIV: usize = 0xFF04;
+pub const TIMA: usize = 0xFF05;
+pub const TMA: usize = 0xFF06;
+pub const TAC: usize = 0xFF07;
+
+/// How many clock cycles go by for each tick of DIV (it counts at 16384 Hz)
+pub const DIV_PERIOD: usize = 256;
+
+/// The timer, which games mostly use to keep music playing at a steady tempo. DIV counts up all
+/// the time, while TIMA counts at whichever rate TAC picks (as long as TAC bit 2 turns it on).
+/// When TIMA overflows it starts again from TMA and requests the timer interrupt.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Timer {
+    pub div: u8,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: u8,
+    // Clock cycles counted towards the next tick of DIV and of TIMA
+    pub(crate) div_cycles: usize,
+    pub(crate) tima_cycles: usize,
+}
+
+impl Timer {
+    pub fn init() -> Self {
+        Self::default()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.tac & 0x04 != 0
+    }
+
+    /// How many clock cycles go by for each tick of TIMA: 4096, 262144, 65536, or 16384 Hz
+    pub fn tima_period(&self) -> usize {
+        match self.tac & 0x03 {
+            0b00 => 1024,
+            0b01 => 16,
+            0b10 => 64,
+            _ => 256,
+        }
+    }
+
+    /// Moves the timer along by some number of clock cycles. Returns true if TIMA overflowed,
+    /// meaning the timer interrupt should be requested.
+    pub fn step(&mut self, cycles: usize) -> bool {
+        self.div_cycles += cycles;
+        while self.div_cycles >= DIV_PERIOD {
+            self.div_cycles -= DIV_PERIOD;
+            self.div = self.div.wrapping_add(1);
+        }
+
+        if !self.enabled() {
+            return false;
+        }
+
+        let mut overflowed = false;
+        let period = self.tima_period();
+
+        self.tima_cycles += cycles;
+        while self.tima_cycles >= period {
+            self.tima_cycles -= period;
+
+            let (tima, overflow) = self.tima.overflowing_add(1);
+            if overflow {
+                self.tima = self.tma;
+                overflowed = true;
+            } else {
+                self.tima = tima;
+            }
+        }
+
+        overflowed
+    }
+
+    pub fn read(&self, offset: usize) -> Option<u8> {
+        match offset {
+            DIV => Some(self.div),
+            TIMA => Some(self.tima),
+            TMA => Some(self.tma),
+            // Only the bottom 3 bits of TAC are used
+            TAC => Some(0xF8 | self.tac),
+            _ => None,
+        }
+    }
+
+    /// Writes to a register the way the CPU does. Writing anything at all to DIV resets it.
+    pub fn write(&mut self, offset: usize, data: u8) -> Option<()> {
+        match offset {
+            DIV => {
+                self.div = 0;
+                self.div_cycles = 0;
+            },
+            TIMA => self.tima = data,
+            TMA => self.tma = data,
+            TAC => {
+                // Changing the rate starts the count towards the next tick over
+                if data & 0x03 != self.tac & 0x03 {
+                    self.tima_cycles = 0;
+                }
+                self.tac = data & 0x07;
+            },
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    /// Sets a register straight to `data`, for setting the timer up rather than writing to it
+    /// from a program (so DIV can be set to something other than 0)
+    pub fn set(&mut self, offset: usize, data: u8) {
+        match offset {
+            DIV => self.div = data,
+            _ => { self.write(offset, data); },
+        }
+    }
+}
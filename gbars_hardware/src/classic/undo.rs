@@ -0,0 +1,160 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::{
+    vec::Vec,
+    string::String,
+    collections::VecDeque,
+};
+
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
+
+use super::{
+    console::CHR_RAM_START,
+    cpu::Cpu,
+    memory::MemoryBus,
+    registers::Registers,
+};
+
+/// How many instructions `Console` remembers by default. Most instructions only touch the
+/// registers and maybe a byte or two of memory, so this doesn't cost much.
+pub const DEFAULT_UNDO_DEPTH: usize = 1024;
+
+/// Everything needed to put things back the way they were before an instruction ran. Rather than
+/// copying all of memory, I only keep the bytes the instruction actually wrote to.
+struct UndoEntry<S> {
+    registers: Registers,
+    ime: bool,
+    halted: bool,
+    halt_bug: bool,
+    stopped: bool,
+    disable_interrupts: bool,
+    enable_interrupts: bool,
+    // (address, old value), in the order the writes happened
+    writes: Vec<(usize, u8)>,
+    // Whatever the rest of the hardware looked like beforehand, since it moves along by itself
+    // while the instruction runs (see `record`)
+    hardware: S,
+}
+
+/// A bounded stack of undo entries, one per instruction. Once it's full, the oldest entry is
+/// thrown away to make room.
+pub struct UndoHistory<S> {
+    entries: VecDeque<UndoEntry<S>>,
+    depth: usize,
+}
+
+impl<S> UndoHistory<S> {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            depth,
+        }
+    }
+
+    pub fn depth(&self) -> usize { self.depth }
+
+    /// Changes how many instructions are remembered, forgetting the oldest ones if there are now
+    /// too many. A depth of 0 turns recording off altogether.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+
+        while self.entries.len() > depth {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Runs `f` (which should execute exactly one instruction), recording what it changes. Writes
+    /// to the cartridge's ROM area don't change memory, they switch banks, and there's no way to
+    /// read back what the bank registers were before. So if an instruction does that, everything
+    /// before it can no longer be undone and the history is cleared.
+    ///
+    /// Only writes the CPU makes go through the recording bus. The timer, the PPU, and so on
+    /// change things without it, so whoever's running them hands over `hardware`, a copy of
+    /// whatever state of theirs needs putting back. `undo` gives it back to them.
+    pub fn record<M, F>(&mut self, cpu: &mut Cpu, memory: &mut M, hardware: S, f: F) -> Result<(), String>
+        where M: MemoryBus, F: FnOnce(&mut Cpu, &mut RecordingBus<M>) -> Result<(), String>
+    {
+        if self.depth == 0 {
+            let mut bus = RecordingBus { memory, writes: None };
+            return f(cpu, &mut bus);
+        }
+
+        let mut entry = UndoEntry {
+            registers: cpu.registers,
+            ime: cpu.ime,
+            halted: cpu.halted,
+            halt_bug: cpu.halt_bug,
+            stopped: cpu.stopped,
+            disable_interrupts: cpu.disable_interrupts,
+            enable_interrupts: cpu.enable_interrupts,
+            writes: Vec::new(),
+            hardware,
+        };
+
+        let mut bus = RecordingBus { memory, writes: Some(&mut entry.writes) };
+        let result = f(cpu, &mut bus);
+
+        if entry.writes.iter().any(|&(addr, _)| addr < CHR_RAM_START) {
+            self.entries.clear();
+            return result;
+        }
+
+        if self.entries.len() == self.depth {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+
+        result
+    }
+
+    /// Reverts the most recently recorded instruction, handing back the hardware state it was
+    /// recorded with for the caller to restore. Returns None if there was nothing to undo.
+    pub fn undo<M: MemoryBus>(&mut self, cpu: &mut Cpu, memory: &mut M) -> Option<S> {
+        let entry = self.entries.pop_back()?;
+
+        // Undo the writes backwards in case the instruction wrote to the same place twice
+        for &(addr, data) in entry.writes.iter().rev() {
+            memory.write(addr, data);
+        }
+
+        cpu.registers = entry.registers;
+        cpu.ime = entry.ime;
+        cpu.halted = entry.halted;
+        cpu.halt_bug = entry.halt_bug;
+        cpu.stopped = entry.stopped;
+        cpu.disable_interrupts = entry.disable_interrupts;
+        cpu.enable_interrupts = entry.enable_interrupts;
+
+        Some(entry.hardware)
+    }
+}
+
+/// Sits between the CPU and memory and makes a note of what every write overwrites
+pub struct RecordingBus<'a, M: MemoryBus> {
+    memory: &'a mut M,
+    writes: Option<&'a mut Vec<(usize, u8)>>,
+}
+
+impl<'a, M: MemoryBus> MemoryBus for RecordingBus<'a, M> {
+    fn read(&self, offset: usize) -> Option<u8> {
+        self.memory.read(offset)
+    }
+
+    fn write(&mut self, offset: usize, data: u8) -> Option<()> {
+        if let Some(writes) = self.writes.as_mut() {
+            if let Some(old) = self.memory.read(offset) {
+                writes.push((offset, old));
+            }
+        }
+
+        self.memory.write(offset, data)
+    }
+}
@@ -0,0 +1,67 @@
+//! Runs Blargg's test ROMs headless. They report their results over the link cable as text, so
+//! whatever's written out through the serial port is collected up and checked for "Passed" or
+//! "Failed".
+//!
+//! The ROMs aren't checked in. To run these, put them in src/test_roms/ and run
+//!
+//!     cargo test --test blargg -- --ignored
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use hardware::classic::{
+    cartridge::Cartridge,
+    console::{Console, Model},
+};
+
+/// How a test ROM finished, along with everything it printed
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Outcome {
+    Passed(String),
+    Failed(String),
+    TimedOut(String),
+}
+
+/// Runs `console` until the ROM prints "Passed" or "Failed", giving up after `max_steps`
+/// instructions
+fn run_until_serial_done(console: &mut Console, max_steps: usize) -> Result<Outcome, String> {
+    let output = Rc::new(RefCell::new(String::new()));
+    {
+        let output = output.clone();
+        console.on_serial_out(move |byte| output.borrow_mut().push(byte as char));
+    }
+    console.set_undo_depth(0);
+
+    for _ in 0..max_steps {
+        console.step_instruction()?;
+
+        let output = output.borrow();
+        if output.contains("Passed") {
+            return Ok(Outcome::Passed(output.clone()));
+        }
+
+        if output.contains("Failed") {
+            return Ok(Outcome::Failed(output.clone()));
+        }
+    }
+
+    let output = output.borrow().clone();
+    Ok(Outcome::TimedOut(output))
+}
+
+fn run_rom(path: &str, max_steps: usize) {
+    let cartridge = Cartridge::load(path).unwrap();
+    let mut console = Console::new_with_model(Model::Dmg, cartridge);
+
+    match run_until_serial_done(&mut console, max_steps).unwrap() {
+        Outcome::Passed(_) => {},
+        Outcome::Failed(output) => panic!("{} failed:\n{}", path, output),
+        Outcome::TimedOut(output) => panic!("{} didn't finish in {} instructions:\n{}", path, max_steps, output),
+    }
+}
+
+#[test]
+#[ignore]
+fn cpu_instrs() {
+    run_rom("src/test_roms/cpu_instrs.gb", 300_000_000);
+}
@@ -8,6 +8,7 @@ use std::path::Path;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter, SeekFrom};
 use std::ffi::OsStr;
+use std::fmt;
 
 /// Reads an IPS file and extracts all the patches from it
 ///
@@ -202,3 +203,155 @@ pub fn restore(rom_file: &str, bak_file: &str, retain_backup: bool) -> Result<()
 
     Ok(())
 }
+
+/// Things that can be wrong with an IPS patch
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum IpsError {
+    /// The patch doesn't start with "PATCH"
+    BadHeader,
+    /// The patch ends partway through a record, or without an "EOF". Holds the offset into the
+    /// patch where it ran out.
+    Truncated(usize),
+    /// A run-length record says to repeat a byte 0 times, at this offset into the patch
+    EmptyRun(usize),
+}
+
+impl fmt::Display for IpsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpsError::BadHeader => write!(f, "Invalid IPS header"),
+            IpsError::Truncated(at) => write!(f, "IPS patch ends early (at byte 0x{:06X})", at),
+            IpsError::EmptyRun(at) => write!(f, "IPS record at byte 0x{:06X} has an empty run", at),
+        }
+    }
+}
+
+impl std::error::Error for IpsError {}
+
+/// Applies an IPS patch (laid out as described for `read`) to a ROM in memory. Records are applied
+/// in order, so later ones win where they overlap, and the ROM is padded with zeroes if a record
+/// goes past the end of it. If the "EOF" is followed by 3 more bytes, the ROM is truncated to that
+/// length.
+///
+/// The patch is checked over completely before anything is written, so the ROM is left alone if
+/// it's malformed.
+pub fn apply(rom: &mut Vec<u8>, patch: &[u8]) -> Result<(), IpsError> {
+    if !patch.starts_with(b"PATCH") {
+        return Err(IpsError::BadHeader);
+    }
+
+    let take = |at: usize, len: usize| patch.get(at..at + len).ok_or(IpsError::Truncated(at));
+    let number = |bytes: &[u8]| bytes.iter().fold(0, |n, &b| (n << 8) | b as usize);
+
+    let mut records = Vec::new();
+    let mut at = 5;
+    let truncate_to = loop {
+        let offset = take(at, 3)?;
+        if offset == b"EOF" {
+            break match patch.get(at + 3..) {
+                Some(size) if size.len() >= 3 => Some(number(&size[..3])),
+                _ => None,
+            };
+        }
+
+        let offset = number(offset);
+        let record_start = at;
+        at += 3;
+
+        let length = number(take(at, 2)?);
+        at += 2;
+
+        if length == 0 {
+            let run = number(take(at, 2)?);
+            let byte = take(at + 2, 1)?[0];
+            at += 3;
+
+            if run == 0 {
+                return Err(IpsError::EmptyRun(record_start));
+            }
+
+            records.push((offset, Record::Run(run, byte)));
+        } else {
+            records.push((offset, Record::Data(take(at, length)?)));
+            at += length;
+        }
+    };
+
+    for (offset, record) in records {
+        let end = offset + record.len();
+        if rom.len() < end {
+            rom.resize(end, 0);
+        }
+
+        match record {
+            Record::Data(data) => rom[offset..end].copy_from_slice(data),
+            Record::Run(_, byte) => rom[offset..end].iter_mut().for_each(|b| *b = byte),
+        }
+    }
+
+    if let Some(size) = truncate_to {
+        rom.truncate(size);
+    }
+
+    Ok(())
+}
+
+enum Record<'a> {
+    Data(&'a [u8]),
+    Run(usize, u8),
+}
+
+impl Record<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Record::Data(data) => data.len(),
+            Record::Run(run, _) => *run,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn records_overwrite_the_rom() {
+        let mut rom = vec![0; 16];
+        let patch = [
+            &b"PATCH"[..],
+            &[0x00, 0x00, 0x02, 0x00, 0x03, 0xAA, 0xBB, 0xCC],
+            &[0x00, 0x00, 0x0E, 0x00, 0x04, 0x11, 0x22, 0x33, 0x44],
+            b"EOF",
+        ].concat();
+
+        apply(&mut rom, &patch).unwrap();
+        assert_eq!(&rom[..6], &[0x00, 0x00, 0xAA, 0xBB, 0xCC, 0x00]);
+        // The second record runs off the end of the ROM, so it grows to fit
+        assert_eq!(&rom[12..], &[0x00, 0x00, 0x11, 0x22, 0x33, 0x44]);
+    }
+
+    #[test]
+    fn rle_records_repeat_a_byte() {
+        let mut rom = vec![0; 8];
+        let patch = [
+            &b"PATCH"[..],
+            &[0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x05, 0xFF],
+            b"EOF",
+            &[0x00, 0x00, 0x07],
+        ].concat();
+
+        apply(&mut rom, &patch).unwrap();
+        assert_eq!(rom, vec![0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]);
+    }
+
+    #[test]
+    fn malformed_patches_leave_the_rom_alone() {
+        let mut rom = vec![0; 8];
+
+        assert_eq!(apply(&mut rom, b"PTACH"), Err(IpsError::BadHeader));
+        assert_eq!(apply(&mut rom, b"PATCH\x00\x00\x01\x00\x02\xAA"), Err(IpsError::Truncated(10)));
+        assert_eq!(apply(&mut rom, b"PATCH\x00\x00\x01\x00\x01\xAA"), Err(IpsError::Truncated(11)));
+        assert_eq!(apply(&mut rom, b"PATCH\x00\x00\x01\x00\x00\x00\x00\x01EOF"), Err(IpsError::EmptyRun(5)));
+        assert_eq!(rom, vec![0; 8]);
+    }
+}
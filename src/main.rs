@@ -13,7 +13,6 @@ pub mod graphics;
 //use interface::cli::cli_main;
 //use interface::gui::gui_main;
 
-use std::thread;
 use std::path::Path;
 use std::env;
 use glutin::{
@@ -31,17 +30,12 @@ use glutin::{
     }
 };
 use std::fs::File;
+use classic::console::Console;
 use classic::cpu::{Cpu, CpuState};
 use classic::memory::{MBC, ROM};
 use std::ops::Range;
 
-const STACK_SIZE: usize = 0x4000000;
-
-fn run() {
-//    let mut console = classic::gb_types::Console::init(
-//        Some("src/test_roms/pokeblue.gbc")
-//    );
-//
+fn run(_console: Console) {
 //    let initial_height = 144.0;
 //    let initial_width = 160.0;
 //
@@ -165,11 +159,38 @@ fn run() {
 }
 
 fn main() {
-    //    let child = thread::Builder::new()
-//        .stack_size(STACK_SIZE)
-//        .name(String::from("gbars"))
-//        .spawn(run)
-//        .unwrap();
-//
-//    child.join().unwrap();
+    let matches = clap::App::new("gbars")
+        .version(crate_version!())
+        .about(crate_description!())
+        .arg(clap::Arg::with_name("ROM")
+            .help("The ROM to play")
+            .required(true))
+        .get_matches();
+
+    // A ROM that can't be loaded is the user's problem, not a bug, so it shouldn't panic. That
+    // goes for ROMs with a bad header or an MBC that isn't emulated, too.
+    let path = matches.value_of("ROM").unwrap();
+    let console = std::fs::read(path)
+        .map_err(|e| format!("Could not open file {}: {}", path, e))
+        .and_then(|rom| Console::from_rom_bytes(rom).map_err(|e| format!("Error loading {}: {}", path, e)));
+
+    let console = match console {
+        Ok(console) => console,
+        Err(e) => {
+            eprintln!("gbars: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // There's no window to play it in yet, so the most useful thing to do is say what it is
+    if let Some(cartridge) = console.bus.cartridge.as_ref() {
+        println!("Title:       {}", cartridge.title);
+        println!("Features:    {:?}", cartridge.features);
+        println!("ROM:         {} KiB ({} banks)", cartridge.rom_size / 1024, cartridge.rom_banks);
+        println!("RAM:         {} KiB ({} banks)", cartridge.ram_size / 1024, cartridge.ram_banks);
+        println!("Destination: {}", cartridge.destination);
+        println!("Checksums:   header 0x{:02X}, global 0x{:04X}", cartridge.header_checksum, cartridge.global_checksum);
+    }
+
+    run(console);
 }
@@ -0,0 +1,48 @@
+use std::process::Command;
+
+use hardware::classic::cartridge::{Cartridge, NINTENDO_GRAPHIC};
+
+#[test]
+fn missing_rom_exits_cleanly() {
+    let output = Command::new(env!("CARGO_BIN_EXE_gbars"))
+        .arg("this/rom/does/not/exist.gb")
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.contains("this/rom/does/not/exist.gb"), "unexpected error: {}", stderr);
+    assert!(!stderr.contains("panicked"), "panicked: {}", stderr);
+}
+
+#[test]
+fn bad_roms_exit_cleanly() {
+    // No Nintendo logo, so the header doesn't check out
+    let path = std::env::temp_dir().join("gbars_cli_bad_header.gb");
+    std::fs::write(&path, vec![0u8; 0x8000]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gbars")).arg(&path).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.contains("gbars_cli_bad_header.gb"), "unexpected error: {}", stderr);
+    assert!(!stderr.contains("panicked"), "panicked: {}", stderr);
+}
+
+#[test]
+fn good_roms_print_their_header() {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x104..0x134].copy_from_slice(&NINTENDO_GRAPHIC);
+    rom[0x134..0x138].copy_from_slice(b"GBAR");
+    Cartridge::fix_checksums(&mut rom);
+
+    let path = std::env::temp_dir().join("gbars_cli_good_header.gb");
+    std::fs::write(&path, rom).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gbars")).arg(&path).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success());
+    assert!(stdout.contains("GBAR"), "unexpected output: {}", stdout);
+}